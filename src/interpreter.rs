@@ -1,8 +1,239 @@
+mod ast;
+mod code;
+mod diagnostic;
+mod evaluator;
+mod list;
+mod map;
+mod native;
+mod parser;
+mod resolver;
+mod symbol;
+#[cfg(test)]
+mod test_support;
 mod token;
+mod vm;
 
+use std::io::Write;
 use std::ops::RangeInclusive;
 
-use token::{Token, Type};
+pub use code::Code;
+pub use diagnostic::{Diagnostic, Severity};
+pub use native::{Natives, Object, Value};
+pub use vm::LoadError;
+
+use ast::Stmt;
+use evaluator::Evaluator;
+use parser::Parser;
+use resolver::Resolver;
+use symbol::Symbol;
+use token::{Span, Token, Type};
+
+/// Runs Lox code, keeping global state between runs.
+pub struct Lox {
+    runner: Runner,
+}
+
+/// How `Lox` runs code. Both print the same and fail with the same errors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// Evaluates the syntax tree directly.
+    #[default]
+    TreeWalker,
+    /// Compiles to bytecode and runs it on a stack-based virtual machine.
+    Vm,
+}
+
+/// How much the VM optimizes the bytecode it compiles, which runs the same
+/// either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OptLevel {
+    /// Compiles code as written, for `-O0`.
+    O0,
+    /// Folds constants, removes dead code and fuses instructions, for `-O1`.
+    #[default]
+    O1,
+}
+
+/// A script compiled for the VM, which can be saved to run it later without
+/// compiling it again.
+pub struct Bytecode {
+    program: vm::Program,
+}
+
+enum Runner {
+    TreeWalker(Evaluator),
+    Vm(Box<vm::Vm>),
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// Errors in the code itself, found before running it.
+    Compile(Vec<Diagnostic>),
+    Runtime(Diagnostic),
+}
+
+impl Error {
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        match self {
+            Error::Compile(diagnostics) => diagnostics,
+            Error::Runtime(diagnostic) => std::slice::from_ref(diagnostic),
+        }
+    }
+}
+
+impl Lox {
+    /// `print` statements write to the standard output.
+    pub fn new() -> Lox {
+        Lox::with_output(Box::new(std::io::stdout()))
+    }
+
+    /// `print` statements write to `out`.
+    pub fn with_output(out: Box<dyn Write>) -> Lox {
+        Lox::with_natives(out, &Natives::new())
+    }
+
+    /// Defines `natives` as globals, besides writing `print`s to `out`.
+    pub fn with_natives(out: Box<dyn Write>, natives: &Natives) -> Lox {
+        Lox::with_backend(out, natives, Backend::default())
+    }
+
+    pub fn with_backend(out: Box<dyn Write>, natives: &Natives, backend: Backend) -> Lox {
+        let runner = match backend {
+            Backend::TreeWalker => Runner::TreeWalker(Evaluator::new(out, natives)),
+            Backend::Vm => Runner::Vm(Box::new(vm::Vm::new(out, natives))),
+        };
+        Lox { runner }
+    }
+
+    /// Makes the VM collect garbage before every allocation, so objects it
+    /// fails to keep alive are found sooner. Does nothing on the tree-walker.
+    pub fn set_gc_stress(&mut self, stress: bool) {
+        if let Runner::Vm(vm) = &mut self.runner {
+            vm.set_gc_stress(stress);
+        }
+    }
+
+    /// Does nothing on the tree-walker.
+    pub fn set_opt_level(&mut self, level: OptLevel) {
+        if let Runner::Vm(vm) = &mut self.runner {
+            vm.set_opt_level(level);
+        }
+    }
+
+    pub fn run(&mut self, source: &str) -> Result<(), Error> {
+        self.run_from(source, 0)
+    }
+
+    /// Runs the code of `source` from byte `start` on, where the code that
+    /// earlier runs were given ends, as in a REPL. Errors point into the
+    /// whole of `source`, so they can be rendered against it even when they
+    /// happen in functions defined by earlier runs.
+    pub fn run_from(&mut self, source: &str, start: usize) -> Result<(), Error> {
+        match &mut self.runner {
+            Runner::TreeWalker(evaluator) => {
+                let statements = compile(source, start).map_err(Error::Compile)?;
+                evaluator.interpret(&statements).map_err(|error| Error::Runtime(error.into()))
+            }
+            Runner::Vm(vm) => {
+                let script = vm.compile(source, start).map_err(Error::Compile)?;
+                vm.interpret(script).map_err(|error| Error::Runtime(error.into()))
+            }
+        }
+    }
+
+    /// Runs compiled code. The tree-walker runs the source it was compiled
+    /// from instead.
+    pub fn run_bytecode(&mut self, bytecode: &Bytecode) -> Result<(), Error> {
+        match &mut self.runner {
+            Runner::TreeWalker(_) => self.run(bytecode.source()),
+            Runner::Vm(vm) => {
+                let script = vm.load(&bytecode.program);
+                vm.interpret(script).map_err(|error| Error::Runtime(error.into()))
+            }
+        }
+    }
+}
+
+impl Bytecode {
+    pub fn compile(source: &str, level: OptLevel) -> Result<Bytecode, Error> {
+        let mut heap = vm::Heap::default();
+        let script = vm::compile(source, 0, &mut heap, vec![], level).map_err(Error::Compile)?;
+        Ok(Bytecode { program: vm::Program::new(&heap, script, source) })
+    }
+
+    /// Reads bytes written by `to_bytes`, possibly by another build of Lox.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Bytecode, LoadError> {
+        vm::Program::from_bytes(bytes).map(|program| Bytecode { program })
+    }
+
+    /// Versioned and checksummed, to be saved in `.loxc` files.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.program.to_bytes()
+    }
+
+    /// Lists the loaded bytecode, like `disassemble` lists that of source.
+    pub fn disassemble(&self) -> String {
+        let mut heap = vm::Heap::default();
+        let script = self.program.load(&mut heap);
+        vm::disassemble(&heap, script)
+    }
+
+    /// The code that was compiled, which errors refer to.
+    pub fn source(&self) -> &str {
+        &self.program.source
+    }
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Lox::new()
+    }
+}
+
+/// Lists the bytecode that `source` compiles to.
+pub fn disassemble(source: &str, level: OptLevel) -> Result<String, Error> {
+    let mut heap = vm::Heap::default();
+    let script = vm::compile(source, 0, &mut heap, vec![], level).map_err(Error::Compile)?;
+    Ok(vm::disassemble(&heap, script))
+}
+
+/// Compiles the code of `source` from byte `start` on.
+fn compile(source: &str, start: usize) -> Result<Vec<Stmt>, Vec<Diagnostic>> {
+    let (tokens, mut diagnostics) = scan(source, start);
+    let statements = match Parser::new(tokens, source.len()).parse() {
+        Ok(statements) => statements,
+        Err(errors) => {
+            diagnostics.extend(errors.into_iter().map(Diagnostic::from));
+            return Err(diagnostics);
+        }
+    };
+    if let Err(errors) = Resolver::new().resolve(&statements) {
+        diagnostics.extend(errors.into_iter().map(Diagnostic::from));
+    }
+
+    if diagnostics.is_empty() {
+        Ok(statements)
+    } else {
+        Err(diagnostics)
+    }
+}
+
+/// Separates the errors of the scanner from the tokens to parse, scanning
+/// `source` from byte `start` on.
+fn scan(source: &str, start: usize) -> (Vec<Token>, Vec<Diagnostic>) {
+    let mut diagnostics = vec![];
+    let mut tokens = vec![];
+    let mut scanner = Scanner::new(source);
+    scanner.position = start;
+    for token in scanner.scan_tokens() {
+        match &token.r#type {
+            Type::Error(error) => diagnostics.push(Diagnostic::from_token_error(error, token.span)),
+            _ => tokens.push(token),
+        }
+    }
+
+    (tokens, diagnostics)
+}
 
 struct Scanner<'a> {
     bytes: &'a [u8],
@@ -10,7 +241,7 @@ struct Scanner<'a> {
 }
 
 impl Scanner<'_> {
-    fn new(source: &str) -> Scanner {
+    fn new(source: &str) -> Scanner<'_> {
         Scanner { bytes: source.as_bytes(), position: 0 }
     }
 
@@ -18,12 +249,14 @@ impl Scanner<'_> {
         let mut tokens: Vec<Token> = vec![];
 
         while !self.is_at_end() {
+            let start = self.position;
             let r#type = self.identify_token_type();
-            let token = Token { r#type };
+            let end = (self.position + 1).min(self.bytes.len());  // Stops over the last byte of the lexeme
+            let token = Token { r#type, span: Span { start, end } };
 
             match token {
-                Token { r#type: Type::SlashSlash } => self.skip_current_line(),
-                Token { r#type: Type::Whitespace } => (),
+                Token { r#type: Type::SlashSlash, .. } => self.skip_current_line(),
+                Token { r#type: Type::Whitespace, .. } => (),
                 token => tokens.push(token),
             }
             self.advance();
@@ -73,21 +306,38 @@ impl Scanner<'_> {
             b")" => RightParen,
             b"{" => LeftBrace,
             b"}" => RightBrace,
+            b"[" => LeftBracket,
+            b"]" => RightBracket,
             b"," => Comma,
+            b":" => Colon,
             b"." => Dot,
             b"-" => Minus,
             b"+" => Plus,
             b";" => Semicolon,
             b"*" => Star,
             b"!" => self.decide_token_type(Bang, (BangEqual, b"=")),
+            b"=" if self.next_byte() == Some(&b'>') => {
+                self.advance();  // Skips the `>` like `decide_token_type` does
+                Arrow
+            }
             b"=" => self.decide_token_type(Equal, (EqualEqual, b"=")),
             b">" => self.decide_token_type(Greater, (GreaterEqual, b"=")),
             b"<" => self.decide_token_type(Less, (LessEqual, b"=")),
+            b"?" => self.decide_token_type(Question, (QuestionQuestion, b"?")),
             b"/" => self.decide_token_type(Slash, (SlashSlash, b"/")),
             [digit] if digit.is_ascii_digit() => self.treat_number(),
             &[a] if a.is_ascii_alphabetic() || a == b'_' => self.treat_word(),
-            _ => todo!("Unexpected lexeme {:#?}", std::str::from_utf8(&[self.current_byte()])),
+            _ => self.treat_unexpected_character(),
+        }
+    }
+
+    /// Stops over the last byte of the character, so that characters
+    /// encoded in several bytes are reported only once.
+    fn treat_unexpected_character(&mut self) -> Type {
+        while self.next_byte().is_some_and(|&byte| byte & 0b1100_0000 == 0b1000_0000) {
+            self.advance();
         }
+        Type::Error(token::Error::UnexpectedCharacter)
     }
 
     fn treat_string(&mut self) -> Type {
@@ -96,21 +346,20 @@ impl Scanner<'_> {
             // Didn't find the closing `"`...
             Type::Error(token::Error::UnterminatedString)
         } else {
-            let s = String::from_utf8(self.bytes[range].to_vec());
+            let s = std::str::from_utf8(&self.bytes[range]);
             let s = s.unwrap();  // TODO: Add error token
-            Type::StringLiteral(s)
+            Type::StringLiteral(Symbol::intern(s))
         }
     }
 
     fn treat_number(&mut self) -> Type {
         let (is_f64, range) = self.measure_number();
-        let number = std::str::from_utf8(&self.bytes[range]);
-        if is_f64 {
-            let n = number.unwrap().parse::<f64>().unwrap();
-            Type::NumberLiteral(token::NumberLiteral::Float(n))
-        } else {
-            let n = number.unwrap().parse::<i32>().unwrap();
-            Type::NumberLiteral(token::NumberLiteral::Integer(n))
+        // Only ASCII digits and a dot were measured
+        let number = std::str::from_utf8(&self.bytes[range]).unwrap();
+        match number.parse::<i32>() {
+            Ok(n) if !is_f64 => Type::NumberLiteral(token::NumberLiteral::Integer(n)),
+            // Integers too big for an `i32` are still numbers, which are all `f64`
+            _ => Type::NumberLiteral(token::NumberLiteral::Float(number.parse().unwrap())),
         }
     }
 
@@ -121,7 +370,9 @@ impl Scanner<'_> {
         let word = &self.bytes[range];
         match word {
             b"and" => Keyword(token::Keyword::And),
+            b"break" => Keyword(token::Keyword::Break),
             b"class" => Keyword(token::Keyword::Class),
+            b"continue" => Keyword(token::Keyword::Continue),
             b"else" => Keyword(token::Keyword::Else),
             b"false" => Keyword(token::Keyword::False),
             b"for" => Keyword(token::Keyword::For),
@@ -136,7 +387,7 @@ impl Scanner<'_> {
             b"true" => Keyword(token::Keyword::True),
             b"var" => Keyword(token::Keyword::Var),
             b"while" => Keyword(token::Keyword::While),
-            bytes => Type::Identifier(String::from_utf8(bytes.to_vec()).unwrap()),
+            bytes => Type::Identifier(Symbol::intern(std::str::from_utf8(bytes).unwrap())),
         }
     }
 
@@ -160,7 +411,7 @@ impl Scanner<'_> {
         let mut is_float = false;
         let start = self.position;
         self.advance_until_not_ascii_digit();
-        if !self.is_at_end() && &[self.current_byte()] == b"." {
+        if !self.is_at_end() && [self.current_byte()] == *b"." {
             self.advance();  // Skips the `.`
             self.advance_until_not_ascii_digit();
             is_float = true;
@@ -198,7 +449,7 @@ impl Scanner<'_> {
         let expected_bytes = compound_type.1;
         let compound_type = compound_type.0;
         match self.next_byte() {
-            Some(&byte) if &[byte] == expected_bytes => {
+            Some(&byte) if [byte] == expected_bytes => {
                 self.advance();  // Skips the next character to avoid matching it again
                 compound_type
             }
@@ -211,53 +462,59 @@ impl Scanner<'_> {
 #[cfg(test)]
 mod tests {
     use super::token::Type::*;
-    use crate::interpreter::token::*;
+    use crate::interpreter::token::{Error, Keyword, NumberLiteral};
 
     use super::*;
 
     #[test]
     fn scans_simple_unnambiguous_tokens() {
-        let code = "(){},.-+;*";
+        let code = "(){}[],:.-+;*";
 
         let tokens = Scanner::new(code).scan_tokens();
 
         assert_eq!(
             tokens,
             &[
-                Token { r#type: LeftParen },
-                Token { r#type: RightParen },
-                Token { r#type: LeftBrace },
-                Token { r#type: RightBrace },
-                Token { r#type: Comma },
-                Token { r#type: Dot },
-                Token { r#type: Minus },
-                Token { r#type: Plus },
-                Token { r#type: Semicolon },
-                Token { r#type: Star },
+                Token::from(LeftParen),
+                Token::from(RightParen),
+                Token::from(LeftBrace),
+                Token::from(RightBrace),
+                Token::from(LeftBracket),
+                Token::from(RightBracket),
+                Token::from(Comma),
+                Token::from(Colon),
+                Token::from(Dot),
+                Token::from(Minus),
+                Token::from(Plus),
+                Token::from(Semicolon),
+                Token::from(Star),
             ],
-            r#"Did not scan "(){{}},.-+;*""#
+            r#"Did not scan "(){{}}[],:.-+;*""#
         )
     }
 
     #[test]
     fn scans_ambiguous_tokens() {
-        let code = "!= ! == = > >= < <=";
+        let code = "!= ! == = => > >= < <= ?? ?";
 
         let tokens = Scanner::new(code).scan_tokens();
 
         assert_eq!(
             tokens,
             &[
-                Token { r#type: BangEqual },
-                Token { r#type: Bang },
-                Token { r#type: EqualEqual },
-                Token { r#type: Equal },
-                Token { r#type: Greater },
-                Token { r#type: GreaterEqual },
-                Token { r#type: Less },
-                Token { r#type: LessEqual },
+                Token::from(BangEqual),
+                Token::from(Bang),
+                Token::from(EqualEqual),
+                Token::from(Equal),
+                Token::from(Arrow),
+                Token::from(Greater),
+                Token::from(GreaterEqual),
+                Token::from(Less),
+                Token::from(LessEqual),
+                Token::from(QuestionQuestion),
+                Token::from(Question),
             ],
-            r#"Did not scan "!= ! == = > >= < <=""#
+            r#"Did not scan "!= ! == = => > >= < <= ?? ?""#
         )
     }
 
@@ -274,14 +531,14 @@ mod tests {
         assert_eq!(
             tokens,
             &[
-                Token { r#type: LeftParen },
-                Token { r#type: Plus },
-                Token { r#type: Minus },
-                Token { r#type: Star },
-                Token { r#type: Equal },
-                Token { r#type: RightParen },
-                Token { r#type: RightBrace },
-                Token { r#type: LeftBrace },
+                Token::from(LeftParen),
+                Token::from(Plus),
+                Token::from(Minus),
+                Token::from(Star),
+                Token::from(Equal),
+                Token::from(RightParen),
+                Token::from(RightBrace),
+                Token::from(LeftBrace),
             ],
         )
     }
@@ -299,7 +556,7 @@ mod tests {
             assert_eq!(
                 tokens,
                 &[
-                    Token { r#type: Equal },
+                    Token::from(Equal),
                 ],
             )
         }
@@ -313,11 +570,11 @@ mod tests {
             assert_eq!(
                 tokens,
                 &[
-                    Token { r#type: Plus },
-                    Token { r#type: Minus },
-                    Token { r#type: Star },
-                    Token { r#type: Slash },
-                    Token { r#type: Equal },
+                    Token::from(Plus),
+                    Token::from(Minus),
+                    Token::from(Star),
+                    Token::from(Slash),
+                    Token::from(Equal),
                 ],
             )
         }
@@ -335,10 +592,10 @@ mod tests {
             assert_eq!(
                 tokens,
                 &[
-                    Token { r#type: Plus },
-                    Token { r#type: Minus },
-                    Token { r#type: Minus },
-                    Token { r#type: Plus },
+                    Token::from(Plus),
+                    Token::from(Minus),
+                    Token::from(Minus),
+                    Token::from(Plus),
                 ],
             )
         }
@@ -356,7 +613,7 @@ mod tests {
             assert_eq!(
                 tokens,
                 &[
-                    Token { r#type: StringLiteral("This is a string!".to_string()) },
+                    Token::from(StringLiteral(Symbol::intern("This is a string!"))),
                 ],
             )
         }
@@ -370,11 +627,11 @@ mod tests {
             assert_eq!(
                 tokens,
                 &[
-                    Token { r#type: Plus },
-                    Token { r#type: Minus },
-                    Token { r#type: StringLiteral("This is a string!".to_string()) },
-                    Token { r#type: Minus },
-                    Token { r#type: Plus },
+                    Token::from(Plus),
+                    Token::from(Minus),
+                    Token::from(StringLiteral(Symbol::intern("This is a string!"))),
+                    Token::from(Minus),
+                    Token::from(Plus),
                 ],
             )
         }
@@ -391,11 +648,11 @@ mod tests {
             assert_eq!(
                 tokens,
                 &[
-                    Token { r#type: Plus },
-                    Token { r#type: Minus },
-                    Token { r#type: StringLiteral("This is a string!\n                And it is still going!".to_string()) },
-                    Token { r#type: Minus },
-                    Token { r#type: Plus },
+                    Token::from(Plus),
+                    Token::from(Minus),
+                    Token::from(StringLiteral(Symbol::intern("This is a string!\n                And it is still going!"))),
+                    Token::from(Minus),
+                    Token::from(Plus),
                 ],
             )
         }
@@ -409,14 +666,32 @@ mod tests {
             assert_eq!(
                 tokens,
                 &[
-                    Token { r#type: Plus },
-                    Token { r#type: Minus },
-                    Token { r#type: Error(Error::UnterminatedString) },
+                    Token::from(Plus),
+                    Token::from(Minus),
+                    Token::from(Error(Error::UnterminatedString)),
                 ],
             )
         }
     }
 
+    #[test]
+    fn detects_unexpected_characters() {
+        let code = "1 @ 2 é";
+
+        let tokens = Scanner::new(code).scan_tokens();
+
+        assert_eq!(
+            tokens,
+            &[
+                Token::from(NumberLiteral(NumberLiteral::Integer(1))),
+                Token::from(Error(Error::UnexpectedCharacter)),
+                Token::from(NumberLiteral(NumberLiteral::Integer(2))),
+                Token::from(Error(Error::UnexpectedCharacter)),
+            ],
+        );
+        assert_eq!(tokens[3].span, Span { start: 6, end: 8 });
+    }
+
     mod numbers {
         use super::*;
 
@@ -432,7 +707,7 @@ mod tests {
                 assert_eq!(
                     tokens,
                     &[
-                        Token { r#type: NumberLiteral(NumberLiteral::Integer(123)) },
+                        Token::from(NumberLiteral(NumberLiteral::Integer(123))),
                     ],
                 )
             }
@@ -446,11 +721,26 @@ mod tests {
                 assert_eq!(
                     tokens,
                     &[
-                        Token { r#type: NumberLiteral(NumberLiteral::Integer(0)) },
-                        Token { r#type: Plus },
-                        Token { r#type: NumberLiteral(NumberLiteral::Integer(123)) },
-                        Token { r#type: Minus },
-                        Token { r#type: NumberLiteral(NumberLiteral::Integer(1)) },
+                        Token::from(NumberLiteral(NumberLiteral::Integer(0))),
+                        Token::from(Plus),
+                        Token::from(NumberLiteral(NumberLiteral::Integer(123))),
+                        Token::from(Minus),
+                        Token::from(NumberLiteral(NumberLiteral::Integer(1))),
+                    ],
+                )
+            }
+
+            #[test]
+            fn scans_integers_too_big_for_i32_as_floats() {
+                let code = "3000000000 2147483647";
+
+                let tokens = Scanner::new(code).scan_tokens();
+
+                assert_eq!(
+                    tokens,
+                    &[
+                        Token::from(NumberLiteral(NumberLiteral::Float(3_000_000_000.0))),
+                        Token::from(NumberLiteral(NumberLiteral::Integer(i32::MAX))),
                     ],
                 )
             }
@@ -468,7 +758,7 @@ mod tests {
                 assert_eq!(
                     tokens,
                     &[
-                        Token { r#type: NumberLiteral(NumberLiteral::Float(12.3)) },
+                        Token::from(NumberLiteral(NumberLiteral::Float(12.3))),
                     ],
                 )
             }
@@ -482,11 +772,11 @@ mod tests {
                 assert_eq!(
                     tokens,
                     &[
-                        Token { r#type: NumberLiteral(NumberLiteral::Integer(0)) },
-                        Token { r#type: Plus },
-                        Token { r#type: NumberLiteral(NumberLiteral::Float(12.3)) },
-                        Token { r#type: Slash },
-                        Token { r#type: NumberLiteral(NumberLiteral::Integer(5)) },
+                        Token::from(NumberLiteral(NumberLiteral::Integer(0))),
+                        Token::from(Plus),
+                        Token::from(NumberLiteral(NumberLiteral::Float(12.3))),
+                        Token::from(Slash),
+                        Token::from(NumberLiteral(NumberLiteral::Integer(5))),
                     ],
                 )
             }
@@ -501,11 +791,11 @@ mod tests {
             assert_eq!(
                 tokens,
                 &[
-                    Token { r#type: NumberLiteral(NumberLiteral::Integer(0)) },
-                    Token { r#type: Plus },
-                    Token { r#type: NumberLiteral(NumberLiteral::Float(12.3)) },
-                    Token { r#type: BangEqual },
-                    Token { r#type: NumberLiteral(NumberLiteral::Float(5.77)) },
+                    Token::from(NumberLiteral(NumberLiteral::Integer(0))),
+                    Token::from(Plus),
+                    Token::from(NumberLiteral(NumberLiteral::Float(12.3))),
+                    Token::from(BangEqual),
+                    Token::from(NumberLiteral(NumberLiteral::Float(5.77))),
                 ],
             )
         }
@@ -518,7 +808,9 @@ mod tests {
         fn scans_keywords() {
             let code = r#"
                 and
+                break
                 class
+                continue
                 else
                 false
                 for
@@ -540,22 +832,24 @@ mod tests {
             assert_eq!(
                 tokens,
                 &[
-                    Token { r#type: Keyword(Keyword::And) },
-                    Token { r#type: Keyword(Keyword::Class) },
-                    Token { r#type: Keyword(Keyword::Else) },
-                    Token { r#type: Keyword(Keyword::False) },
-                    Token { r#type: Keyword(Keyword::For) },
-                    Token { r#type: Keyword(Keyword::Fun) },
-                    Token { r#type: Keyword(Keyword::If) },
-                    Token { r#type: Keyword(Keyword::Nil) },
-                    Token { r#type: Keyword(Keyword::Or) },
-                    Token { r#type: Keyword(Keyword::Print) },
-                    Token { r#type: Keyword(Keyword::Return) },
-                    Token { r#type: Keyword(Keyword::Super) },
-                    Token { r#type: Keyword(Keyword::This) },
-                    Token { r#type: Keyword(Keyword::True) },
-                    Token { r#type: Keyword(Keyword::Var) },
-                    Token { r#type: Keyword(Keyword::While) },
+                    Token::from(Keyword(Keyword::And)),
+                    Token::from(Keyword(Keyword::Break)),
+                    Token::from(Keyword(Keyword::Class)),
+                    Token::from(Keyword(Keyword::Continue)),
+                    Token::from(Keyword(Keyword::Else)),
+                    Token::from(Keyword(Keyword::False)),
+                    Token::from(Keyword(Keyword::For)),
+                    Token::from(Keyword(Keyword::Fun)),
+                    Token::from(Keyword(Keyword::If)),
+                    Token::from(Keyword(Keyword::Nil)),
+                    Token::from(Keyword(Keyword::Or)),
+                    Token::from(Keyword(Keyword::Print)),
+                    Token::from(Keyword(Keyword::Return)),
+                    Token::from(Keyword(Keyword::Super)),
+                    Token::from(Keyword(Keyword::This)),
+                    Token::from(Keyword(Keyword::True)),
+                    Token::from(Keyword(Keyword::Var)),
+                    Token::from(Keyword(Keyword::While)),
                 ],
             )
         }
@@ -569,8 +863,8 @@ mod tests {
             assert_eq!(
                 tokens,
                 &[
-                    Token { r#type: Keyword(Keyword::Fun) },
-                    Token { r#type: Keyword(Keyword::Var) },
+                    Token::from(Keyword(Keyword::Fun)),
+                    Token::from(Keyword(Keyword::Var)),
                 ],
             )
         }
@@ -588,11 +882,11 @@ mod tests {
             assert_eq!(
                 tokens,
                 &[
-                    Token { r#type: Keyword(Keyword::Var) },
-                    Token { r#type: Identifier("fred".to_string()) },
-                    Token { r#type: Equal },
-                    Token { r#type: NumberLiteral(NumberLiteral::Integer(5)) },
-                    Token { r#type: Semicolon },
+                    Token::from(Keyword(Keyword::Var)),
+                    Token::from(Identifier(Symbol::intern("fred"))),
+                    Token::from(Equal),
+                    Token::from(NumberLiteral(NumberLiteral::Integer(5))),
+                    Token::from(Semicolon),
                 ],
             )
         }
@@ -606,11 +900,11 @@ mod tests {
             assert_eq!(
                 tokens,
                 &[
-                    Token { r#type: Keyword(Keyword::Var) },
-                    Token { r#type: Identifier("fred".to_string()) },
-                    Token { r#type: Equal },
-                    Token { r#type: NumberLiteral(NumberLiteral::Integer(5)) },
-                    Token { r#type: Semicolon },
+                    Token::from(Keyword(Keyword::Var)),
+                    Token::from(Identifier(Symbol::intern("fred"))),
+                    Token::from(Equal),
+                    Token::from(NumberLiteral(NumberLiteral::Integer(5))),
+                    Token::from(Semicolon),
                 ],
             )
         }
@@ -628,20 +922,20 @@ mod tests {
             assert_eq!(
                 tokens,
                 &[
-                    Token { r#type: Keyword(Keyword::Fun) },
-                    Token { r#type: Identifier("foo_bar_1".to_string()) },
-                    Token { r#type: LeftParen },
-                    Token { r#type: Identifier("x".to_string()) },
-                    Token { r#type: Comma },
-                    Token { r#type: Identifier("y".to_string()) },
-                    Token { r#type: RightParen },
-                    Token { r#type: LeftBrace },
-                    Token { r#type: Keyword(Keyword::Return) },
-                    Token { r#type: Identifier("x".to_string()) },
-                    Token { r#type: Plus },
-                    Token { r#type: Identifier("y".to_string()) },
-                    Token { r#type: Semicolon },
-                    Token { r#type: RightBrace },
+                    Token::from(Keyword(Keyword::Fun)),
+                    Token::from(Identifier(Symbol::intern("foo_bar_1"))),
+                    Token::from(LeftParen),
+                    Token::from(Identifier(Symbol::intern("x"))),
+                    Token::from(Comma),
+                    Token::from(Identifier(Symbol::intern("y"))),
+                    Token::from(RightParen),
+                    Token::from(LeftBrace),
+                    Token::from(Keyword(Keyword::Return)),
+                    Token::from(Identifier(Symbol::intern("x"))),
+                    Token::from(Plus),
+                    Token::from(Identifier(Symbol::intern("y"))),
+                    Token::from(Semicolon),
+                    Token::from(RightBrace),
                 ],
             )
         }
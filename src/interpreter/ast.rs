@@ -0,0 +1,91 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use super::symbol::Symbol;
+use super::token::{Span, Token};
+
+#[derive(Debug)]
+pub(crate) enum Expr {
+    Literal(Literal),
+    Grouping(Box<Expr>),
+    Unary { operator: Token, right: Box<Expr> },
+    Binary { left: Box<Expr>, operator: Token, right: Box<Expr> },
+    /// `and`, `or` and `??`, which may not evaluate their right operand.
+    Logical { left: Box<Expr>, operator: Token, right: Box<Expr> },
+    /// `condition ? then_branch : else_branch`.
+    Conditional { condition: Box<Expr>, then_branch: Box<Expr>, else_branch: Box<Expr> },
+    Variable(Variable),
+    Assign { variable: Variable, value: Box<Expr> },
+    /// `paren` is the closing parenthesis, where call errors are reported.
+    Call { callee: Box<Expr>, paren: Span, arguments: Vec<Expr> },
+    Get { object: Box<Expr>, name: Identifier },
+    Set { object: Box<Expr>, name: Identifier, value: Box<Expr> },
+    List(Vec<Expr>),
+    /// `brace` is the opening brace, where invalid keys are reported.
+    Map { brace: Span, entries: Vec<(Expr, Expr)> },
+    /// `bracket` is the closing bracket, where index errors are reported.
+    Index { object: Box<Expr>, bracket: Span, index: Box<Expr> },
+    SetIndex { object: Box<Expr>, bracket: Span, index: Box<Expr>, value: Box<Expr> },
+    This(Variable),
+    Super { keyword: Variable, method: Identifier },
+    /// `fun (a) { ... }` and `(a) => ...`, whose name is `lambda`.
+    Function(Rc<Function>),
+}
+
+#[derive(Debug)]
+pub(crate) enum Literal {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Symbol),
+}
+
+#[derive(Debug)]
+pub(crate) enum Stmt {
+    Expression(Expr),
+    Print(Expr),
+    Var { name: Identifier, initializer: Option<Expr> },
+    Block(Vec<Stmt>),
+    If { condition: Expr, then_branch: Box<Stmt>, else_branch: Option<Box<Stmt>> },
+    /// Also used for `for` loops, which are desugared into a `while` whose
+    /// `increment` runs after each iteration, even those that `continue`.
+    While { condition: Expr, body: Box<Stmt>, increment: Option<Expr> },
+    Function(Rc<Function>),
+    Class { name: Identifier, superclass: Option<Variable>, methods: Vec<Rc<Function>> },
+    Return { keyword: Span, value: Option<Expr> },
+    Break { keyword: Span },
+    Continue { keyword: Span },
+}
+
+/// Shared between the declaration and every function value created from it.
+///
+/// Arrow functions have a body that returns their expression.
+#[derive(Debug)]
+pub(crate) struct Function {
+    pub(crate) name: Identifier,
+    pub(crate) params: Vec<Identifier>,
+    pub(crate) body: Vec<Stmt>,
+}
+
+/// A name as written in the source, such as a variable being declared or
+/// referenced.
+#[derive(Clone, Debug)]
+pub(crate) struct Identifier {
+    pub(crate) name: Symbol,
+    pub(crate) span: Span,
+}
+
+/// A use of a variable, which is also how `this` and `super` are looked up.
+#[derive(Debug)]
+pub(crate) struct Variable {
+    pub(crate) name: Identifier,
+    /// Number of scopes between the use and the declaration, or `None` for
+    /// globals. Set by the resolver.
+    pub(crate) depth: Cell<Option<usize>>,
+}
+
+impl Variable {
+    pub(crate) fn new(name: Identifier) -> Variable {
+        Variable { name, depth: Cell::new(None) }
+    }
+}
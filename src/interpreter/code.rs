@@ -0,0 +1,735 @@
+/// Stable identifier of a kind of error, like `L0001`.
+///
+/// Codes are never reused nor renumbered, so they can be searched for and
+/// looked up with `lox explain`. The hundreds tell which phase finds the
+/// error: 0 for the scanner, 1 for the parser, 2 for the resolver, 3 for the
+/// runtime and 4 for limits of the bytecode compiler.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Code(u16);
+
+impl Code {
+    pub(crate) const UNTERMINATED_STRING: Code = Code(1);
+    pub(crate) const UNEXPECTED_CHARACTER: Code = Code(2);
+
+    pub(crate) const EXPECT_EXPRESSION: Code = Code(101);
+    pub(crate) const EXPECT_TOKEN: Code = Code(102);
+    pub(crate) const INVALID_ASSIGNMENT_TARGET: Code = Code(103);
+    pub(crate) const TOO_MANY_ARGUMENTS: Code = Code(104);
+    pub(crate) const TOO_MANY_PARAMETERS: Code = Code(105);
+    pub(crate) const INHERITS_FROM_ITSELF: Code = Code(106);
+    pub(crate) const TOO_MANY_ELEMENTS: Code = Code(107);
+
+    pub(crate) const READ_IN_OWN_INITIALIZER: Code = Code(201);
+    pub(crate) const ALREADY_DECLARED: Code = Code(202);
+    pub(crate) const RETURN_FROM_TOP_LEVEL: Code = Code(203);
+    pub(crate) const RETURN_VALUE_FROM_INITIALIZER: Code = Code(204);
+    pub(crate) const THIS_OUTSIDE_CLASS: Code = Code(205);
+    pub(crate) const SUPER_OUTSIDE_CLASS: Code = Code(206);
+    pub(crate) const SUPER_WITHOUT_SUPERCLASS: Code = Code(207);
+    pub(crate) const BREAK_OUTSIDE_LOOP: Code = Code(208);
+    pub(crate) const CONTINUE_OUTSIDE_LOOP: Code = Code(209);
+
+    pub(crate) const NOT_CALLABLE: Code = Code(301);
+    pub(crate) const WRONG_ARITY: Code = Code(302);
+    pub(crate) const STACK_OVERFLOW: Code = Code(303);
+    pub(crate) const OPERAND_MUST_BE_NUMBER: Code = Code(304);
+    pub(crate) const OPERANDS_MUST_BE_NUMBERS: Code = Code(305);
+    pub(crate) const OPERANDS_MUST_BE_NUMBERS_OR_STRINGS: Code = Code(306);
+    pub(crate) const UNDEFINED_VARIABLE: Code = Code(307);
+    pub(crate) const UNDEFINED_PROPERTY: Code = Code(308);
+    pub(crate) const NOT_AN_INSTANCE: Code = Code(309);
+    pub(crate) const FIELDS_ON_NON_INSTANCE: Code = Code(310);
+    pub(crate) const SUPERCLASS_MUST_BE_CLASS: Code = Code(311);
+    pub(crate) const NATIVE: Code = Code(312);
+    pub(crate) const NOT_INDEXABLE: Code = Code(313);
+    pub(crate) const INDEX_MUST_BE_INTEGER: Code = Code(314);
+    pub(crate) const INDEX_OUT_OF_BOUNDS: Code = Code(315);
+    pub(crate) const POP_FROM_EMPTY_LIST: Code = Code(316);
+    pub(crate) const INVALID_KEY: Code = Code(317);
+    pub(crate) const UNDEFINED_KEY: Code = Code(318);
+    pub(crate) const INVALID_OBJECT: Code = Code(319);
+
+    pub(crate) const TOO_MANY_CONSTANTS: Code = Code(401);
+    pub(crate) const TOO_MANY_LOCALS: Code = Code(402);
+    pub(crate) const JUMP_TOO_LARGE: Code = Code(403);
+    pub(crate) const LOOP_TOO_LARGE: Code = Code(404);
+    pub(crate) const TOO_MANY_UPVALUES: Code = Code(405);
+
+    /// Reads codes as written in diagnostics, like `L0001`, ignoring case.
+    ///
+    /// Returns `None` for codes that no error has.
+    pub fn parse(code: &str) -> Option<Code> {
+        let digits = code.strip_prefix(['L', 'l'])?;
+        if digits.len() != 4 || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        let code = Code(digits.parse().ok()?);
+        EXPLANATIONS.iter().any(|(known, _)| *known == code).then_some(code)
+    }
+
+    /// A longer description of the error than its message, with an example
+    /// and how to fix it.
+    pub fn explanation(self) -> &'static str {
+        let (_, explanation) = EXPLANATIONS.iter().find(|(code, _)| *code == self).expect("Code without explanation");
+        explanation.trim()
+    }
+}
+
+impl std::fmt::Display for Code {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "L{:04}", self.0)
+    }
+}
+
+const EXPLANATIONS: &[(Code, &str)] = &[
+    (Code::UNTERMINATED_STRING, r#"
+A string literal has no closing quote.
+
+Strings start and end with `"` and may span several lines, so a missing quote
+makes the string go on until the end of the file.
+
+Erroneous example:
+
+    print "Hello, world;
+
+Add the closing quote:
+
+    print "Hello, world";
+"#),
+    (Code::UNEXPECTED_CHARACTER, r#"
+The code contains a character that is not part of Lox.
+
+Outside of strings and comments, only ASCII letters, digits, `_`, whitespace
+and the punctuation used by operators can appear.
+
+Erroneous example:
+
+    var total = price # 2;
+
+Remove the character or replace it with the intended operator:
+
+    var total = price * 2;
+"#),
+    (Code::EXPECT_EXPRESSION, r#"
+An expression was expected, but something else was found.
+
+This happens when an operator is missing an operand, or when a statement
+keyword appears where a value should be.
+
+Erroneous example:
+
+    var a = 1 + ;
+
+Complete the expression:
+
+    var a = 1 + 2;
+"#),
+    (Code::EXPECT_TOKEN, r#"
+A specific token, named in the message, was expected but something else was
+found.
+
+Most often a `;` is missing at the end of a statement, or a parenthesis or
+brace is left unclosed.
+
+Erroneous example:
+
+    print "one"
+    print "two";
+
+Add the missing token:
+
+    print "one";
+    print "two";
+"#),
+    (Code::INVALID_ASSIGNMENT_TARGET, r#"
+The left side of `=` is not something that can be assigned to.
+
+Only variables, fields of instances and elements of lists and maps can be
+assigned.
+
+Erroneous example:
+
+    var a = 1;
+    var b = 2;
+    a + b = 3;
+
+Assign to a variable or field instead:
+
+    var c = a + b;
+"#),
+    (Code::TOO_MANY_ARGUMENTS, r#"
+A call has more than 255 arguments.
+
+Calls are limited to 255 arguments, like function declarations are limited to
+255 parameters.
+
+Group related arguments into an instance and pass it instead:
+
+    class Point { init(x, y) { this.x = x; this.y = y; } }
+    draw(Point(1, 2));
+"#),
+    (Code::TOO_MANY_PARAMETERS, r#"
+A function declaration has more than 255 parameters.
+
+Functions are limited to 255 parameters, like calls are limited to 255
+arguments.
+
+Group related parameters into an instance and take it instead:
+
+    fun draw(point) { print point.x; }
+"#),
+    (Code::INHERITS_FROM_ITSELF, r#"
+A class names itself as its superclass.
+
+Inheritance can't be circular, so a class can only inherit from another class.
+
+Erroneous example:
+
+    class Oops < Oops {}
+
+Inherit from a different class, or from none:
+
+    class Base {}
+    class Derived < Base {}
+"#),
+    (Code::TOO_MANY_ELEMENTS, r#"
+A list literal has more than 255 elements, or a map literal more than 255
+entries.
+
+Literals are limited to 255 elements, like calls are limited to 255
+arguments.
+
+Build long lists by pushing elements onto a shorter one:
+
+    var squares = [];
+    for (var i = 0; i < 1000; i = i + 1) squares.push(i * i);
+"#),
+    (Code::READ_IN_OWN_INITIALIZER, r#"
+A local variable is read in the expression that initializes it.
+
+The variable is declared, but it has no value until its initializer finishes,
+even if a variable of the same name exists in an enclosing scope.
+
+Erroneous example:
+
+    var a = 1;
+    {
+        var a = a + 1;
+    }
+
+Give the inner variable a different name:
+
+    var a = 1;
+    {
+        var b = a + 1;
+    }
+"#),
+    (Code::ALREADY_DECLARED, r#"
+A local variable is declared twice in the same scope.
+
+Globals can be redeclared, but locals and parameters can't, as that is usually
+a mistake.
+
+Erroneous example:
+
+    fun f() {
+        var a = 1;
+        var a = 2;
+    }
+
+Assign to the existing variable instead:
+
+    fun f() {
+        var a = 1;
+        a = 2;
+    }
+"#),
+    (Code::RETURN_FROM_TOP_LEVEL, r#"
+A `return` statement appears outside of any function.
+
+Only functions and methods can return.
+
+Erroneous example:
+
+    return 1;
+
+Move the code into a function, or remove the `return`:
+
+    fun one() {
+        return 1;
+    }
+"#),
+    (Code::RETURN_VALUE_FROM_INITIALIZER, r#"
+An `init` method returns a value.
+
+Initializers always return the instance being initialized, so they can only
+use `return` without a value to stop early.
+
+Erroneous example:
+
+    class Point {
+        init(x) {
+            this.x = x;
+            return x;
+        }
+    }
+
+Return without a value:
+
+    class Point {
+        init(x) {
+            this.x = x;
+            return;
+        }
+    }
+"#),
+    (Code::THIS_OUTSIDE_CLASS, r#"
+`this` is used outside of a method.
+
+`this` is the instance a method was called on, so it only exists inside class
+bodies.
+
+Erroneous example:
+
+    fun name() {
+        return this.name;
+    }
+
+Take the instance as a parameter instead:
+
+    fun name(person) {
+        return person.name;
+    }
+"#),
+    (Code::SUPER_OUTSIDE_CLASS, r#"
+`super` is used outside of a method.
+
+`super` looks up methods of the superclass of the enclosing class, so it only
+exists inside class bodies.
+
+Erroneous example:
+
+    fun greet() {
+        super.greet();
+    }
+
+Call the method on an instance instead:
+
+    fun greet(person) {
+        person.greet();
+    }
+"#),
+    (Code::SUPER_WITHOUT_SUPERCLASS, r#"
+`super` is used in a class that doesn't inherit from any other.
+
+Erroneous example:
+
+    class Dog {
+        speak() {
+            super.speak();
+        }
+    }
+
+Declare the superclass:
+
+    class Animal { speak() { print "..."; } }
+    class Dog < Animal {
+        speak() {
+            super.speak();
+        }
+    }
+"#),
+    (Code::BREAK_OUTSIDE_LOOP, r#"
+A `break` statement appears outside of any loop.
+
+`break` stops the innermost `while` or `for` loop of the function it is in, so
+it can't be used outside of loops, nor stop loops around the function.
+
+Erroneous example:
+
+    fun find(n) {
+        if (n > 10) break;
+    }
+
+Return from the function instead:
+
+    fun find(n) {
+        if (n > 10) return;
+    }
+"#),
+    (Code::CONTINUE_OUTSIDE_LOOP, r#"
+A `continue` statement appears outside of any loop.
+
+`continue` skips to the next iteration of the innermost `while` or `for` loop
+of the function it is in, so it can't be used outside of loops, nor in
+functions declared inside loops.
+
+Erroneous example:
+
+    for (var i = 0; i < 3; i = i + 1) {
+        fun skip() {
+            continue;
+        }
+    }
+
+Move the `continue` into the loop itself:
+
+    for (var i = 0; i < 3; i = i + 1) {
+        if (i == 1) continue;
+        print i;
+    }
+"#),
+    (Code::NOT_CALLABLE, r#"
+A value that is not a function or class was called.
+
+Erroneous example:
+
+    var name = "Lox";
+    name();
+
+Only call functions, methods and classes:
+
+    fun name() { return "Lox"; }
+    name();
+"#),
+    (Code::WRONG_ARITY, r#"
+A function was called with a different number of arguments than it has
+parameters.
+
+Calling a class passes the arguments to its `init` method, which must take
+them all.
+
+Erroneous example:
+
+    fun add(a, b) { return a + b; }
+    add(1);
+
+Pass exactly one argument per parameter:
+
+    add(1, 2);
+"#),
+    (Code::STACK_OVERFLOW, r#"
+Too many calls were nested, usually because of recursion that never stops.
+
+Erroneous example:
+
+    fun count(n) {
+        return count(n + 1);
+    }
+    count(0);
+
+Make sure recursion reaches a case that returns without recursing:
+
+    fun count(n) {
+        if (n == 10) return n;
+        return count(n + 1);
+    }
+"#),
+    (Code::OPERAND_MUST_BE_NUMBER, r#"
+The operand of `-` is not a number.
+
+Erroneous example:
+
+    print -"1";
+
+Negate numbers only:
+
+    print -1;
+"#),
+    (Code::OPERANDS_MUST_BE_NUMBERS, r#"
+An operand of an arithmetic or comparison operator is not a number.
+
+`-`, `*`, `/`, `<`, `<=`, `>` and `>=` only work on numbers. Lox never
+converts other values into numbers.
+
+Erroneous example:
+
+    print "3" * 2;
+
+Use numbers on both sides:
+
+    print 3 * 2;
+"#),
+    (Code::OPERANDS_MUST_BE_NUMBERS_OR_STRINGS, r#"
+The operands of `+` are neither two numbers nor two strings.
+
+`+` adds numbers and concatenates strings, but doesn't mix them.
+
+Erroneous example:
+
+    print "Total: " + 3;
+
+Keep both operands as strings, so that `+` concatenates them into a single
+expression:
+
+    print "Total: " + "3";
+
+Or use one `print` statement per value:
+
+    print "Total:";
+    print 3;
+"#),
+    (Code::UNDEFINED_VARIABLE, r#"
+A variable was used, but no variable with that name exists.
+
+Global variables must be declared before the code using them runs.
+
+Erroneous example:
+
+    print count;
+    var count = 1;
+
+Declare the variable first, and check its spelling:
+
+    var count = 1;
+    print count;
+"#),
+    (Code::UNDEFINED_PROPERTY, r#"
+An instance has no field nor method with the name that was read.
+
+Fields only exist after they are assigned, usually in `init`.
+
+Erroneous example:
+
+    class Point { init(x) { this.x = x; } }
+    print Point(1).y;
+
+Assign the field before reading it:
+
+    class Point { init(x, y) { this.x = x; this.y = y; } }
+    print Point(1, 2).y;
+"#),
+    (Code::NOT_AN_INSTANCE, r#"
+A property was read from a value that is not an instance, a list or a map.
+
+Only instances of classes have fields and methods. Lists and maps have
+methods like `len`.
+
+Erroneous example:
+
+    var name = "Lox";
+    print name.length;
+
+Read properties of instances only.
+"#),
+    (Code::FIELDS_ON_NON_INSTANCE, r#"
+A field was assigned on a value that is not an instance.
+
+Only instances of classes have fields.
+
+Erroneous example:
+
+    var point = nil;
+    point.x = 1;
+
+Create an instance first:
+
+    class Point {}
+    var point = Point();
+    point.x = 1;
+"#),
+    (Code::SUPERCLASS_MUST_BE_CLASS, r#"
+A class inherits from a value that is not a class.
+
+Erroneous example:
+
+    var Base = "Base";
+    class Derived < Base {}
+
+Inherit from a class:
+
+    class Base {}
+    class Derived < Base {}
+"#),
+    (Code::NATIVE, r#"
+A native function, implemented by the host of the interpreter, failed.
+
+The message comes from the native function itself, and tells what went wrong.
+Check the arguments passed to it.
+"#),
+    (Code::NOT_INDEXABLE, r#"
+A value that is neither a list nor a map was indexed with `[]`.
+
+Erroneous example:
+
+    var name = "Lox";
+    print name[0];
+
+Index lists and maps only:
+
+    var letters = ["L", "o", "x"];
+    print letters[0];
+"#),
+    (Code::INDEX_MUST_BE_INTEGER, r#"
+A list was indexed with something other than a whole number.
+
+Indices count elements from 0, so they are never fractions, and Lox never
+converts other values into numbers.
+
+Erroneous example:
+
+    var xs = [1, 2, 3];
+    print xs["1"];
+
+Index with whole numbers:
+
+    print xs[1];
+"#),
+    (Code::INDEX_OUT_OF_BOUNDS, r#"
+A list was indexed past its end, or with a negative index.
+
+The elements of a list of length `n` are at indices `0` to `n - 1`. `insert`
+and `slice` also accept `n`, to refer to the end of the list.
+
+Erroneous example:
+
+    var xs = [1, 2, 3];
+    print xs[3];
+
+Check the index against the length of the list:
+
+    var i = 3;
+    if (i < xs.len()) print xs[i];
+"#),
+    (Code::POP_FROM_EMPTY_LIST, r#"
+`pop` was called on a list without elements.
+
+Erroneous example:
+
+    var stack = [];
+    print stack.pop();
+
+Check that the list has elements first:
+
+    if (stack.len() > 0) print stack.pop();
+"#),
+    (Code::INVALID_KEY, r#"
+A map was indexed with a value that can't be a key.
+
+Keys of maps are compared by value, so only strings, numbers and booleans can
+be keys. Instances, lists and other objects can't, nor can `nil`.
+
+Erroneous example:
+
+    var ages = {};
+    ages[nil] = 3;
+
+Use a string, number or boolean as the key:
+
+    ages["nobody"] = 3;
+"#),
+    (Code::UNDEFINED_KEY, r#"
+A map was indexed with a key that it doesn't have, or a key that it doesn't
+have was removed.
+
+Keys only exist after they are assigned or written in the map literal.
+
+Erroneous example:
+
+    var ages = {"ada": 36};
+    print ages["alan"];
+
+Check that the map has the key first:
+
+    if (ages.has("alan")) print ages["alan"];
+"#),
+    (Code::INVALID_OBJECT, r#"
+A native function returned an object that it was not given in the same call.
+
+The bytecode backend may free or move objects between calls, so natives can
+only return the objects passed to them by the call that is returning. Objects
+of one interpreter don't exist in another either.
+
+Return a value built from the arguments of the call instead, such as a
+number or a string, or the argument itself.
+"#),
+    (Code::TOO_MANY_CONSTANTS, r#"
+A function uses more than 65536 different constants, which is all that the
+bytecode backend can address in one function.
+
+Numbers, strings, and names of globals, properties and nested functions are
+all constants. The top-level code of a script counts as a function too.
+
+Split the code into smaller functions, each with its own constants:
+
+    fun first() { print "one"; print "two"; }
+    fun second() { print "three"; print "four"; }
+"#),
+    (Code::TOO_MANY_LOCALS, r#"
+A function has more than 256 local variables in scope at once, which is all
+that the bytecode backend can address in one function.
+
+Parameters count as locals, as do variables of enclosing blocks.
+
+Close blocks so their variables go out of scope, or split the function:
+
+    {
+        var a = 1;
+        print a;
+    }
+    {
+        var b = 2;
+        print b;
+    }
+"#),
+    (Code::JUMP_TOO_LARGE, r#"
+The branch of an `if`, the body of a loop, or the right operand of `and` or
+`or` compiled to more than 65535 bytes of bytecode, which is as far as the
+bytecode backend can jump.
+
+Move part of the code into a function and call it instead:
+
+    fun handle() {
+        // ...
+    }
+    if (ready) handle();
+"#),
+    (Code::LOOP_TOO_LARGE, r#"
+The body of a loop compiled to more than 65535 bytes of bytecode, which is as
+far back as the bytecode backend can jump.
+
+Move part of the body into a function and call it instead:
+
+    fun step(i) {
+        // ...
+    }
+    for (var i = 0; i < 10; i = i + 1) step(i);
+"#),
+    (Code::TOO_MANY_UPVALUES, r#"
+A function uses more than 256 variables of the functions enclosing it, which
+is all that the bytecode backend can capture in one closure.
+
+Pass some of the values as arguments instead of capturing them:
+
+    fun outer() {
+        var a = 1;
+        fun inner(a) {
+            print a;
+        }
+        inner(a);
+    }
+"#),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_known_codes() {
+        assert_eq!(Code::parse("L0001"), Some(Code::UNTERMINATED_STRING));
+        assert_eq!(Code::parse("l0307"), Some(Code::UNDEFINED_VARIABLE));
+        assert_eq!(Code::parse("L0999"), None);
+        assert_eq!(Code::parse("L1"), None);
+        assert_eq!(Code::parse("0001"), None);
+    }
+
+    #[test]
+    fn explains_every_code_once() {
+        for (i, (code, explanation)) in EXPLANATIONS.iter().enumerate() {
+            assert!(!explanation.trim().is_empty());
+            assert_eq!(Code::parse(&code.to_string()), Some(*code));
+            assert!(EXPLANATIONS[i + 1..].iter().all(|(other, _)| other != code), "{code} is explained twice");
+        }
+    }
+}
@@ -0,0 +1,436 @@
+use std::fmt::Write;
+
+use super::code::Code;
+use super::token::{self, Span};
+use super::{evaluator, parser, resolver, vm};
+
+/// A problem found in Lox code, pointing at where it happened.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub(crate) severity: Severity,
+    pub(crate) code: Option<Code>,
+    pub(crate) message: String,
+    pub(crate) span: Span,
+    pub(crate) notes: Vec<Note>,
+    pub(crate) help: Option<String>,
+    /// Calls running when a runtime error happened, innermost first.
+    pub(crate) trace: Vec<Frame>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Extra information about a diagnostic, which may point at another place in
+/// the source.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Note {
+    pub(crate) message: String,
+    pub(crate) span: Option<Span>,
+}
+
+/// A function call that was running when a runtime error happened.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Frame {
+    /// `None` for the top-level code of the script.
+    pub(crate) function: Option<String>,
+    /// Where the function was when the error happened, which is the error
+    /// itself for the innermost frame and a call for the others.
+    pub(crate) span: Span,
+}
+
+impl Diagnostic {
+    pub(crate) fn error(message: impl ToString, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            message: message.to_string(),
+            span,
+            notes: vec![],
+            help: None,
+            trace: vec![],
+        }
+    }
+
+    pub(crate) fn with_code(mut self, code: Code) -> Diagnostic {
+        self.code = Some(code);
+        self
+    }
+
+    pub(crate) fn with_note(mut self, message: impl ToString, span: Option<Span>) -> Diagnostic {
+        self.notes.push(Note { message: message.to_string(), span });
+        self
+    }
+
+    pub(crate) fn with_help(mut self, help: impl ToString) -> Diagnostic {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn code(&self) -> Option<Code> {
+        self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Renders the diagnostic like a compiler would, quoting the offending
+    /// line of `source` and underlining the exact span.
+    ///
+    /// `file_name` is only used to tell the user where `source` came from.
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let mut output = match self.code {
+            Some(code) => format!("{}[{code}]: {}\n", self.severity, self.message),
+            None => format!("{}: {}\n", self.severity, self.message),
+        };
+
+        let last_line = std::iter::once(self.span)
+            .chain(self.notes.iter().filter_map(|note| note.span))
+            .map(|span| span.location(source).0)
+            .max()
+            .unwrap_or(1);
+        let gutter = " ".repeat(last_line.to_string().len());
+
+        render_snippet(&mut output, &gutter, file_name, source, self.span);
+        for note in &self.notes {
+            match note.span {
+                Some(span) => {
+                    let _ = writeln!(output, "note: {}", note.message);
+                    render_snippet(&mut output, &gutter, file_name, source, span);
+                }
+                None => {
+                    let _ = writeln!(output, "{gutter} = note: {}", note.message);
+                }
+            }
+        }
+        if let Some(help) = &self.help {
+            let _ = writeln!(output, "{gutter} = help: {help}");
+        }
+        // Errors in top-level code have nothing to add to the snippet
+        if self.trace.len() > 1 {
+            render_trace(&mut output, file_name, source, &self.trace);
+        }
+
+        output
+    }
+
+    /// Serializes the diagnostic as a single line of JSON, for tools.
+    ///
+    /// Spans are byte offsets into `source`, excluding `end`. Lines and
+    /// columns start at 1, and columns count characters.
+    pub fn to_json(&self, file_name: &str, source: &str) -> String {
+        let notes: Vec<String> = self
+            .notes
+            .iter()
+            .map(|note| {
+                let location = match note.span {
+                    Some(span) => span_to_json(span, source),
+                    None => r#""span":null,"line":null,"column":null"#.to_string(),
+                };
+                format!(r#"{{"message":{},{location}}}"#, json_string(&note.message))
+            })
+            .collect();
+        let trace: Vec<String> = self
+            .trace
+            .iter()
+            .map(|frame| {
+                let function = frame.function.as_deref().map_or("null".to_string(), json_string);
+                format!(r#"{{"function":{function},{}}}"#, span_to_json(frame.span, source))
+            })
+            .collect();
+        let code = self.code.map_or("null".to_string(), |code| format!(r#""{code}""#));
+        let help = self.help.as_deref().map_or("null".to_string(), json_string);
+
+        format!(
+            r#"{{"severity":"{}","code":{code},"message":{},"file":{},{},"notes":[{}],"help":{help},"trace":[{}]}}"#,
+            self.severity,
+            json_string(&self.message),
+            json_string(file_name),
+            span_to_json(self.span, source),
+            notes.join(","),
+            trace.join(","),
+        )
+    }
+}
+
+/// Writes the span and where it starts as JSON fields, without braces.
+fn span_to_json(span: Span, source: &str) -> String {
+    let (line, column) = span.location(source);
+    format!(r#""span":{{"start":{},"end":{}}},"line":{line},"column":{column}"#, span.start, span.end)
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Writes one line per frame, collapsing consecutive frames at the same place
+/// like those of deep recursion.
+fn render_trace(output: &mut String, file_name: &str, source: &str, trace: &[Frame]) {
+    let _ = writeln!(output, "stack backtrace:");
+    let mut frames = trace.iter().enumerate().peekable();
+    while let Some((i, frame)) = frames.next() {
+        let (line, column) = frame.span.location(source);
+        let function = frame.function.as_ref().map_or("script".to_string(), |name| format!("{name}()"));
+        let _ = writeln!(output, "{i:>4}: {function} at {file_name}:{line}:{column}");
+
+        let mut repeated = 0;
+        while frames.next_if(|(_, next)| *next == frame).is_some() {
+            repeated += 1;
+        }
+        if repeated > 0 {
+            let _ = writeln!(output, "      [previous frame repeated {repeated} more times]");
+        }
+    }
+}
+
+/// Writes the location of `span`, then the line where it starts with the span
+/// underlined.
+fn render_snippet(output: &mut String, gutter: &str, file_name: &str, source: &str, span: Span) {
+    let (line, column) = span.location(source);
+    let start = span.start.min(source.len());
+    let line_start = source[..start].rfind('\n').map_or(0, |newline| newline + 1);
+    let line_end = source[start..].find('\n').map_or(source.len(), |newline| start + newline);
+    let text = source[line_start..line_end].trim_end_matches('\r');
+
+    // Spans continuing on the next lines are only underlined up to the end of the first
+    let end = span.end.clamp(start, line_start + text.len());
+    let length = source[start..end].chars().count().max(1);
+    // Keeps tabs so the underline lines up with the text above it
+    let indent: String = text
+        .chars()
+        .chain(std::iter::repeat(' '))
+        .take(column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+
+    let _ = writeln!(output, "{gutter}--> {file_name}:{line}:{column}");
+    let _ = writeln!(output, "{gutter} |");
+    let _ = writeln!(output, "{line:>width$} | {text}", width = gutter.len());
+    let _ = writeln!(output, "{gutter} | {indent}{}", "^".repeat(length));
+}
+
+impl Diagnostic {
+    /// Errors of the scanner are only known by their token.
+    pub(crate) fn from_token_error(error: &token::Error, span: Span) -> Diagnostic {
+        match error {
+            token::Error::UnterminatedString => {
+                let quote = Span { start: span.start, end: span.start + 1 };
+                Diagnostic::error(error, quote).with_code(error.code()).with_help("add a closing `\"`")
+            }
+            token::Error::UnexpectedCharacter => Diagnostic::error(error, span).with_code(error.code()),
+        }
+    }
+}
+
+impl From<parser::Error> for Diagnostic {
+    fn from(error: parser::Error) -> Self {
+        let diagnostic = Diagnostic::error(&error.kind, error.span).with_code(error.kind.code());
+        match error.kind {
+            parser::ErrorKind::InvalidAssignmentTarget => {
+                diagnostic.with_help("only variables and fields can be assigned to")
+            }
+            _ => diagnostic,
+        }
+    }
+}
+
+impl From<resolver::Error> for Diagnostic {
+    fn from(error: resolver::Error) -> Self {
+        let diagnostic = Diagnostic::error(&error.kind, error.span).with_code(error.kind.code());
+        match error.previous {
+            Some(previous) => diagnostic.with_note("previously declared here", Some(previous)),
+            None => diagnostic,
+        }
+    }
+}
+
+impl From<vm::Error> for Diagnostic {
+    fn from(error: vm::Error) -> Self {
+        Diagnostic::error(&error.kind, error.span).with_code(error.kind.code())
+    }
+}
+
+impl From<evaluator::Error> for Diagnostic {
+    fn from(error: evaluator::Error) -> Self {
+        let mut diagnostic = Diagnostic::error(&error.kind, error.span).with_code(error.kind.code());
+        diagnostic.trace = error.trace;
+        diagnostic
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_snippet_with_underline() {
+        let source = "var a = 1;\nprint a + \"b\";\n";
+        let diagnostic = Diagnostic::error("Operands must be two numbers or two strings.", Span { start: 19, end: 20 });
+
+        assert_eq!(
+            diagnostic.render("script.lox", source),
+            "\
+error: Operands must be two numbers or two strings.
+ --> script.lox:2:9
+  |
+2 | print a + \"b\";
+  |         ^
+",
+        );
+    }
+
+    #[test]
+    fn underlines_whole_span() {
+        let source = "print missing;";
+        let diagnostic = Diagnostic::error("Undefined variable 'missing'.", Span { start: 6, end: 13 });
+
+        assert!(diagnostic.render("-", source).ends_with("1 | print missing;\n  |       ^^^^^^^\n"));
+    }
+
+    #[test]
+    fn underlines_end_of_source() {
+        let source = "print 1";
+        let diagnostic = Diagnostic::error("Expect ';' after value.", Span { start: 7, end: 7 });
+
+        assert!(diagnostic.render("-", source).ends_with("1 | print 1\n  |        ^\n"));
+    }
+
+    #[test]
+    fn keeps_tabs_in_underline_indentation() {
+        let source = "\tprint -nil;";
+        let diagnostic = Diagnostic::error("Operand must be a number.", Span { start: 7, end: 8 });
+
+        assert!(diagnostic.render("-", source).ends_with("1 | \tprint -nil;\n  | \t      ^\n"));
+    }
+
+    #[test]
+    fn renders_notes_and_help() {
+        let source = "{\n  var a;\n  var a;\n}";
+        let diagnostic = Diagnostic::error("Already a variable with this name in this scope.", Span { start: 17, end: 18 })
+            .with_note("previously declared here", Some(Span { start: 8, end: 9 }))
+            .with_note("scopes can't redeclare names", None)
+            .with_help("rename one of them");
+
+        assert_eq!(
+            diagnostic.render("script.lox", source),
+            "\
+error: Already a variable with this name in this scope.
+ --> script.lox:3:7
+  |
+3 |   var a;
+  |       ^
+note: previously declared here
+ --> script.lox:2:7
+  |
+2 |   var a;
+  |       ^
+  = note: scopes can't redeclare names
+  = help: rename one of them
+",
+        );
+    }
+
+    #[test]
+    fn serializes_to_json() {
+        let source = "{\n  var a;\n  var a;\n}";
+        let diagnostic = Diagnostic::error("Already a variable with this name in this scope.", Span { start: 17, end: 18 })
+            .with_note("previously declared here", Some(Span { start: 8, end: 9 }))
+            .with_note("scopes can't redeclare names", None);
+
+        assert_eq!(
+            diagnostic.to_json("script.lox", source),
+            concat!(
+                r#"{"severity":"error","code":null,"#,
+                r#""message":"Already a variable with this name in this scope.","file":"script.lox","#,
+                r#""span":{"start":17,"end":18},"line":3,"column":7,"#,
+                r#""notes":["#,
+                r#"{"message":"previously declared here","span":{"start":8,"end":9},"line":2,"column":7},"#,
+                r#"{"message":"scopes can't redeclare names","span":null,"line":null,"column":null}"#,
+                r#"],"help":null,"trace":[]}"#,
+            ),
+        );
+    }
+
+    #[test]
+    fn renders_and_serializes_codes() {
+        let source = "print nothing;";
+        let diagnostic = Diagnostic::error("Undefined variable 'nothing'.", Span { start: 6, end: 13 })
+            .with_code(Code::UNDEFINED_VARIABLE);
+
+        assert!(diagnostic.render("-", source).starts_with("error[L0307]: Undefined variable 'nothing'.\n"));
+        assert!(diagnostic.to_json("-", source).contains(r#""code":"L0307""#));
+    }
+
+    #[test]
+    fn renders_stack_traces() {
+        let source = "fun f() {\n  f();\n}\nf();";
+        let mut diagnostic = Diagnostic::error("Stack overflow.", Span { start: 13, end: 14 });
+        let recursion = Frame { function: Some("f".to_string()), span: Span { start: 13, end: 14 } };
+        let script = Frame { function: None, span: Span { start: 20, end: 21 } };
+        diagnostic.trace = vec![recursion.clone(), recursion.clone(), recursion, script];
+
+        assert!(diagnostic.render("script.lox", source).ends_with(
+            "\
+stack backtrace:
+   0: f() at script.lox:2:4
+      [previous frame repeated 2 more times]
+   3: script at script.lox:4:2
+",
+        ));
+        assert!(diagnostic.to_json("script.lox", source).ends_with(concat!(
+            r#""trace":[{"function":"f","span":{"start":13,"end":14},"line":2,"column":4},"#,
+            r#"{"function":"f","span":{"start":13,"end":14},"line":2,"column":4},"#,
+            r#"{"function":"f","span":{"start":13,"end":14},"line":2,"column":4},"#,
+            r#"{"function":null,"span":{"start":20,"end":21},"line":4,"column":2}]}"#,
+        )));
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        let diagnostic = Diagnostic::error("Undefined property 'a\"b'.", Span::default()).with_help("tab\there");
+
+        let json = diagnostic.to_json("C:\\scripts\\new.lox", "");
+
+        assert!(json.contains(r#""message":"Undefined property 'a\"b'.""#));
+        assert!(json.contains(r#""file":"C:\\scripts\\new.lox""#));
+        assert!(json.contains(r#""help":"tab\there""#));
+    }
+
+    #[test]
+    fn points_unterminated_strings_at_opening_quote() {
+        let source = "print \"never\nends";
+        let diagnostic = Diagnostic::from_token_error(&token::Error::UnterminatedString, Span { start: 6, end: 17 });
+
+        assert!(diagnostic.render("-", source).contains("1 | print \"never\n  |       ^\n"));
+    }
+}
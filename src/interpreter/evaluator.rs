@@ -0,0 +1,1405 @@
+mod class;
+mod environment;
+mod function;
+mod value;
+
+pub(crate) use value::Value;
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use class::{Class, Instance};
+use environment::Environment;
+use function::Function;
+
+use super::ast::{Expr, Literal, Stmt, Variable};
+use super::code::Code;
+use super::diagnostic::Frame;
+use super::list::{self, Return};
+use super::map::{self, Key};
+use super::native::{self, Natives};
+use super::symbol::Symbol;
+use super::token::{Keyword, Span, Token, Type};
+
+/// Executes the syntax tree directly.
+pub(crate) struct Evaluator {
+    out: Box<dyn Write>,
+    globals: Rc<RefCell<Environment>>,
+    environment: Rc<RefCell<Environment>>,
+    /// Function calls currently being executed, innermost last.
+    calls: Vec<Call>,
+}
+
+struct Call {
+    function: String,
+    paren: Span,
+}
+
+/// Function calls can't be nested deeper than this.
+///
+/// Each call takes several native stack frames, so running deep recursion
+/// needs a thread with a big stack, like `main` uses.
+pub(crate) const MAX_DEPTH: usize = 1000;
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Error {
+    pub(crate) kind: ErrorKind,
+    pub(crate) span: Span,
+    /// Calls that were running when the error happened, innermost first.
+    ///
+    /// Empty until the error leaves the function where it happened.
+    pub(crate) trace: Vec<Frame>,
+}
+
+impl Error {
+    fn new(kind: ErrorKind, span: Span) -> Error {
+        Error { kind, span, trace: vec![] }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum ErrorKind {
+    NotCallable,
+    WrongArity { expected: usize, got: usize },
+    StackOverflow,
+    OperandMustBeNumber,
+    OperandsMustBeNumbers,
+    OperandsMustBeNumbersOrStrings,
+    UndefinedVariable(String),
+    UndefinedProperty(String),
+    /// Properties were read from something other than an instance.
+    NotAnInstance,
+    /// Fields were set on something other than an instance.
+    FieldsOnNonInstance,
+    SuperclassMustBeClass,
+    /// A native function failed with this message.
+    Native(String),
+    /// Something other than a list or map was indexed.
+    NotIndexable,
+    IndexMustBeInteger,
+    IndexOutOfBounds { index: f64, length: usize },
+    PopFromEmptyList,
+    InvalidKey,
+    UndefinedKey(String),
+    /// A native function returned an object it can't return.
+    InvalidObject,
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::NotCallable => write!(f, "Can only call functions and classes."),
+            ErrorKind::WrongArity { expected, got } => {
+                write!(f, "Expected {expected} arguments but got {got}.")
+            }
+            ErrorKind::StackOverflow => write!(f, "Stack overflow."),
+            ErrorKind::OperandMustBeNumber => write!(f, "Operand must be a number."),
+            ErrorKind::OperandsMustBeNumbers => write!(f, "Operands must be numbers."),
+            ErrorKind::OperandsMustBeNumbersOrStrings => {
+                write!(f, "Operands must be two numbers or two strings.")
+            }
+            ErrorKind::UndefinedVariable(name) => write!(f, "Undefined variable '{name}'."),
+            ErrorKind::UndefinedProperty(name) => write!(f, "Undefined property '{name}'."),
+            ErrorKind::NotAnInstance => write!(f, "Only instances have properties."),
+            ErrorKind::FieldsOnNonInstance => write!(f, "Only instances have fields."),
+            ErrorKind::SuperclassMustBeClass => write!(f, "Superclass must be a class."),
+            ErrorKind::Native(message) => write!(f, "{message}"),
+            ErrorKind::NotIndexable => write!(f, "Only lists and maps can be indexed."),
+            ErrorKind::IndexMustBeInteger => write!(f, "Index must be an integer."),
+            ErrorKind::IndexOutOfBounds { index, length } => {
+                write!(f, "Index {index} is out of bounds for a list of length {length}.")
+            }
+            ErrorKind::PopFromEmptyList => write!(f, "Can't pop from an empty list."),
+            ErrorKind::InvalidKey => write!(f, "Map keys must be strings, numbers or booleans."),
+            ErrorKind::UndefinedKey(key) => write!(f, "Undefined key '{key}'."),
+            ErrorKind::InvalidObject => write!(f, "Native function returned an object it was not given."),
+        }
+    }
+}
+
+impl ErrorKind {
+    pub(crate) fn code(&self) -> Code {
+        match self {
+            ErrorKind::NotCallable => Code::NOT_CALLABLE,
+            ErrorKind::WrongArity { .. } => Code::WRONG_ARITY,
+            ErrorKind::StackOverflow => Code::STACK_OVERFLOW,
+            ErrorKind::OperandMustBeNumber => Code::OPERAND_MUST_BE_NUMBER,
+            ErrorKind::OperandsMustBeNumbers => Code::OPERANDS_MUST_BE_NUMBERS,
+            ErrorKind::OperandsMustBeNumbersOrStrings => Code::OPERANDS_MUST_BE_NUMBERS_OR_STRINGS,
+            ErrorKind::UndefinedVariable(_) => Code::UNDEFINED_VARIABLE,
+            ErrorKind::UndefinedProperty(_) => Code::UNDEFINED_PROPERTY,
+            ErrorKind::NotAnInstance => Code::NOT_AN_INSTANCE,
+            ErrorKind::FieldsOnNonInstance => Code::FIELDS_ON_NON_INSTANCE,
+            ErrorKind::SuperclassMustBeClass => Code::SUPERCLASS_MUST_BE_CLASS,
+            ErrorKind::Native(_) => Code::NATIVE,
+            ErrorKind::NotIndexable => Code::NOT_INDEXABLE,
+            ErrorKind::IndexMustBeInteger => Code::INDEX_MUST_BE_INTEGER,
+            ErrorKind::IndexOutOfBounds { .. } => Code::INDEX_OUT_OF_BOUNDS,
+            ErrorKind::PopFromEmptyList => Code::POP_FROM_EMPTY_LIST,
+            ErrorKind::InvalidKey => Code::INVALID_KEY,
+            ErrorKind::UndefinedKey(_) => Code::UNDEFINED_KEY,
+            ErrorKind::InvalidObject => Code::INVALID_OBJECT,
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Reasons to stop executing statements early.
+enum Unwind {
+    Error(Error),
+    Return(Value),
+    /// Stops the innermost loop.
+    Break,
+    /// Skips to the next iteration of the innermost loop.
+    Continue,
+}
+
+impl From<Error> for Unwind {
+    fn from(error: Error) -> Self {
+        Unwind::Error(error)
+    }
+}
+
+impl Evaluator {
+    /// `print` statements write to `out`.
+    pub(crate) fn new(out: Box<dyn Write>, natives: &Natives) -> Evaluator {
+        let globals: Rc<RefCell<Environment>> = Rc::default();
+        for native in natives.iter() {
+            globals.borrow_mut().define(&Symbol::intern(&native.name), Value::Native(Rc::clone(native)));
+        }
+        Evaluator { out, environment: Rc::clone(&globals), globals, calls: vec![] }
+    }
+
+    pub(crate) fn interpret(&mut self, statements: &[Stmt]) -> Result<()> {
+        for statement in statements {
+            match self.execute(statement) {
+                Ok(()) => (),
+                Err(Unwind::Error(mut error)) => {
+                    if error.trace.is_empty() {
+                        error.trace = self.trace(error.span);
+                    }
+                    return Err(error);
+                }
+                // Stops the script, as there is no function to return from
+                Err(Unwind::Return(_)) => return Ok(()),
+                Err(Unwind::Break | Unwind::Continue) => unreachable!("Resolver checks that loops enclose them"),
+            }
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, statement: &Stmt) -> std::result::Result<(), Unwind> {
+        match statement {
+            Stmt::Expression(expr) => {
+                self.evaluate(expr)?;
+            }
+            Stmt::Print(expr) => {
+                let value = self.evaluate(expr)?;
+                // Nothing sensible to do if the output is gone
+                let _ = writeln!(self.out, "{value}");
+            }
+            Stmt::Var { name, initializer } => {
+                let value = match initializer {
+                    Some(initializer) => self.evaluate(initializer)?,
+                    None => Value::Nil,
+                };
+                self.environment.borrow_mut().define(&name.name, value);
+            }
+            Stmt::Block(statements) => {
+                let environment = Environment::new(Rc::clone(&self.environment));
+                self.execute_block(statements, Rc::new(RefCell::new(environment)))?;
+            }
+            Stmt::If { condition, then_branch, else_branch } => {
+                if self.evaluate(condition)?.is_truthy() {
+                    self.execute(then_branch)?;
+                } else if let Some(else_branch) = else_branch {
+                    self.execute(else_branch)?;
+                }
+            }
+            Stmt::While { condition, body, increment } => {
+                while self.evaluate(condition)?.is_truthy() {
+                    match self.execute(body) {
+                        Ok(()) | Err(Unwind::Continue) => (),
+                        Err(Unwind::Break) => break,
+                        Err(unwind) => return Err(unwind),
+                    }
+                    if let Some(increment) = increment {
+                        self.evaluate(increment)?;
+                    }
+                }
+            }
+            Stmt::Function(declaration) => {
+                let function = Function {
+                    declaration: Rc::clone(declaration),
+                    closure: Rc::clone(&self.environment),
+                    is_initializer: false,
+                };
+                let name = &declaration.name.name;
+                self.environment.borrow_mut().define(name, Value::Function(Rc::new(function)));
+            }
+            Stmt::Class { name, superclass, methods } => {
+                let superclass = match superclass {
+                    Some(superclass) => match self.look_up_variable(superclass)? {
+                        Value::Class(class) => Some(class),
+                        _ => {
+                            let span = superclass.name.span;
+                            return Err(Error::new(ErrorKind::SuperclassMustBeClass, span).into());
+                        }
+                    },
+                    None => None,
+                };
+
+                // Methods of subclasses close over a scope where `super` is defined
+                let enclosing = Rc::clone(&self.environment);
+                if let Some(superclass) = &superclass {
+                    let mut environment = Environment::new(Rc::clone(&enclosing));
+                    environment.define(&Symbol::intern("super"), Value::Class(Rc::clone(superclass)));
+                    self.environment = Rc::new(RefCell::new(environment));
+                }
+
+                let methods = methods
+                    .iter()
+                    .map(|method| {
+                        let function = Function {
+                            declaration: Rc::clone(method),
+                            closure: Rc::clone(&self.environment),
+                            is_initializer: method.name.name == "init",
+                        };
+                        (method.name.name.clone(), Rc::new(function))
+                    })
+                    .collect();
+                let class = Class { name: name.name.clone(), superclass, methods };
+                self.environment = enclosing;
+                self.environment.borrow_mut().define(&name.name, Value::Class(Rc::new(class)));
+            }
+            Stmt::Return { value, .. } => {
+                let value = match value {
+                    Some(value) => self.evaluate(value)?,
+                    None => Value::Nil,
+                };
+                return Err(Unwind::Return(value));
+            }
+            Stmt::Break { .. } => return Err(Unwind::Break),
+            Stmt::Continue { .. } => return Err(Unwind::Continue),
+        }
+
+        Ok(())
+    }
+
+    /// Executes `statements` in `environment`, restoring the current
+    /// environment afterwards even if an error occurs.
+    fn execute_block(
+        &mut self,
+        statements: &[Stmt],
+        environment: Rc<RefCell<Environment>>,
+    ) -> std::result::Result<(), Unwind> {
+        let previous = std::mem::replace(&mut self.environment, environment);
+        let result = statements.iter().try_for_each(|statement| self.execute(statement));
+        self.environment = previous;
+
+        result
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value> {
+        match expr {
+            Expr::Literal(literal) => Ok(match literal {
+                Literal::Nil => Value::Nil,
+                Literal::Bool(b) => Value::Bool(*b),
+                Literal::Number(n) => Value::Number(*n),
+                Literal::String(s) => Value::String(s.clone()),
+            }),
+            Expr::Grouping(expr) => self.evaluate(expr),
+            Expr::Unary { operator, right } => {
+                let right = self.evaluate(right)?;
+                match (&operator.r#type, right) {
+                    (Type::Bang, right) => Ok(Value::Bool(!right.is_truthy())),
+                    (Type::Minus, Value::Number(n)) => Ok(Value::Number(-n)),
+                    _ => Err(Error::new(ErrorKind::OperandMustBeNumber, operator.span)),
+                }
+            }
+            Expr::Binary { left, operator, right } => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                binary(left, operator, right)
+            }
+            Expr::Logical { left, operator, right } => {
+                let left = self.evaluate(left)?;
+                // Returns the operand that decided the result, not a `bool`
+                let is_decided = match operator.r#type {
+                    Type::Keyword(Keyword::Or) => left.is_truthy(),
+                    Type::QuestionQuestion => left != Value::Nil,
+                    _ => !left.is_truthy(),
+                };
+                if is_decided {
+                    Ok(left)
+                } else {
+                    self.evaluate(right)
+                }
+            }
+            Expr::Conditional { condition, then_branch, else_branch } => {
+                if self.evaluate(condition)?.is_truthy() {
+                    self.evaluate(then_branch)
+                } else {
+                    self.evaluate(else_branch)
+                }
+            }
+            Expr::Variable(variable) => self.look_up_variable(variable),
+            Expr::Assign { variable, value } => {
+                let value = self.evaluate(value)?;
+                let name = &variable.name;
+                let is_assigned = match variable.depth.get() {
+                    Some(depth) => self.environment.borrow_mut().assign_at(depth, &name.name, value.clone()),
+                    None => self.globals.borrow_mut().assign(&name.name, value.clone()),
+                };
+                if is_assigned {
+                    Ok(value)
+                } else {
+                    Err(Error::new(ErrorKind::UndefinedVariable(name.name.to_string()), name.span))
+                }
+            }
+            Expr::Call { callee, paren, arguments } => {
+                let callee = self.evaluate(callee)?;
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.evaluate(argument))
+                    .collect::<Result<Vec<_>>>()?;
+                self.call(callee, arguments, *paren)
+            }
+            Expr::Get { object, name } => match self.evaluate(object)? {
+                Value::Instance(instance) => Instance::get(&instance, &name.name).ok_or_else(|| {
+                    Error::new(ErrorKind::UndefinedProperty(name.name.to_string()), name.span)
+                }),
+                Value::List(list) => match list::Method::find(&name.name) {
+                    Some(method) => Ok(Value::ListMethod(list, method)),
+                    None => Err(Error::new(ErrorKind::UndefinedProperty(name.name.to_string()), name.span)),
+                },
+                Value::Map(map) => match map::Method::find(&name.name) {
+                    Some(method) => Ok(Value::MapMethod(map, method)),
+                    None => Err(Error::new(ErrorKind::UndefinedProperty(name.name.to_string()), name.span)),
+                },
+                _ => Err(Error::new(ErrorKind::NotAnInstance, name.span)),
+            },
+            Expr::Set { object, name, value } => {
+                let Value::Instance(instance) = self.evaluate(object)? else {
+                    return Err(Error::new(ErrorKind::FieldsOnNonInstance, name.span));
+                };
+                let value = self.evaluate(value)?;
+                instance.borrow_mut().set(&name.name, value.clone());
+                Ok(value)
+            }
+            Expr::List(elements) => {
+                let elements = elements.iter().map(|element| self.evaluate(element)).collect::<Result<_>>()?;
+                Ok(Value::List(Rc::new(RefCell::new(elements))))
+            }
+            Expr::Map { brace, entries } => {
+                let mut map = map::Map::default();
+                for (key, value) in entries {
+                    let key = self.evaluate(key)?;
+                    let value = self.evaluate(value)?;
+                    map.insert(self::key(&key).map_err(|kind| Error::new(kind, *brace))?, value);
+                }
+                Ok(Value::Map(Rc::new(RefCell::new(map))))
+            }
+            Expr::Index { object, bracket, index } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                match object {
+                    Value::List(list) => {
+                        let list = list.borrow();
+                        let index =
+                            list::index(number(&index), list.len()).map_err(|kind| Error::new(kind, *bracket))?;
+                        Ok(list[index].clone())
+                    }
+                    Value::Map(map) => {
+                        let key = key(&index).map_err(|kind| Error::new(kind, *bracket))?;
+                        let value = map.borrow().get(&key).cloned();
+                        value.ok_or_else(|| Error::new(ErrorKind::UndefinedKey(index.to_string()), *bracket))
+                    }
+                    _ => Err(Error::new(ErrorKind::NotIndexable, *bracket)),
+                }
+            }
+            Expr::SetIndex { object, bracket, index, value } => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                let value = self.evaluate(value)?;
+                match object {
+                    Value::List(list) => {
+                        let mut list = list.borrow_mut();
+                        let index =
+                            list::index(number(&index), list.len()).map_err(|kind| Error::new(kind, *bracket))?;
+                        list[index] = value.clone();
+                    }
+                    Value::Map(map) => {
+                        let key = key(&index).map_err(|kind| Error::new(kind, *bracket))?;
+                        map.borrow_mut().insert(key, value.clone());
+                    }
+                    _ => return Err(Error::new(ErrorKind::NotIndexable, *bracket)),
+                }
+                Ok(value)
+            }
+            Expr::Function(declaration) => Ok(Value::Function(Rc::new(Function {
+                declaration: Rc::clone(declaration),
+                closure: Rc::clone(&self.environment),
+                is_initializer: false,
+            }))),
+            Expr::This(keyword) => self.look_up_variable(keyword),
+            Expr::Super { keyword, method } => {
+                // `this` is bound in the scope just inside the one defining `super`
+                let depth = keyword.depth.get().expect("Resolver binds `super` to a local scope");
+                let environment = self.environment.borrow();
+                let superclass = environment.get_at(depth, &Symbol::intern("super"));
+                let this = environment.get_at(depth - 1, &Symbol::intern("this"));
+                let (Some(Value::Class(superclass)), Some(this)) = (superclass, this) else {
+                    let kind = ErrorKind::UndefinedVariable("super".to_string());
+                    return Err(Error::new(kind, keyword.name.span));
+                };
+                let Some(method) = superclass.find_method(&method.name) else {
+                    let kind = ErrorKind::UndefinedProperty(method.name.to_string());
+                    return Err(Error::new(kind, method.span));
+                };
+
+                Ok(Value::Function(Rc::new(method.bind(this))))
+            }
+        }
+    }
+
+    fn look_up_variable(&self, variable: &Variable) -> Result<Value> {
+        let name = &variable.name;
+        let value = match variable.depth.get() {
+            Some(depth) => self.environment.borrow().get_at(depth, &name.name),
+            None => self.globals.borrow().get(&name.name),
+        };
+
+        value.ok_or_else(|| Error::new(ErrorKind::UndefinedVariable(name.name.to_string()), name.span))
+    }
+
+    /// `paren` is where errors of the call itself are reported.
+    fn call(&mut self, callee: Value, arguments: Vec<Value>, paren: Span) -> Result<Value> {
+        let arity = match &callee {
+            Value::Function(function) => function.arity(),
+            Value::Native(native) => native.arity,
+            Value::Class(class) => class.arity(),
+            Value::ListMethod(_, method) => method.arity(),
+            Value::MapMethod(_, method) => method.arity(),
+            _ => return Err(Error::new(ErrorKind::NotCallable, paren)),
+        };
+        if arguments.len() != arity {
+            let kind = ErrorKind::WrongArity { expected: arity, got: arguments.len() };
+            return Err(Error::new(kind, paren));
+        }
+        if self.calls.len() >= MAX_DEPTH {
+            return Err(Error::new(ErrorKind::StackOverflow, paren));
+        }
+
+        let function = match &callee {
+            Value::Function(function) => function.declaration.name.name.to_string(),
+            Value::Native(native) => native.name.clone(),
+            Value::Class(class) => class.name.to_string(),
+            Value::ListMethod(_, method) => method.name().to_string(),
+            Value::MapMethod(_, method) => method.name().to_string(),
+            _ => unreachable!("Checked that the callee is callable"),
+        };
+        self.calls.push(Call { function, paren });
+        let mut result = match callee {
+            Value::Function(function) => self.call_function(&function, arguments),
+            Value::Native(native) => {
+                let arguments: Vec<native::Value> = arguments.into_iter().map(Into::into).collect();
+                (native.body)(&arguments)
+                    .map_err(ErrorKind::Native)
+                    .and_then(Value::try_from)
+                    .map_err(|kind| Error::new(kind, paren))
+            }
+            Value::Class(class) => self.instantiate(class, arguments),
+            Value::ListMethod(list, method @ (list::Method::Map | list::Method::Filter)) => {
+                let function = arguments.into_iter().next().expect("Checked the arity");
+                self.map_list(&list, method, function, paren)
+            }
+            Value::ListMethod(list, method) => {
+                let result = method.call(&mut list.borrow_mut(), &arguments, number);
+                result.map_err(|kind| Error::new(kind, paren)).map(|result| match result {
+                    Return::Nil => Value::Nil,
+                    Return::Number(n) => Value::Number(n),
+                    Return::Element(element) => element,
+                    Return::List(elements) => Value::List(Rc::new(RefCell::new(elements))),
+                })
+            }
+            Value::MapMethod(map, method) => self.call_map_method(&map, method, arguments.first(), paren),
+            _ => unreachable!("Checked that the callee is callable"),
+        };
+        // The innermost call that fails knows the whole trace
+        if let Err(error) = &mut result {
+            if error.trace.is_empty() {
+                error.trace = self.trace(error.span);
+            }
+        }
+        self.calls.pop();
+
+        result
+    }
+
+    /// Runs `map` or `filter` on `list`, calling `function` on each element.
+    fn map_list(&mut self, list: &value::List, method: list::Method, function: Value, paren: Span) -> Result<Value> {
+        let mut results = vec![];
+        // The function may change the list, so elements are read as they are reached
+        for index in 0.. {
+            let element = list.borrow().get(index).cloned();
+            let Some(element) = element else {
+                break;
+            };
+            let result = self.call(function.clone(), vec![element.clone()], paren)?;
+            match method {
+                list::Method::Filter if result.is_truthy() => results.push(element),
+                list::Method::Filter => {}
+                _ => results.push(result),
+            }
+        }
+        Ok(Value::List(Rc::new(RefCell::new(results))))
+    }
+
+    /// Runs `method` on `map`, with the `argument` of those that take one.
+    fn call_map_method(
+        &self,
+        map: &value::Map,
+        method: map::Method,
+        argument: Option<&Value>,
+        paren: Span,
+    ) -> Result<Value> {
+        let key = argument.map(key).transpose().map_err(|kind| Error::new(kind, paren))?;
+        let Some(result) = method.call(&mut map.borrow_mut(), key.as_ref()) else {
+            let key = argument.expect("Only removing a key can fail").to_string();
+            return Err(Error::new(ErrorKind::UndefinedKey(key), paren));
+        };
+        let list = |elements| Value::List(Rc::new(RefCell::new(elements)));
+        Ok(match result {
+            map::Return::Bool(b) => Value::Bool(b),
+            map::Return::Number(n) => Value::Number(n),
+            map::Return::Value(value) => value,
+            map::Return::Keys(keys) => list(keys.into_iter().map(Value::from).collect()),
+            map::Return::Values(values) => list(values),
+        })
+    }
+
+    /// `span` is where the innermost call was when it stopped.
+    fn trace(&self, span: Span) -> Vec<Frame> {
+        let functions = self.calls.iter().rev().map(|call| Some(call.function.clone())).chain([None]);
+        let spans = std::iter::once(span).chain(self.calls.iter().rev().map(|call| call.paren));
+        functions.zip(spans).map(|(function, span)| Frame { function, span }).collect()
+    }
+
+    fn instantiate(&mut self, class: Rc<Class>, arguments: Vec<Value>) -> Result<Value> {
+        let initializer = class.find_method(&Symbol::intern("init"));
+        let instance = Value::Instance(Rc::new(RefCell::new(Instance::new(class))));
+        if let Some(initializer) = initializer {
+            self.call_function(&initializer.bind(instance.clone()), arguments)?;
+        }
+
+        Ok(instance)
+    }
+
+    fn call_function(&mut self, function: &Function, arguments: Vec<Value>) -> Result<Value> {
+        let mut environment = Environment::new(Rc::clone(&function.closure));
+        for (param, argument) in function.declaration.params.iter().zip(arguments) {
+            environment.define(&param.name, argument);
+        }
+
+        let result = match self.execute_block(&function.declaration.body, Rc::new(RefCell::new(environment))) {
+            Ok(()) => Value::Nil,
+            Err(Unwind::Return(value)) => value,
+            Err(Unwind::Error(error)) => return Err(error),
+            Err(Unwind::Break | Unwind::Continue) => unreachable!("Resolver checks that loops enclose them"),
+        };
+
+        if function.is_initializer {
+            let this = function.closure.borrow().get_at(0, &Symbol::intern("this"));
+            Ok(this.expect("Initializers are bound to an instance"))
+        } else {
+            Ok(result)
+        }
+    }
+}
+
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => Some(*n),
+        _ => None,
+    }
+}
+
+fn key(value: &Value) -> std::result::Result<Key<Symbol>, ErrorKind> {
+    match value {
+        Value::String(s) => Ok(Key::String(s.clone())),
+        Value::Number(n) => Ok(Key::number(*n)),
+        Value::Bool(b) => Ok(Key::Bool(*b)),
+        _ => Err(ErrorKind::InvalidKey),
+    }
+}
+
+fn binary(left: Value, operator: &Token, right: Value) -> Result<Value> {
+    use Value::{Bool, Number};
+
+    let value = match (left, &operator.r#type, right) {
+        (left, Type::EqualEqual, right) => Bool(left == right),
+        (left, Type::BangEqual, right) => Bool(left != right),
+        (Number(a), Type::Plus, Number(b)) => Number(a + b),
+        (Value::String(a), Type::Plus, Value::String(b)) => Value::String(Symbol::intern(&format!("{a}{b}"))),
+        (_, Type::Plus, _) => {
+            return Err(Error::new(ErrorKind::OperandsMustBeNumbersOrStrings, operator.span));
+        }
+        (Number(a), Type::Minus, Number(b)) => Number(a - b),
+        (Number(a), Type::Star, Number(b)) => Number(a * b),
+        (Number(a), Type::Slash, Number(b)) => Number(a / b),
+        (Number(a), Type::Greater, Number(b)) => Bool(a > b),
+        (Number(a), Type::GreaterEqual, Number(b)) => Bool(a >= b),
+        (Number(a), Type::Less, Number(b)) => Bool(a < b),
+        (Number(a), Type::LessEqual, Number(b)) => Bool(a <= b),
+        _ => return Err(Error::new(ErrorKind::OperandsMustBeNumbers, operator.span)),
+    };
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::test_support::{run, run_err};
+
+    mod expressions {
+        use super::*;
+
+        #[test]
+        fn evaluates_arithmetic() {
+            assert_eq!(run("print 1 + 2 * 3 - 4 / 2;"), "5\n");
+            assert_eq!(run("print (1 + 2) * 3;"), "9\n");
+            assert_eq!(run("print 0.5 + 1;"), "1.5\n");
+            assert_eq!(run("print -(3);"), "-3\n");
+            assert_eq!(run("print 3000000000 + 1;"), "3000000001\n");
+        }
+
+        #[test]
+        fn concatenates_strings() {
+            assert_eq!(run(r#"print "foo" + "bar";"#), "foobar\n");
+        }
+
+        #[test]
+        fn compares_values() {
+            assert_eq!(run("print 1 < 2;"), "true\n");
+            assert_eq!(run("print 2 <= 1;"), "false\n");
+            assert_eq!(run(r#"print "a" == "a";"#), "true\n");
+            assert_eq!(run(r#"print nil == false;"#), "false\n");
+            assert_eq!(run("print !nil;"), "true\n");
+        }
+
+        #[test]
+        fn evaluates_conditionals_and_coalescing() {
+            assert_eq!(run("print 1 < 2 ? \"yes\" : \"no\";"), "yes\n");
+            assert_eq!(run("print nil ? 1 : false ? 2 : 3;"), "3\n");
+            assert_eq!(run("print nil ?? \"default\"; print false ?? 1; print nil ?? nil;"), "default\nfalse\nnil\n");
+            assert_eq!(run("var a; print true ? a = 1 : nil; print a;"), "1\n1\n");
+            // The unused operands would fail
+            assert_eq!(run("print true ? 1 : -nil; print 0 ?? -nil;"), "1\n0\n");
+        }
+
+        #[test]
+        fn reports_invalid_operands() {
+            assert_eq!(run_err("print -true;"), "1:7: Operand must be a number.");
+            assert_eq!(run_err("print 1 < \"2\";"), "1:9: Operands must be numbers.");
+            assert_eq!(
+                run_err("print 1 +\n nil;"),
+                "1:9: Operands must be two numbers or two strings.",
+            );
+        }
+    }
+
+    mod variables {
+        use super::*;
+
+        #[test]
+        fn defines_and_assigns() {
+            let code = "
+                var a = 1;
+                var b;
+                print b;
+                b = a = 2;
+                print a + b;
+            ";
+
+            assert_eq!(run(code), "nil\n4\n");
+        }
+
+        #[test]
+        fn shadows_in_blocks() {
+            let code = "
+                var a = \"outer\";
+                {
+                    var a = \"inner\";
+                    print a;
+                }
+                print a;
+            ";
+
+            assert_eq!(run(code), "inner\nouter\n");
+        }
+
+        #[test]
+        fn reports_undefined_variables() {
+            assert_eq!(run_err("print a;"), "1:7: Undefined variable 'a'.");
+            assert_eq!(run_err("\na = 1;"), "2:1: Undefined variable 'a'.");
+        }
+    }
+
+    mod control_flow {
+        use super::*;
+
+        #[test]
+        fn executes_if_else() {
+            let code = "
+                if (1 < 2) print \"then\"; else print \"else\";
+                if (nil) print \"then\"; else print \"else\";
+                if (false) print \"skipped\";
+            ";
+
+            assert_eq!(run(code), "then\nelse\n");
+        }
+
+        #[test]
+        fn binds_dangling_else_to_nearest_if() {
+            assert_eq!(run("if (true) if (false) print 1; else print 2;"), "2\n");
+        }
+
+        #[test]
+        fn executes_while() {
+            let code = "
+                var i = 0;
+                while (i < 3) {
+                    print i;
+                    i = i + 1;
+                }
+            ";
+
+            assert_eq!(run(code), "0\n1\n2\n");
+        }
+
+        #[test]
+        fn executes_for() {
+            let code = "
+                for (var i = 0; i < 3; i = i + 1) print i;
+
+                var a = 0;
+                for (; a < 2;) a = a + 1;
+                print a;
+            ";
+
+            assert_eq!(run(code), "0\n1\n2\n2\n");
+        }
+
+        #[test]
+        fn breaks_and_continues_innermost_loop() {
+            let code = "
+                for (var i = 0; i < 4; i = i + 1) {
+                    if (i == 1) continue;
+                    var j = 0;
+                    while (true) {
+                        if (j == i) break;
+                        j = j + 1;
+                    }
+                    print j;
+                    if (i == 2) break;
+                }
+            ";
+
+            assert_eq!(run(code), "0\n2\n");
+        }
+
+        #[test]
+        fn scopes_for_variable_to_the_loop() {
+            assert_eq!(run_err("for (var i = 0; i < 1; i = i + 1) {} print i;"), "1:44: Undefined variable 'i'.");
+        }
+
+        #[test]
+        fn returns_deciding_operand_of_logical_operators() {
+            let code = r#"
+                print "left" or "right";
+                print nil or "right";
+                print false and "right";
+                print 1 and 2;
+                print nil or false;
+            "#;
+
+            assert_eq!(run(code), "left\nright\nfalse\n2\nfalse\n");
+        }
+
+        #[test]
+        fn short_circuits_logical_operators() {
+            let code = "
+                var a = 0;
+                true or (a = 1);
+                false and (a = 2);
+                print a;
+                nil or (a = 3);
+                print a;
+            ";
+
+            assert_eq!(run(code), "0\n3\n");
+        }
+    }
+
+    mod functions {
+        use super::*;
+
+        #[test]
+        fn calls_functions() {
+            let code = "
+                fun add(a, b, c) {
+                    print a + b + c;
+                }
+                add(1, 2, 3);
+                print add;
+            ";
+
+            assert_eq!(run(code), "6\n<fn add>\n");
+        }
+
+        #[test]
+        fn returns_values() {
+            let code = "
+                fun fib(n) {
+                    if (n < 2) return n;
+                    return fib(n - 2) + fib(n - 1);
+                }
+                print fib(10);
+
+                fun nothing() { return; }
+                print nothing();
+
+                fun implicit() {}
+                print implicit();
+            ";
+
+            assert_eq!(run(code), "55\nnil\nnil\n");
+        }
+
+        #[test]
+        fn returns_from_inside_loops() {
+            let code = "
+                fun first_square_over(limit) {
+                    for (var i = 0;; i = i + 1) {
+                        while (true) {
+                            if (i * i > limit) return i;
+                            i = i + 1;
+                        }
+                    }
+                }
+                print first_square_over(10);
+            ";
+
+            assert_eq!(run(code), "4\n");
+        }
+
+        #[test]
+        fn closes_over_environment() {
+            let code = "
+                fun makeCounter() {
+                    var i = 0;
+                    fun count() {
+                        i = i + 1;
+                        return i;
+                    }
+                    return count;
+                }
+
+                var a = makeCounter();
+                var b = makeCounter();
+                print a();
+                print a();
+                print b();
+            ";
+
+            assert_eq!(run(code), "1\n2\n1\n");
+        }
+
+        #[test]
+        fn binds_closures_to_the_variables_in_scope_at_declaration() {
+            let code = "
+                var a = \"global\";
+                {
+                    fun showA() {
+                        print a;
+                    }
+                    showA();
+                    var a = \"block\";
+                    showA();
+                }
+            ";
+
+            assert_eq!(run(code), "global\nglobal\n");
+        }
+
+        #[test]
+        fn creates_closures_from_lambdas() {
+            let code = "
+                fun makeCounter() {
+                    var i = 0;
+                    return () => i = i + 1;
+                }
+                var count = makeCounter();
+                count();
+                print count();
+                var compose = (f, g) => (x) => f(g(x));
+                print compose((x) => x * 2, fun (x) { return x + 1; })(3);
+                fun (message) { print message; }(\"called\");
+                print () => nil;
+            ";
+
+            assert_eq!(run(code), "2\n8\ncalled\n<fn lambda>\n");
+        }
+
+        #[test]
+        fn reports_resolution_errors_before_running() {
+            let code = "
+                print \"not printed\";
+                fun f() {
+                    var a = 1;
+                    var a = 2;
+                }
+                return;
+            ";
+
+            assert_eq!(
+                run_err(code),
+                "5:25: Already a variable with this name in this scope.\n\
+                 7:17: Can't return from top-level code.",
+            );
+        }
+
+        #[test]
+        fn passes_functions_as_callbacks() {
+            let code = "
+                fun twice(f, x) { return f(f(x)); }
+                fun inc(x) { return x + 1; }
+                print twice(inc, 1);
+            ";
+
+            assert_eq!(run(code), "3\n");
+        }
+
+        #[test]
+        fn calls_clock() {
+            assert_eq!(run("var now = clock(); print now > 0 and now == now;"), "true\n");
+            assert_eq!(run("print clock;"), "<native fn>\n");
+            assert_eq!(run_err("clock(1);"), "1:8: Expected 0 arguments but got 1.");
+        }
+
+        #[test]
+        fn reports_calls_to_non_callables() {
+            assert_eq!(run_err("\"not a function\"();"), "1:18: Can only call functions and classes.");
+        }
+
+        #[test]
+        fn reports_errors_at_closing_paren() {
+            let code = "
+                fun f(a, b) {}
+                f(1,
+                  2,
+                  3
+                );
+            ";
+
+            assert_eq!(run_err(code), "6:17: Expected 2 arguments but got 3.");
+        }
+
+        #[test]
+        fn reports_stack_overflow() {
+            let code = "
+                fun forever() { forever(); }
+                forever();
+            ";
+
+            assert_eq!(run_err(code), "2:41: Stack overflow.");
+        }
+    }
+
+    mod classes {
+        use super::*;
+
+        #[test]
+        fn prints_classes_and_instances() {
+            let code = "
+                class Bagel {}
+                print Bagel;
+                print Bagel();
+            ";
+
+            assert_eq!(run(code), "Bagel\nBagel instance\n");
+        }
+
+        #[test]
+        fn gets_and_sets_fields() {
+            let code = "
+                class Box {}
+                var box = Box();
+                box.content = \"bagel\";
+                print box.content;
+                print box.size = 3;
+            ";
+
+            assert_eq!(run(code), "bagel\n3\n");
+        }
+
+        #[test]
+        fn binds_this_to_receiver() {
+            let code = "
+                class Person {
+                    greet() {
+                        print \"Hi, \" + this.name;
+                    }
+                }
+                var jane = Person();
+                jane.name = \"Jane\";
+                var greet = jane.greet;
+                jane.name = \"Jane Doe\";
+                greet();
+            ";
+
+            assert_eq!(run(code), "Hi, Jane Doe\n");
+        }
+
+        #[test]
+        fn closes_methods_over_this() {
+            let code = "
+                class Counter {
+                    init() { this.count = 0; }
+                    incrementer() {
+                        fun increment() {
+                            this.count = this.count + 1;
+                            return this.count;
+                        }
+                        return increment;
+                    }
+                }
+                var increment = Counter().incrementer();
+                increment();
+                print increment();
+            ";
+
+            assert_eq!(run(code), "2\n");
+        }
+
+        #[test]
+        fn prefers_fields_over_methods() {
+            let code = "
+                class Thing {
+                    name() { return \"method\"; }
+                }
+                var thing = Thing();
+                fun field() { return \"field\"; }
+                thing.name = field;
+                print thing.name();
+            ";
+
+            assert_eq!(run(code), "field\n");
+        }
+
+        #[test]
+        fn runs_initializers() {
+            let code = "
+                class Point {
+                    init(x, y) {
+                        this.x = x;
+                        this.y = y;
+                    }
+                }
+                var point = Point(1, 2);
+                print point.x + point.y;
+            ";
+
+            assert_eq!(run(code), "3\n");
+        }
+
+        #[test]
+        fn returns_this_from_initializers() {
+            let code = "
+                class Foo {
+                    init() {
+                        this.calls = 1;
+                        return;
+                    }
+                }
+                var foo = Foo();
+                print foo.init() == foo;
+                print foo.init().calls;
+            ";
+
+            assert_eq!(run(code), "true\n1\n");
+        }
+
+        #[test]
+        fn checks_initializer_arity() {
+            let code = "
+                class Point {
+                    init(x, y) {}
+                }
+                Point(1);
+            ";
+
+            assert_eq!(run_err(code), "5:24: Expected 2 arguments but got 1.");
+            assert_eq!(run_err("class Empty {}\nEmpty(1);"), "2:8: Expected 0 arguments but got 1.");
+        }
+
+        #[test]
+        fn reports_property_errors() {
+            assert_eq!(run_err("class A {}\nprint A().missing;"), "2:11: Undefined property 'missing'.");
+            assert_eq!(run_err("print true.field;"), "1:12: Only instances have properties.");
+            assert_eq!(run_err("var a = \"s\";\na.field = 1;"), "2:3: Only instances have fields.");
+        }
+    }
+
+    mod lists {
+        use super::*;
+
+        #[test]
+        fn indexes_and_assigns_elements() {
+            let code = "
+                var xs = [1, \"two\", [3]];
+                print xs;
+                print xs[1];
+                xs[2][0] = xs[0] = 4;
+                print xs;
+                print [];
+            ";
+
+            assert_eq!(run(code), "[1, \"two\", [3]]\ntwo\n[4, \"two\", [4]]\n[]\n");
+        }
+
+        #[test]
+        fn runs_methods() {
+            let code = "
+                var xs = [1, 2];
+                xs.push(3);
+                xs.insert(0, 0);
+                print xs.remove(1) + xs.pop();
+                print xs;
+                print xs.len();
+                var slice = xs.slice(0, 1);
+                slice.push(5);
+                print slice;
+                print xs;
+                var push = xs.push;
+                push(6);
+                print xs;
+            ";
+
+            assert_eq!(run(code), "4\n[0, 2]\n2\n[0, 5]\n[0, 2]\n[0, 2, 6]\n");
+        }
+
+        #[test]
+        fn maps_and_filters() {
+            let code = "
+                var xs = [1, 2, 3];
+                print xs.map((x) => x * 2).filter((x) => x > 2);
+                print xs.filter((x) => x != 2 and nil);
+                fun grow(x) { if (x < 3) xs.push(x + 10); return x; }
+                print xs.map(grow);
+            ";
+
+            assert_eq!(run(code), "[4, 6]\n[]\n[1, 2, 3, 11, 12]\n");
+            assert_eq!(run_err("[1].map((x) => -x, 2);"), "1:21: Expected 1 arguments but got 2.");
+        }
+
+        #[test]
+        fn compares_lists_by_identity() {
+            assert_eq!(run("var xs = [1]; var ys = xs; print xs == ys; print xs == [1];"), "true\nfalse\n");
+        }
+
+        #[test]
+        fn prints_lists_containing_themselves() {
+            assert_eq!(run("var xs = [1]; xs.push(xs); print xs; print [xs];"), "[1, [...]]\n[[1, [...]]]\n");
+        }
+
+        #[test]
+        fn reports_index_errors() {
+            assert_eq!(
+                run_err("var xs = [1];\nprint xs[1];"),
+                "2:11: Index 1 is out of bounds for a list of length 1.",
+            );
+            assert_eq!(run_err("[1][-1] = 2;"), "1:7: Index -1 is out of bounds for a list of length 1.");
+            assert_eq!(run_err("print [1][0.5];"), "1:14: Index must be an integer.");
+            assert_eq!(run_err("print [1][nil];"), "1:14: Index must be an integer.");
+            assert_eq!(run_err("print \"abc\"[0];"), "1:14: Only lists and maps can be indexed.");
+            assert_eq!(run_err("[1].insert(2, 0);"), "1:16: Index 2 is out of bounds for a list of length 1.");
+            assert_eq!(run_err("[].pop();"), "1:8: Can't pop from an empty list.");
+            assert_eq!(run_err("[].len(1);"), "1:9: Expected 0 arguments but got 1.");
+            assert_eq!(run_err("[].size();"), "1:4: Undefined property 'size'.");
+        }
+    }
+
+    mod maps {
+        use super::*;
+
+        #[test]
+        fn indexes_and_assigns_keys() {
+            let code = "
+                var m = {\"b\": 1, 2: [true], false: {}};
+                print m;
+                print m[\"b\"] + m[2.0].len();
+                m[\"a\"] = 3;
+                m[\"b\"] = m[false][-0] = 4;
+                print m;
+                print m[false][0];
+                print {};
+            ";
+
+            assert_eq!(
+                run(code),
+                "{\"b\": 1, 2: [true], false: {}}\n2\n{\"b\": 4, 2: [true], false: {0: 4}, \"a\": 3}\n4\n{}\n",
+            );
+        }
+
+        #[test]
+        fn runs_methods() {
+            let code = "
+                var m = {\"a\": 1, \"b\": 2, \"c\": 3};
+                print m.remove(\"b\");
+                m[\"b\"] = 4;
+                print m.keys();
+                print m.values();
+                print m.has(\"a\");
+                print m.has(1);
+                print m.len();
+            ";
+
+            assert_eq!(run(code), "2\n[\"a\", \"c\", \"b\"]\n[1, 3, 4]\ntrue\nfalse\n3\n");
+        }
+
+        #[test]
+        fn compares_maps_by_identity() {
+            assert_eq!(run("var m = {}; var n = m; print m == n; print m == {};"), "true\nfalse\n");
+        }
+
+        #[test]
+        fn prints_maps_containing_themselves() {
+            assert_eq!(run("var m = {}; m[1] = [m]; print m;"), "{1: [{...}]}\n");
+        }
+
+        #[test]
+        fn reports_key_errors() {
+            assert_eq!(run_err("var m = {1: 2};\nprint m[2];"), "2:10: Undefined key '2'.");
+            assert_eq!(run_err("print {}[nil];"), "1:13: Map keys must be strings, numbers or booleans.");
+            assert_eq!(run_err("var m = {[]: 1};"), "1:9: Map keys must be strings, numbers or booleans.");
+            assert_eq!(run_err("print {}.remove(\"a\");"), "1:20: Undefined key 'a'.");
+            assert_eq!(run_err("print {}.has({});"), "1:16: Map keys must be strings, numbers or booleans.");
+            assert_eq!(run_err("print {}.keys(1);"), "1:16: Expected 0 arguments but got 1.");
+            assert_eq!(run_err("print {}.get(1);"), "1:10: Undefined property 'get'.");
+        }
+    }
+
+    mod inheritance {
+        use super::*;
+
+        #[test]
+        fn inherits_methods() {
+            let code = "
+                class Doughnut {
+                    cook() { print \"Fry until golden brown.\"; }
+                }
+                class BostonCream < Doughnut {}
+                BostonCream().cook();
+            ";
+
+            assert_eq!(run(code), "Fry until golden brown.\n");
+        }
+
+        #[test]
+        fn inherits_initializers() {
+            let code = "
+                class Base {
+                    init(value) { this.value = value; }
+                }
+                class Derived < Base {}
+                print Derived(3).value;
+            ";
+
+            assert_eq!(run(code), "3\n");
+        }
+
+        #[test]
+        fn calls_superclass_methods() {
+            let code = "
+                class Doughnut {
+                    cook() { print \"Fry until golden brown.\"; }
+                }
+                class BostonCream < Doughnut {
+                    cook() {
+                        super.cook();
+                        print \"Pipe full of custard and coat with chocolate.\";
+                    }
+                }
+                BostonCream().cook();
+            ";
+
+            assert_eq!(run(code), "Fry until golden brown.\nPipe full of custard and coat with chocolate.\n");
+        }
+
+        #[test]
+        fn binds_super_to_the_declaring_class() {
+            let code = "
+                class A {
+                    method() { print \"A method\"; }
+                }
+                class B < A {
+                    method() { print \"B method\"; }
+                    test() { super.method(); }
+                }
+                class C < B {}
+                C().test();
+            ";
+
+            assert_eq!(run(code), "A method\n");
+        }
+
+        #[test]
+        fn binds_this_in_superclass_methods() {
+            let code = "
+                class Base {
+                    describe() { return \"I am \" + this.name(); }
+                    name() { return \"base\"; }
+                }
+                class Derived < Base {
+                    name() { return \"derived\"; }
+                    describe() { return super.describe() + \"!\"; }
+                }
+                print Derived().describe();
+            ";
+
+            assert_eq!(run(code), "I am derived!\n");
+        }
+
+        #[test]
+        fn reports_superclasses_that_are_not_classes() {
+            let code = "
+                var NotAClass = \"so not a class\";
+                class OhNo < NotAClass {}
+            ";
+
+            assert_eq!(run_err(code), "3:30: Superclass must be a class.");
+        }
+
+        #[test]
+        fn reports_classes_inheriting_from_themselves() {
+            assert_eq!(run_err("class Oops < Oops {}"), "1:14: A class can't inherit from itself.");
+        }
+
+        #[test]
+        fn reports_undefined_superclass_methods() {
+            let code = "
+                class Base {}
+                class Derived < Base {
+                    f() { super.missing(); }
+                }
+                Derived().f();
+            ";
+
+            assert_eq!(run_err(code), "4:33: Undefined property 'missing'.");
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::function::Function;
+use super::value::Value;
+use crate::interpreter::symbol::Symbol;
+
+pub(crate) struct Class {
+    pub(crate) name: Symbol,
+    pub(crate) superclass: Option<Rc<Class>>,
+    pub(crate) methods: HashMap<Symbol, Rc<Function>>,
+}
+
+impl Class {
+    /// Also looks up the superclass chain.
+    pub(crate) fn find_method(&self, name: &Symbol) -> Option<Rc<Function>> {
+        match self.methods.get(name) {
+            Some(method) => Some(Rc::clone(method)),
+            None => self.superclass.as_ref()?.find_method(name),
+        }
+    }
+
+    /// The arity of the initializer, which receives the arguments of a call
+    /// to the class.
+    pub(crate) fn arity(&self) -> usize {
+        self.find_method(&Symbol::intern("init")).map_or(0, |init| init.arity())
+    }
+}
+
+impl std::fmt::Debug for Class {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+pub(crate) struct Instance {
+    pub(crate) class: Rc<Class>,
+    fields: HashMap<Symbol, Value>,
+}
+
+impl Instance {
+    pub(crate) fn new(class: Rc<Class>) -> Instance {
+        Instance { class, fields: HashMap::new() }
+    }
+
+    /// Looks up a field, then a method bound to `instance`.
+    pub(crate) fn get(instance: &Rc<RefCell<Instance>>, name: &Symbol) -> Option<Value> {
+        if let Some(value) = instance.borrow().fields.get(name) {
+            return Some(value.clone());
+        }
+
+        let method = instance.borrow().class.find_method(name)?;
+        let receiver = Value::Instance(Rc::clone(instance));
+        Some(Value::Function(Rc::new(method.bind(receiver))))
+    }
+
+    pub(crate) fn set(&mut self, name: &Symbol, value: Value) {
+        self.fields.insert(name.clone(), value);
+    }
+}
+
+impl std::fmt::Debug for Instance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
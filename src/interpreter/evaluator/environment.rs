@@ -0,0 +1,68 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::value::Value;
+use crate::interpreter::symbol::Symbol;
+
+/// Variables of a scope, linked to the scope enclosing it.
+#[derive(Default)]
+pub(crate) struct Environment {
+    values: HashMap<Symbol, Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub(crate) fn new(enclosing: Rc<RefCell<Environment>>) -> Environment {
+        Environment { values: HashMap::new(), enclosing: Some(enclosing) }
+    }
+
+    /// Also redefines variables that already exist in this scope.
+    pub(crate) fn define(&mut self, name: &Symbol, value: Value) {
+        self.values.insert(name.clone(), value);
+    }
+
+    pub(crate) fn get(&self, name: &Symbol) -> Option<Value> {
+        match self.values.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self.enclosing.as_ref()?.borrow().get(name),
+        }
+    }
+
+    /// Looks up a variable exactly `depth` scopes up.
+    pub(crate) fn get_at(&self, depth: usize, name: &Symbol) -> Option<Value> {
+        match depth {
+            0 => self.values.get(name).cloned(),
+            _ => self.enclosing.as_ref()?.borrow().get_at(depth - 1, name),
+        }
+    }
+
+    /// Returns `false` if the variable is not defined exactly `depth` scopes up.
+    pub(crate) fn assign_at(&mut self, depth: usize, name: &Symbol, value: Value) -> bool {
+        match (depth, &self.enclosing) {
+            (0, _) => match self.values.get_mut(name) {
+                Some(slot) => {
+                    *slot = value;
+                    true
+                }
+                None => false,
+            },
+            (_, Some(enclosing)) => enclosing.borrow_mut().assign_at(depth - 1, name, value),
+            (_, None) => false,
+        }
+    }
+
+    /// Returns `false` if the variable is not defined in any enclosing scope.
+    pub(crate) fn assign(&mut self, name: &Symbol, value: Value) -> bool {
+        match self.values.get_mut(name) {
+            Some(slot) => {
+                *slot = value;
+                true
+            }
+            None => match &self.enclosing {
+                Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+                None => false,
+            },
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::environment::Environment;
+use super::value::Value;
+use crate::interpreter::ast;
+use crate::interpreter::symbol::Symbol;
+
+/// A function value, closing over the environment where it was declared.
+pub(crate) struct Function {
+    pub(crate) declaration: Rc<ast::Function>,
+    pub(crate) closure: Rc<RefCell<Environment>>,
+    /// Initializers always return `this`.
+    pub(crate) is_initializer: bool,
+}
+
+impl Function {
+    pub(crate) fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    /// Makes a method whose `this` is `receiver`.
+    pub(crate) fn bind(&self, receiver: Value) -> Function {
+        let mut environment = Environment::new(Rc::clone(&self.closure));
+        environment.define(&Symbol::intern("this"), receiver);
+        Function {
+            declaration: Rc::clone(&self.declaration),
+            closure: Rc::new(RefCell::new(environment)),
+            is_initializer: self.is_initializer,
+        }
+    }
+}
+
+impl std::fmt::Debug for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<fn {}>", self.declaration.name.name)
+    }
+}
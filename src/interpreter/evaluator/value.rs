@@ -0,0 +1,163 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use super::class::{Class, Instance};
+use super::function::Function;
+use super::ErrorKind;
+use crate::interpreter::list;
+use crate::interpreter::map::{self, Key};
+use crate::interpreter::native::{self, NativeFunction};
+use crate::interpreter::symbol::Symbol;
+
+#[derive(Clone, Debug)]
+pub(crate) enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Symbol),
+    Function(Rc<Function>),
+    Native(Rc<NativeFunction>),
+    Class(Rc<Class>),
+    Instance(Rc<RefCell<Instance>>),
+    List(List),
+    /// A method read from a list, which it runs on.
+    ListMethod(List, list::Method),
+    Map(Map),
+    /// A method read from a map, which it runs on.
+    MapMethod(Map, map::Method),
+}
+
+pub(crate) type List = Rc<RefCell<Vec<Value>>>;
+
+pub(crate) type Map = Rc<RefCell<map::Map<Symbol, Value>>>;
+
+impl Value {
+    /// `nil` and `false` are falsey, everything else is truthy.
+    pub(crate) fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+            (Value::ListMethod(a, a_method), Value::ListMethod(b, b_method)) => {
+                Rc::ptr_eq(a, b) && a_method == b_method
+            }
+            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+            (Value::MapMethod(a, a_method), Value::MapMethod(b, b_method)) => {
+                Rc::ptr_eq(a, b) && a_method == b_method
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write(f, &mut vec![])
+    }
+}
+
+impl Value {
+    /// Formats the value inside the lists and maps at the addresses of
+    /// `enclosing`, which are shown as `[...]` and `{...}` if they contain
+    /// themselves, instead of forever.
+    fn write(&self, f: &mut std::fmt::Formatter<'_>, enclosing: &mut Vec<*const ()>) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::String(s) => write!(f, "{s}"),
+            Value::Function(function) => write!(f, "{function:?}"),
+            Value::Native(function) => write!(f, "{function:?}"),
+            Value::Class(class) => write!(f, "{class:?}"),
+            Value::Instance(instance) => write!(f, "{:?}", instance.borrow()),
+            Value::List(list) if enclosing.contains(&Rc::as_ptr(list).cast()) => write!(f, "[...]"),
+            Value::List(list) => {
+                enclosing.push(Rc::as_ptr(list).cast());
+                write!(f, "[")?;
+                for (i, element) in list.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.write_element(f, enclosing)?;
+                }
+                enclosing.pop();
+                write!(f, "]")
+            }
+            Value::Map(map) if enclosing.contains(&Rc::as_ptr(map).cast()) => write!(f, "{{...}}"),
+            Value::Map(map) => {
+                enclosing.push(Rc::as_ptr(map).cast());
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    Value::from(key.clone()).write_element(f, enclosing)?;
+                    write!(f, ": ")?;
+                    value.write_element(f, enclosing)?;
+                }
+                enclosing.pop();
+                write!(f, "}}")
+            }
+            Value::ListMethod(..) | Value::MapMethod(..) => write!(f, "<native fn>"),
+        }
+    }
+
+    /// Like `write`, but quotes strings, so that elements of lists and maps
+    /// that are strings can be told apart from those that aren't.
+    fn write_element(&self, f: &mut std::fmt::Formatter<'_>, enclosing: &mut Vec<*const ()>) -> std::fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "\"{s}\""),
+            value => value.write(f, enclosing),
+        }
+    }
+}
+
+impl From<Key<Symbol>> for Value {
+    fn from(key: Key<Symbol>) -> Self {
+        match key {
+            Key::String(s) => Value::String(s),
+            Key::Number(bits) => Value::Number(f64::from_bits(bits)),
+            Key::Bool(b) => Value::Bool(b),
+        }
+    }
+}
+
+impl TryFrom<native::Value> for Value {
+    type Error = ErrorKind;
+
+    fn try_from(value: native::Value) -> Result<Self, Self::Error> {
+        match value {
+            native::Value::Nil => Ok(Value::Nil),
+            native::Value::Bool(b) => Ok(Value::Bool(b)),
+            native::Value::Number(n) => Ok(Value::Number(n)),
+            native::Value::String(s) => Ok(Value::String(Symbol::intern(&s))),
+            native::Value::Object(native::Object(native::Handle::Evaluator(value))) => Ok(value),
+            // Objects of other interpreters don't exist here
+            native::Value::Object(native::Object(native::Handle::Vm { .. })) => Err(ErrorKind::InvalidObject),
+        }
+    }
+}
+
+impl From<Value> for native::Value {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Nil => native::Value::Nil,
+            Value::Bool(b) => native::Value::Bool(b),
+            Value::Number(n) => native::Value::Number(n),
+            Value::String(s) => native::Value::String(s.into()),
+            value => native::Value::Object(native::Object(native::Handle::Evaluator(value))),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//! Methods of lists, which both backends run the same way on their own
+//! values.
+
+use super::evaluator::ErrorKind;
+
+/// A method that every list has.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Method {
+    /// Adds its argument at the end.
+    Push,
+    /// Removes the last element and returns it.
+    Pop,
+    Len,
+    /// Adds its second argument before the element at its first.
+    Insert,
+    /// Removes the element at its argument and returns it.
+    Remove,
+    /// Returns a new list with the elements from its first argument up to,
+    /// but excluding, its second.
+    Slice,
+    /// Returns a new list with the results of calling its argument on each
+    /// element.
+    Map,
+    /// Returns a new list with the elements that its argument returns a
+    /// truthy value for.
+    Filter,
+}
+
+/// What a method returns, which each backend turns into one of its values.
+pub(crate) enum Return<T> {
+    Nil,
+    Number(f64),
+    Element(T),
+    List(Vec<T>),
+}
+
+impl Method {
+    pub(crate) fn find(name: &str) -> Option<Method> {
+        match name {
+            "push" => Some(Method::Push),
+            "pop" => Some(Method::Pop),
+            "len" => Some(Method::Len),
+            "insert" => Some(Method::Insert),
+            "remove" => Some(Method::Remove),
+            "slice" => Some(Method::Slice),
+            "map" => Some(Method::Map),
+            "filter" => Some(Method::Filter),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Method::Push => "push",
+            Method::Pop => "pop",
+            Method::Len => "len",
+            Method::Insert => "insert",
+            Method::Remove => "remove",
+            Method::Slice => "slice",
+            Method::Map => "map",
+            Method::Filter => "filter",
+        }
+    }
+
+    pub(crate) fn arity(self) -> usize {
+        match self {
+            Method::Pop | Method::Len => 0,
+            Method::Push | Method::Remove | Method::Map | Method::Filter => 1,
+            Method::Insert | Method::Slice => 2,
+        }
+    }
+
+    /// Runs the method on `list`, with as many `arguments` as its arity.
+    ///
+    /// `number` tells which values are numbers, to use them as indices.
+    ///
+    /// Backends run `map` and `filter` themselves, as those call functions.
+    pub(crate) fn call<T: Clone>(
+        self,
+        list: &mut Vec<T>,
+        arguments: &[T],
+        number: impl Fn(&T) -> Option<f64>,
+    ) -> Result<Return<T>, ErrorKind> {
+        let result = match (self, arguments) {
+            (Method::Push, [element]) => {
+                list.push(element.clone());
+                Return::Nil
+            }
+            (Method::Pop, []) => Return::Element(list.pop().ok_or(ErrorKind::PopFromEmptyList)?),
+            (Method::Len, []) => Return::Number(list.len() as f64),
+            (Method::Insert, [index, element]) => {
+                // Inserting at the length appends
+                let index = position(number(index), list.len())?;
+                list.insert(index, element.clone());
+                Return::Nil
+            }
+            (Method::Remove, [at]) => Return::Element(list.remove(index(number(at), list.len())?)),
+            (Method::Slice, [start, end]) => {
+                let end = position(number(end), list.len())?;
+                let start = position(number(start), list.len())?;
+                Return::List(list.get(start..end).map_or(vec![], <[T]>::to_vec))
+            }
+            (Method::Map | Method::Filter, _) => unreachable!("Backends call functions themselves"),
+            _ => unreachable!("Callers check the arity of methods"),
+        };
+        Ok(result)
+    }
+}
+
+/// Checks that `index` is the index of an element of a list of `length`
+/// elements.
+pub(crate) fn index(index: Option<f64>, length: usize) -> Result<usize, ErrorKind> {
+    below(index, length, length)
+}
+
+/// Like `index`, but also accepting the length, which is the position after
+/// the last element.
+fn position(index: Option<f64>, length: usize) -> Result<usize, ErrorKind> {
+    below(index, length + 1, length)
+}
+
+fn below(index: Option<f64>, end: usize, length: usize) -> Result<usize, ErrorKind> {
+    let index = index.filter(|index| index.fract() == 0.0).ok_or(ErrorKind::IndexMustBeInteger)?;
+    if index >= 0.0 && index < end as f64 {
+        Ok(index as usize)
+    } else {
+        Err(ErrorKind::IndexOutOfBounds { index, length })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(method: Method, list: &mut Vec<f64>, arguments: &[f64]) -> Result<Vec<f64>, ErrorKind> {
+        match method.call(list, arguments, |&n| Some(n))? {
+            Return::Nil => Ok(vec![]),
+            Return::Number(n) | Return::Element(n) => Ok(vec![n]),
+            Return::List(list) => Ok(list),
+        }
+    }
+
+    #[test]
+    fn changes_lists_in_place() {
+        let mut list = vec![1.0, 2.0];
+
+        assert_eq!(call(Method::Push, &mut list, &[3.0]), Ok(vec![]));
+        assert_eq!(call(Method::Insert, &mut list, &[0.0, 0.0]), Ok(vec![]));
+        assert_eq!(call(Method::Insert, &mut list, &[4.0, 4.0]), Ok(vec![]));
+        assert_eq!(call(Method::Remove, &mut list, &[1.0]), Ok(vec![1.0]));
+        assert_eq!(call(Method::Pop, &mut list, &[]), Ok(vec![4.0]));
+        assert_eq!(list, [0.0, 2.0, 3.0]);
+        assert_eq!(call(Method::Len, &mut list, &[]), Ok(vec![3.0]));
+        assert_eq!(call(Method::Slice, &mut list, &[1.0, 3.0]), Ok(vec![2.0, 3.0]));
+        assert_eq!(call(Method::Slice, &mut list, &[2.0, 1.0]), Ok(vec![]));
+    }
+
+    #[test]
+    fn checks_bounds() {
+        let mut list = vec![1.0];
+
+        assert_eq!(index(Some(1.0), 1), Err(ErrorKind::IndexOutOfBounds { index: 1.0, length: 1 }));
+        assert_eq!(index(Some(-1.0), 1), Err(ErrorKind::IndexOutOfBounds { index: -1.0, length: 1 }));
+        assert_eq!(index(Some(0.5), 1), Err(ErrorKind::IndexMustBeInteger));
+        assert_eq!(index(None, 1), Err(ErrorKind::IndexMustBeInteger));
+        let out_of_bounds = Err(ErrorKind::IndexOutOfBounds { index: 2.0, length: 1 });
+        assert_eq!(call(Method::Insert, &mut list, &[2.0, 0.0]), out_of_bounds);
+        assert_eq!(call(Method::Slice, &mut list, &[0.0, 2.0]), out_of_bounds);
+        assert_eq!(call(Method::Pop, &mut vec![], &[]), Err(ErrorKind::PopFromEmptyList));
+    }
+}
@@ -0,0 +1,190 @@
+//! Maps and their methods, which both backends share, each with its own
+//! strings and values.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A key of a map, whose strings are `S`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Key<S> {
+    String(S),
+    /// Bits of the number, with `-0` made `0` as they are equal.
+    Number(u64),
+    Bool(bool),
+}
+
+impl<S> Key<S> {
+    pub(crate) fn number(n: f64) -> Key<S> {
+        Key::Number(if n == 0.0 { 0.0_f64.to_bits() } else { n.to_bits() })
+    }
+}
+
+/// Values by key, which iterate in the order the keys were first inserted.
+#[derive(Debug)]
+pub(crate) struct Map<S, V> {
+    entries: Vec<(Key<S>, V)>,
+    /// Position of each key in `entries`.
+    positions: HashMap<Key<S>, usize>,
+}
+
+impl<S, V> Default for Map<S, V> {
+    fn default() -> Self {
+        Map { entries: vec![], positions: HashMap::new() }
+    }
+}
+
+impl<S: Clone + Eq + Hash, V> Map<S, V> {
+    pub(crate) fn get(&self, key: &Key<S>) -> Option<&V> {
+        self.positions.get(key).map(|&position| &self.entries[position].1)
+    }
+
+    /// Replaces the value of `key`, keeping its position, or adds it last.
+    pub(crate) fn insert(&mut self, key: Key<S>, value: V) {
+        match self.positions.get(&key) {
+            Some(&position) => self.entries[position].1 = value,
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    /// Removes `key`, keeping the order of the other keys.
+    pub(crate) fn remove(&mut self, key: &Key<S>) -> Option<V> {
+        let position = self.positions.remove(key)?;
+        let (_, value) = self.entries.remove(position);
+        for later in self.positions.values_mut().filter(|later| **later > position) {
+            *later -= 1;
+        }
+        Some(value)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&Key<S>, &V)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
+
+/// A method that every map has.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Method {
+    /// Returns a list of the keys.
+    Keys,
+    /// Returns a list of the values, in the order of their keys.
+    Values,
+    /// Returns whether its argument is a key.
+    Has,
+    /// Removes the key of its argument and returns its value.
+    Remove,
+    Len,
+}
+
+/// What a method returns, which each backend turns into one of its values.
+pub(crate) enum Return<S, V> {
+    Bool(bool),
+    Number(f64),
+    Value(V),
+    Keys(Vec<Key<S>>),
+    Values(Vec<V>),
+}
+
+impl Method {
+    pub(crate) fn find(name: &str) -> Option<Method> {
+        match name {
+            "keys" => Some(Method::Keys),
+            "values" => Some(Method::Values),
+            "has" => Some(Method::Has),
+            "remove" => Some(Method::Remove),
+            "len" => Some(Method::Len),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Method::Keys => "keys",
+            Method::Values => "values",
+            Method::Has => "has",
+            Method::Remove => "remove",
+            Method::Len => "len",
+        }
+    }
+
+    pub(crate) fn arity(self) -> usize {
+        match self {
+            Method::Keys | Method::Values | Method::Len => 0,
+            Method::Has | Method::Remove => 1,
+        }
+    }
+
+    /// Runs the method on `map`, with the `key` of its argument if it has
+    /// one.
+    ///
+    /// Returns `None` if it removes a key that `map` doesn't have.
+    pub(crate) fn call<S: Clone + Eq + Hash, V: Clone>(
+        self,
+        map: &mut Map<S, V>,
+        key: Option<&Key<S>>,
+    ) -> Option<Return<S, V>> {
+        let result = match (self, key) {
+            (Method::Keys, None) => Return::Keys(map.iter().map(|(key, _)| key.clone()).collect()),
+            (Method::Values, None) => Return::Values(map.iter().map(|(_, value)| value.clone()).collect()),
+            (Method::Has, Some(key)) => Return::Bool(map.get(key).is_some()),
+            (Method::Remove, Some(key)) => Return::Value(map.remove(key)?),
+            (Method::Len, None) => Return::Number(map.len() as f64),
+            _ => unreachable!("Callers check the arity of methods"),
+        };
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_insertion_order() {
+        let mut map: Map<&str, i32> = Map::default();
+        for (key, value) in [("b", 1), ("a", 2), ("c", 3), ("a", 4)] {
+            map.insert(Key::String(key), value);
+        }
+        assert_eq!(map.remove(&Key::String("b")), Some(1));
+        map.insert(Key::String("b"), 5);
+
+        let entries: Vec<_> = map.iter().map(|(key, &value)| (*key, value)).collect();
+        assert_eq!(entries, [(Key::String("a"), 4), (Key::String("c"), 3), (Key::String("b"), 5)]);
+        assert_eq!(map.get(&Key::String("c")), Some(&3));
+        assert_eq!(map.remove(&Key::String("d")), None);
+        assert_eq!(map.len(), 3);
+    }
+
+    #[test]
+    fn compares_number_keys_like_lox() {
+        let mut map: Map<&str, i32> = Map::default();
+        map.insert(Key::number(0.0), 1);
+        map.insert(Key::number(-0.0), 2);
+        map.insert(Key::Bool(false), 3);
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&Key::number(0.0)), Some(&2));
+    }
+
+    #[test]
+    fn runs_methods() {
+        let mut map: Map<&str, i32> = Map::default();
+        map.insert(Key::String("a"), 1);
+        map.insert(Key::Bool(true), 2);
+
+        let Some(Return::Keys(keys)) = Method::Keys.call(&mut map, None) else { panic!("Expected keys") };
+        assert_eq!(keys, [Key::String("a"), Key::Bool(true)]);
+        let Some(Return::Values(values)) = Method::Values.call(&mut map, None) else { panic!("Expected values") };
+        assert_eq!(values, [1, 2]);
+        assert!(matches!(Method::Remove.call(&mut map, Some(&Key::String("a"))), Some(Return::Value(1))));
+        assert!(Method::Remove.call(&mut map, Some(&Key::String("a"))).is_none());
+        assert!(matches!(Method::Has.call(&mut map, Some(&Key::Bool(true))), Some(Return::Bool(true))));
+        assert!(matches!(Method::Len.call(&mut map, None), Some(Return::Number(1.0))));
+    }
+}
@@ -0,0 +1,145 @@
+use std::rc::Rc;
+
+use super::{evaluator, vm};
+
+/// A value passed to or returned from a native function.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Rc<str>),
+    /// Functions, classes and instances, which natives can only pass around.
+    Object(Object),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::String(s) => write!(f, "{s}"),
+            Value::Object(object) => write!(f, "{object}"),
+        }
+    }
+}
+
+/// A Lox object owned by the interpreter running the code.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Object(pub(crate) Handle);
+
+/// How each backend refers to its objects.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Handle {
+    Evaluator(evaluator::Value),
+    /// Objects of the VM are only valid during the call that received them,
+    /// as the VM can't tell whether natives hold on to them.
+    Vm {
+        /// Which VM the object belongs to.
+        vm: u64,
+        /// Which call of a native received the object, counting the calls of
+        /// that VM.
+        call: u64,
+        reference: vm::ObjRef,
+        /// What the object looks like when printed.
+        description: Rc<str>,
+    },
+}
+
+impl std::fmt::Display for Object {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Handle::Evaluator(value) => write!(f, "{value}"),
+            Handle::Vm { description, .. } => write!(f, "{description}"),
+        }
+    }
+}
+
+/// Returning `Err` makes the call fail with that message as a runtime error.
+type Body = dyn Fn(&[Value]) -> Result<Value, String>;
+
+/// A function implemented in Rust and callable from Lox code.
+pub(crate) struct NativeFunction {
+    pub(crate) name: String,
+    pub(crate) arity: usize,
+    pub(crate) body: Box<Body>,
+}
+
+impl std::fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn>")
+    }
+}
+
+/// Native functions to define as globals before running any code.
+pub struct Natives {
+    functions: Vec<Rc<NativeFunction>>,
+}
+
+impl Natives {
+    /// Includes the standard natives, such as `clock`.
+    pub fn new() -> Natives {
+        let mut natives = Natives::empty();
+        natives.define("clock", 0, |_| Ok(Value::Number(clock())));
+        natives
+    }
+
+    pub fn empty() -> Natives {
+        Natives { functions: vec![] }
+    }
+
+    /// Registers `body` as a global function called `name`, which can be
+    /// called with exactly `arity` arguments.
+    ///
+    /// Replaces natives previously defined with the same name.
+    pub fn define<F>(&mut self, name: &str, arity: usize, body: F) -> &mut Natives
+    where
+        F: Fn(&[Value]) -> Result<Value, String> + 'static,
+    {
+        self.functions.retain(|function| function.name != name);
+        let function = NativeFunction { name: name.to_string(), arity, body: Box::new(body) };
+        self.functions.push(Rc::new(function));
+        self
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Rc<NativeFunction>> {
+        self.functions.iter()
+    }
+}
+
+impl Default for Natives {
+    fn default() -> Self {
+        Natives::new()
+    }
+}
+
+/// Seconds since the Unix epoch.
+fn clock() -> f64 {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH);
+    now.map_or(0.0, |duration| duration.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defines_clock_by_default() {
+        let natives = Natives::new();
+
+        let clock = natives.iter().find(|function| function.name == "clock").unwrap();
+        assert_eq!(clock.arity, 0);
+        assert!(matches!((clock.body)(&[]), Ok(Value::Number(seconds)) if seconds > 0.0));
+    }
+
+    #[test]
+    fn replaces_natives_with_the_same_name() {
+        let mut natives = Natives::empty();
+        natives.define("f", 0, |_| Ok(Value::Nil)).define("f", 1, |_| Ok(Value::Bool(true)));
+
+        let functions: Vec<_> = natives.iter().collect();
+        assert_eq!(functions.len(), 1);
+        assert_eq!(functions[0].arity, 1);
+    }
+}
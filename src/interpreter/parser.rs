@@ -0,0 +1,894 @@
+use std::rc::Rc;
+
+use super::ast::{Expr, Function, Identifier, Literal, Stmt, Variable};
+use super::code::Code;
+use super::symbol::Symbol;
+use super::token::{self, Keyword, Span, Token, Type};
+
+pub(crate) struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    /// Where errors found after the last token are reported.
+    end: Span,
+    errors: Vec<Error>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Error {
+    pub(crate) kind: ErrorKind,
+    pub(crate) span: Span,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum ErrorKind {
+    ExpectExpression,
+    /// Holds what was expected, as in "Expect {0}."
+    Expect(&'static str),
+    InvalidAssignmentTarget,
+    TooManyArguments,
+    TooManyParameters,
+    InheritsFromItself,
+    TooManyElements,
+}
+
+/// Calls and functions can't have more arguments or parameters than this,
+/// nor list and map literals more elements.
+const MAX_ARGUMENTS: usize = 255;
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::ExpectExpression => write!(f, "Expect expression."),
+            ErrorKind::Expect(what) => write!(f, "Expect {what}."),
+            ErrorKind::InvalidAssignmentTarget => write!(f, "Invalid assignment target."),
+            ErrorKind::TooManyArguments => write!(f, "Can't have more than {MAX_ARGUMENTS} arguments."),
+            ErrorKind::TooManyParameters => write!(f, "Can't have more than {MAX_ARGUMENTS} parameters."),
+            ErrorKind::InheritsFromItself => write!(f, "A class can't inherit from itself."),
+            ErrorKind::TooManyElements => write!(f, "Can't have more than {MAX_ARGUMENTS} elements."),
+        }
+    }
+}
+
+impl ErrorKind {
+    pub(crate) fn code(&self) -> Code {
+        match self {
+            ErrorKind::ExpectExpression => Code::EXPECT_EXPRESSION,
+            ErrorKind::Expect(_) => Code::EXPECT_TOKEN,
+            ErrorKind::InvalidAssignmentTarget => Code::INVALID_ASSIGNMENT_TARGET,
+            ErrorKind::TooManyArguments => Code::TOO_MANY_ARGUMENTS,
+            ErrorKind::TooManyParameters => Code::TOO_MANY_PARAMETERS,
+            ErrorKind::InheritsFromItself => Code::INHERITS_FROM_ITSELF,
+            ErrorKind::TooManyElements => Code::TOO_MANY_ELEMENTS,
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Clone, Copy)]
+enum FunctionKind {
+    Function,
+    Method,
+}
+
+impl Parser {
+    /// `source_length` is used to point errors at the end of the source.
+    ///
+    /// `tokens` should not contain `Type::Error` tokens.
+    pub(crate) fn new(tokens: Vec<Token>, source_length: usize) -> Parser {
+        let end = Span { start: source_length, end: source_length };
+        Parser { tokens, position: 0, end, errors: vec![] }
+    }
+
+    pub(crate) fn parse(mut self) -> std::result::Result<Vec<Stmt>, Vec<Error>> {
+        let mut statements = vec![];
+        while !self.is_at_end() {
+            match self.declaration() {
+                Ok(statement) => statements.push(statement),
+                Err(error) => {
+                    self.errors.push(error);
+                    self.synchronize();
+                }
+            }
+        }
+
+        if self.errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    fn declaration(&mut self) -> Result<Stmt> {
+        if self.matches(&Type::Keyword(Keyword::Class)) {
+            self.class_declaration()
+        } else if self.check(&Type::Keyword(Keyword::Fun)) && !self.check_next(&Type::LeftParen) {
+            self.advance();
+            Ok(Stmt::Function(Rc::new(self.function(FunctionKind::Function)?)))
+        } else if self.matches(&Type::Keyword(Keyword::Var)) {
+            self.var_declaration()
+        } else {
+            self.statement()
+        }
+    }
+
+    fn class_declaration(&mut self) -> Result<Stmt> {
+        let name = self.consume_identifier("class name")?;
+        let superclass = if self.matches(&Type::Less) {
+            let superclass = self.consume_identifier("superclass name")?;
+            if superclass.name == name.name {
+                self.errors.push(Error { kind: ErrorKind::InheritsFromItself, span: superclass.span });
+            }
+            Some(Variable::new(superclass))
+        } else {
+            None
+        };
+        self.consume(Type::LeftBrace, "'{' before class body")?;
+
+        let mut methods = vec![];
+        while !self.check(&Type::RightBrace) && !self.is_at_end() {
+            methods.push(Rc::new(self.function(FunctionKind::Method)?));
+        }
+        self.consume(Type::RightBrace, "'}' after class body")?;
+
+        Ok(Stmt::Class { name, superclass, methods })
+    }
+
+    fn function(&mut self, kind: FunctionKind) -> Result<Function> {
+        let (name, paren, brace) = match kind {
+            FunctionKind::Function => ("function name", "'(' after function name", "'{' before function body"),
+            FunctionKind::Method => ("method name", "'(' after method name", "'{' before method body"),
+        };
+        let name = self.consume_identifier(name)?;
+        self.consume(Type::LeftParen, paren)?;
+        let params = self.parameters()?;
+
+        self.consume(Type::LeftBrace, brace)?;
+        let body = self.block()?;
+
+        Ok(Function { name, params, body })
+    }
+
+    /// Should be called after consuming the `(`, and consumes the `)`.
+    fn parameters(&mut self) -> Result<Vec<Identifier>> {
+        let mut params = vec![];
+        if !self.check(&Type::RightParen) {
+            loop {
+                if params.len() >= MAX_ARGUMENTS {
+                    let error = self.error(ErrorKind::TooManyParameters);
+                    self.errors.push(error);
+                }
+                params.push(self.consume_identifier("parameter name")?);
+                if !self.matches(&Type::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(Type::RightParen, "')' after parameters")?;
+
+        Ok(params)
+    }
+
+    fn var_declaration(&mut self) -> Result<Stmt> {
+        let name = self.consume_identifier("variable name")?;
+        let initializer = if self.matches(&Type::Equal) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(Type::Semicolon, "';' after variable declaration")?;
+
+        Ok(Stmt::Var { name, initializer })
+    }
+
+    fn statement(&mut self) -> Result<Stmt> {
+        if self.matches(&Type::Keyword(Keyword::For)) {
+            self.for_statement()
+        } else if self.matches(&Type::Keyword(Keyword::If)) {
+            self.if_statement()
+        } else if self.matches(&Type::Keyword(Keyword::Print)) {
+            self.print_statement()
+        } else if self.matches(&Type::Keyword(Keyword::Return)) {
+            self.return_statement()
+        } else if self.matches(&Type::Keyword(Keyword::While)) {
+            self.while_statement()
+        } else if self.matches(&Type::Keyword(Keyword::Break)) {
+            let keyword = self.previous().span;
+            self.consume(Type::Semicolon, "';' after 'break'")?;
+            Ok(Stmt::Break { keyword })
+        } else if self.matches(&Type::Keyword(Keyword::Continue)) {
+            let keyword = self.previous().span;
+            self.consume(Type::Semicolon, "';' after 'continue'")?;
+            Ok(Stmt::Continue { keyword })
+        } else if self.matches(&Type::LeftBrace) {
+            Ok(Stmt::Block(self.block()?))
+        } else {
+            self.expression_statement()
+        }
+    }
+
+    /// Desugars `for (initializer; condition; increment) body` into
+    /// `{ initializer; while (condition) body }`, with the increment kept in
+    /// the `while`.
+    fn for_statement(&mut self) -> Result<Stmt> {
+        self.consume(Type::LeftParen, "'(' after 'for'")?;
+        let initializer = if self.matches(&Type::Semicolon) {
+            None
+        } else if self.matches(&Type::Keyword(Keyword::Var)) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if self.check(&Type::Semicolon) {
+            Expr::Literal(Literal::Bool(true))
+        } else {
+            self.expression()?
+        };
+        self.consume(Type::Semicolon, "';' after loop condition")?;
+
+        let increment = if self.check(&Type::RightParen) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(Type::RightParen, "')' after for clauses")?;
+
+        let body = self.statement()?;
+        let mut body = Stmt::While { condition, body: Box::new(body), increment };
+        if let Some(initializer) = initializer {
+            body = Stmt::Block(vec![initializer, body]);
+        }
+
+        Ok(body)
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt> {
+        self.consume(Type::LeftParen, "'(' after 'if'")?;
+        let condition = self.expression()?;
+        self.consume(Type::RightParen, "')' after if condition")?;
+
+        let then_branch = Box::new(self.statement()?);
+        let else_branch = if self.matches(&Type::Keyword(Keyword::Else)) {
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+
+        Ok(Stmt::If { condition, then_branch, else_branch })
+    }
+
+    fn print_statement(&mut self) -> Result<Stmt> {
+        let value = self.expression()?;
+        self.consume(Type::Semicolon, "';' after value")?;
+        Ok(Stmt::Print(value))
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt> {
+        let keyword = self.previous().span;
+        let value = if self.check(&Type::Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(Type::Semicolon, "';' after return value")?;
+
+        Ok(Stmt::Return { keyword, value })
+    }
+
+    fn while_statement(&mut self) -> Result<Stmt> {
+        self.consume(Type::LeftParen, "'(' after 'while'")?;
+        let condition = self.expression()?;
+        self.consume(Type::RightParen, "')' after condition")?;
+        let body = Box::new(self.statement()?);
+
+        Ok(Stmt::While { condition, body, increment: None })
+    }
+
+    /// Should be called after consuming the `{`.
+    fn block(&mut self) -> Result<Vec<Stmt>> {
+        let mut statements = vec![];
+        while !self.check(&Type::RightBrace) && !self.is_at_end() {
+            statements.push(self.declaration()?);
+        }
+        self.consume(Type::RightBrace, "'}' after block")?;
+
+        Ok(statements)
+    }
+
+    fn expression_statement(&mut self) -> Result<Stmt> {
+        let expr = self.expression()?;
+        self.consume(Type::Semicolon, "';' after expression")?;
+        Ok(Stmt::Expression(expr))
+    }
+
+    fn expression(&mut self) -> Result<Expr> {
+        self.assignment()
+    }
+
+    fn assignment(&mut self) -> Result<Expr> {
+        let expr = self.conditional()?;
+
+        if self.matches(&Type::Equal) {
+            let equals = self.previous().span;
+            let value = Box::new(self.assignment()?);
+            return match expr {
+                Expr::Variable(variable) => Ok(Expr::Assign { variable, value }),
+                Expr::Get { object, name } => Ok(Expr::Set { object, name, value }),
+                Expr::Index { object, bracket, index } => Ok(Expr::SetIndex { object, bracket, index, value }),
+                expr => {
+                    // Reported without unwinding, since the parser is not confused
+                    self.errors.push(Error { kind: ErrorKind::InvalidAssignmentTarget, span: equals });
+                    Ok(expr)
+                }
+            };
+        }
+
+        Ok(expr)
+    }
+
+    fn conditional(&mut self) -> Result<Expr> {
+        let condition = self.coalesce()?;
+
+        if self.matches(&Type::Question) {
+            let then_branch = self.expression()?;
+            self.consume(Type::Colon, "':' after then branch of conditional")?;
+            // Right-associative, so that conditionals chain like `else if`
+            let else_branch = self.conditional()?;
+            return Ok(Expr::Conditional {
+                condition: Box::new(condition),
+                then_branch: Box::new(then_branch),
+                else_branch: Box::new(else_branch),
+            });
+        }
+
+        Ok(condition)
+    }
+
+    fn coalesce(&mut self) -> Result<Expr> {
+        let mut expr = self.or()?;
+        while self.matches(&Type::QuestionQuestion) {
+            let operator = self.previous().clone();
+            let right = self.or()?;
+            expr = Expr::Logical { left: Box::new(expr), operator, right: Box::new(right) };
+        }
+
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.matches(&Type::Keyword(Keyword::Or)) {
+            let operator = self.previous().clone();
+            let right = self.and()?;
+            expr = Expr::Logical { left: Box::new(expr), operator, right: Box::new(right) };
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.equality()?;
+        while self.matches(&Type::Keyword(Keyword::And)) {
+            let operator = self.previous().clone();
+            let right = self.equality()?;
+            expr = Expr::Logical { left: Box::new(expr), operator, right: Box::new(right) };
+        }
+
+        Ok(expr)
+    }
+
+    fn equality(&mut self) -> Result<Expr> {
+        self.binary(&[Type::BangEqual, Type::EqualEqual], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        self.binary(
+            &[Type::Greater, Type::GreaterEqual, Type::Less, Type::LessEqual],
+            Self::term,
+        )
+    }
+
+    fn term(&mut self) -> Result<Expr> {
+        self.binary(&[Type::Minus, Type::Plus], Self::factor)
+    }
+
+    fn factor(&mut self) -> Result<Expr> {
+        self.binary(&[Type::Slash, Type::Star], Self::unary)
+    }
+
+    /// Parses a left-associative chain of `operators` whose operands are
+    /// parsed by `operand`.
+    fn binary(&mut self, operators: &[Type], operand: fn(&mut Self) -> Result<Expr>) -> Result<Expr> {
+        let mut expr = operand(self)?;
+        while operators.iter().any(|operator| self.matches(operator)) {
+            let operator = self.previous().clone();
+            let right = operand(self)?;
+            expr = Expr::Binary { left: Box::new(expr), operator, right: Box::new(right) };
+        }
+
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.matches(&Type::Bang) || self.matches(&Type::Minus) {
+            let operator = self.previous().clone();
+            let right = self.unary()?;
+            return Ok(Expr::Unary { operator, right: Box::new(right) });
+        }
+
+        self.call()
+    }
+
+    fn call(&mut self) -> Result<Expr> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.matches(&Type::LeftParen) {
+                expr = self.finish_call(expr)?;
+            } else if self.matches(&Type::Dot) {
+                let name = self.consume_identifier("property name after '.'")?;
+                expr = Expr::Get { object: Box::new(expr), name };
+            } else if self.matches(&Type::LeftBracket) {
+                let index = self.expression()?;
+                let bracket = self.consume(Type::RightBracket, "']' after index")?.span;
+                expr = Expr::Index { object: Box::new(expr), bracket, index: Box::new(index) };
+            } else {
+                break;
+            }
+        }
+
+        Ok(expr)
+    }
+
+    /// Should be called after consuming the `(`.
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr> {
+        let mut arguments = vec![];
+        if !self.check(&Type::RightParen) {
+            loop {
+                if arguments.len() >= MAX_ARGUMENTS {
+                    let error = self.error(ErrorKind::TooManyArguments);
+                    self.errors.push(error);
+                }
+                arguments.push(self.expression()?);
+                if !self.matches(&Type::Comma) {
+                    break;
+                }
+            }
+        }
+        let paren = self.consume(Type::RightParen, "')' after arguments")?.span;
+
+        Ok(Expr::Call { callee: Box::new(callee), paren, arguments })
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        let Some(token) = self.peek() else {
+            return Err(self.error(ErrorKind::ExpectExpression));
+        };
+
+        let expr = match &token.r#type {
+            Type::Keyword(Keyword::False) => Expr::Literal(Literal::Bool(false)),
+            Type::Keyword(Keyword::True) => Expr::Literal(Literal::Bool(true)),
+            Type::Keyword(Keyword::Nil) => Expr::Literal(Literal::Nil),
+            Type::Keyword(Keyword::This) => Expr::This(keyword(token, "this")),
+            Type::Keyword(Keyword::Super) => {
+                let keyword = keyword(token, "super");
+                self.advance();
+                self.consume(Type::Dot, "'.' after 'super'")?;
+                let method = self.consume_identifier("superclass method name")?;
+                return Ok(Expr::Super { keyword, method });
+            }
+            Type::NumberLiteral(token::NumberLiteral::Integer(n)) => Expr::Literal(Literal::Number(*n as f64)),
+            Type::NumberLiteral(token::NumberLiteral::Float(n)) => Expr::Literal(Literal::Number(*n)),
+            Type::StringLiteral(s) => Expr::Literal(Literal::String(s.clone())),
+            Type::Identifier(name) => Expr::Variable(Variable::new(Identifier {
+                name: name.clone(),
+                span: token.span,
+            })),
+            Type::Keyword(Keyword::Fun) => {
+                let keyword = token.span;
+                self.advance();
+                self.consume(Type::LeftParen, "'(' after 'fun'")?;
+                let params = self.parameters()?;
+                self.consume(Type::LeftBrace, "'{' before function body")?;
+                let body = self.block()?;
+                return Ok(lambda(keyword, params, body));
+            }
+            Type::LeftParen if self.is_arrow() => {
+                let paren = token.span;
+                self.advance();
+                let params = self.parameters()?;
+                let arrow = self.consume(Type::Arrow, "'=>' after parameters")?.span;
+                let value = self.expression()?;
+                return Ok(lambda(paren, params, vec![Stmt::Return { keyword: arrow, value: Some(value) }]));
+            }
+            Type::LeftParen => {
+                self.advance();
+                let expr = self.expression()?;
+                self.consume(Type::RightParen, "')' after expression")?;
+                return Ok(Expr::Grouping(Box::new(expr)));
+            }
+            Type::LeftBracket => {
+                self.advance();
+                return self.list();
+            }
+            Type::LeftBrace => {
+                let brace = token.span;
+                self.advance();
+                return self.map(brace);
+            }
+            _ => return Err(self.error(ErrorKind::ExpectExpression)),
+        };
+        self.advance();
+
+        Ok(expr)
+    }
+
+    /// Should be called after consuming the `[`.
+    fn list(&mut self) -> Result<Expr> {
+        let mut elements = vec![];
+        if !self.check(&Type::RightBracket) {
+            loop {
+                if elements.len() >= MAX_ARGUMENTS {
+                    let error = self.error(ErrorKind::TooManyElements);
+                    self.errors.push(error);
+                }
+                elements.push(self.expression()?);
+                if !self.matches(&Type::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(Type::RightBracket, "']' after list elements")?;
+
+        Ok(Expr::List(elements))
+    }
+
+    /// Should be called after consuming the `{`, at `brace`.
+    fn map(&mut self, brace: Span) -> Result<Expr> {
+        let mut entries = vec![];
+        if !self.check(&Type::RightBrace) {
+            loop {
+                if entries.len() >= MAX_ARGUMENTS {
+                    let error = self.error(ErrorKind::TooManyElements);
+                    self.errors.push(error);
+                }
+                let key = self.expression()?;
+                self.consume(Type::Colon, "':' after map key")?;
+                entries.push((key, self.expression()?));
+                if !self.matches(&Type::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(Type::RightBrace, "'}' after map entries")?;
+
+        Ok(Expr::Map { brace, entries })
+    }
+
+    fn consume(&mut self, r#type: Type, expected: &'static str) -> Result<&Token> {
+        if self.check(&r#type) {
+            self.advance();
+            Ok(self.previous())
+        } else {
+            Err(self.error(ErrorKind::Expect(expected)))
+        }
+    }
+
+    /// Like `consume`, but for any identifier.
+    ///
+    /// `expected` describes the role of the identifier, as in "variable name".
+    fn consume_identifier(&mut self, expected: &'static str) -> Result<Identifier> {
+        match self.peek() {
+            Some(Token { r#type: Type::Identifier(name), span }) => {
+                let identifier = Identifier { name: name.clone(), span: *span };
+                self.advance();
+                Ok(identifier)
+            }
+            _ => Err(self.error(ErrorKind::Expect(expected))),
+        }
+    }
+
+    /// Consumes the current token if it is of type `r#type`.
+    fn matches(&mut self, r#type: &Type) -> bool {
+        let is_match = self.check(r#type);
+        if is_match {
+            self.advance();
+        }
+        is_match
+    }
+
+    fn check(&self, r#type: &Type) -> bool {
+        self.peek().is_some_and(|token| &token.r#type == r#type)
+    }
+
+    /// Like `check`, but for the token after the current one.
+    fn check_next(&self, r#type: &Type) -> bool {
+        self.tokens.get(self.position + 1).is_some_and(|token| &token.r#type == r#type)
+    }
+
+    /// Whether the current `(` starts the parameters of an arrow function,
+    /// rather than a grouping.
+    fn is_arrow(&self) -> bool {
+        let mut rest = self.tokens[self.position + 1..]
+            .iter()
+            .map(|token| &token.r#type)
+            .skip_while(|r#type| matches!(r#type, Type::Identifier(_) | Type::Comma));
+        rest.next() == Some(&Type::RightParen) && rest.next() == Some(&Type::Arrow)
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn previous(&self) -> &Token {
+        &self.tokens[self.position - 1]
+    }
+
+    fn advance(&mut self) {
+        if !self.is_at_end() {
+            self.position += 1;
+        }
+    }
+
+    /// Builds an error pointing at the current token.
+    fn error(&self, kind: ErrorKind) -> Error {
+        let span = self.peek().map_or(self.end, |token| token.span);
+        Error { kind, span }
+    }
+
+    /// Discards tokens until the probable start of the next statement, so
+    /// that one mistake does not cause a cascade of errors.
+    fn synchronize(&mut self) {
+        self.advance();
+        while !self.is_at_end() {
+            if self.previous().r#type == Type::Semicolon {
+                return;
+            }
+            match self.peek().map(|token| &token.r#type) {
+                Some(Type::Keyword(
+                    Keyword::Class
+                    | Keyword::Fun
+                    | Keyword::Var
+                    | Keyword::For
+                    | Keyword::If
+                    | Keyword::While
+                    | Keyword::Print
+                    | Keyword::Return
+                    | Keyword::Break
+                    | Keyword::Continue
+                )) => return,
+                _ => self.advance(),
+            }
+        }
+    }
+}
+
+/// `this` and `super` are looked up like variables named after them.
+fn keyword(token: &Token, name: &str) -> Variable {
+    Variable::new(Identifier { name: Symbol::intern(name), span: token.span })
+}
+
+/// Makes an anonymous function starting at `span`.
+fn lambda(span: Span, params: Vec<Identifier>, body: Vec<Stmt>) -> Expr {
+    Expr::Function(Rc::new(Function { name: Identifier { name: Symbol::intern("lambda"), span }, params, body }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::Scanner;
+
+    use super::*;
+
+    fn parse(source: &str) -> std::result::Result<Vec<Stmt>, Vec<Error>> {
+        let tokens = Scanner::new(source).scan_tokens();
+        Parser::new(tokens, source.len()).parse()
+    }
+
+    fn error_kinds(source: &str) -> Vec<ErrorKind> {
+        let errors = parse(source).expect_err("Parsed invalid code");
+        errors.into_iter().map(|error| error.kind).collect()
+    }
+
+    #[test]
+    fn parses_precedence() {
+        let statements = parse("1 + 2 * 3;").unwrap();
+
+        let [Stmt::Expression(Expr::Binary { operator, right, .. })] = statements.as_slice() else {
+            panic!("Expected a binary expression, got {statements:?}");
+        };
+        assert_eq!(operator.r#type, Type::Plus);
+        assert!(matches!(**right, Expr::Binary { .. }));
+    }
+
+    #[test]
+    fn desugars_for_into_while() {
+        let statements = parse("for (var i = 0; i < 3; i = i + 1) print i;").unwrap();
+
+        let [Stmt::Block(block)] = statements.as_slice() else {
+            panic!("Expected a block, got {statements:?}");
+        };
+        assert!(matches!(block.as_slice(), [Stmt::Var { .. }, Stmt::While { .. }]));
+    }
+
+    #[test]
+    fn parses_conditionals_below_assignment_and_coalescing_below_or() {
+        let statements = parse("a = b ? c : d ? e : f; a ?? b or c;").unwrap();
+
+        let [Stmt::Expression(Expr::Assign { value, .. }), Stmt::Expression(Expr::Logical { operator, right, .. })] =
+            statements.as_slice()
+        else {
+            panic!("Expected an assignment and a logical expression, got {statements:?}");
+        };
+        let Expr::Conditional { else_branch, .. } = &**value else {
+            panic!("Expected a conditional, got {value:?}");
+        };
+        assert!(matches!(**else_branch, Expr::Conditional { .. }));
+        assert_eq!(operator.r#type, Type::QuestionQuestion);
+        assert!(matches!(**right, Expr::Logical { .. }));
+        assert_eq!(error_kinds("a ? b;"), &[ErrorKind::Expect("':' after then branch of conditional")]);
+        assert_eq!(error_kinds("a ? b : c = d;"), &[ErrorKind::InvalidAssignmentTarget]);
+    }
+
+    #[test]
+    fn parses_chained_calls() {
+        let statements = parse("f(1, 2)();").unwrap();
+
+        let [Stmt::Expression(Expr::Call { callee, arguments, .. })] = statements.as_slice() else {
+            panic!("Expected a call, got {statements:?}");
+        };
+        assert!(arguments.is_empty());
+        assert!(matches!(**callee, Expr::Call { ref arguments, .. } if arguments.len() == 2));
+    }
+
+    #[test]
+    fn parses_function_declarations() {
+        let statements = parse("fun add(a, b) { return a + b; }").unwrap();
+
+        let [Stmt::Function(function)] = statements.as_slice() else {
+            panic!("Expected a function, got {statements:?}");
+        };
+        assert_eq!(function.name.name, "add");
+        assert_eq!(function.params.len(), 2);
+        assert!(matches!(function.body.as_slice(), [Stmt::Return { value: Some(_), .. }]));
+    }
+
+    #[test]
+    fn parses_lambdas_and_arrow_functions() {
+        let statements = parse("fun (a) {}(1); print (a, b) => a + b; print (a);").unwrap();
+
+        let [Stmt::Expression(Expr::Call { callee, .. }), Stmt::Print(Expr::Function(arrow)), Stmt::Print(grouping)] =
+            statements.as_slice()
+        else {
+            panic!("Expected a lambda, an arrow function and a grouping, got {statements:?}");
+        };
+        assert!(matches!(&**callee, Expr::Function(lambda) if lambda.params.len() == 1 && lambda.body.is_empty()));
+        assert_eq!(arrow.params.len(), 2);
+        assert!(matches!(arrow.body.as_slice(), [Stmt::Return { value: Some(Expr::Binary { .. }), .. }]));
+        assert!(matches!(grouping, Expr::Grouping(_)));
+        assert_eq!(error_kinds("print fun f() {};"), &[ErrorKind::Expect("'(' after 'fun'")]);
+    }
+
+    #[test]
+    fn parses_class_declarations() {
+        let statements = parse("class Point { init(x) { this.x = x; } norm() {} }").unwrap();
+
+        let [Stmt::Class { name, superclass: None, methods }] = statements.as_slice() else {
+            panic!("Expected a class, got {statements:?}");
+        };
+        assert_eq!(name.name, "Point");
+        let [init, norm] = methods.as_slice() else {
+            panic!("Expected two methods, got {methods:?}");
+        };
+        assert_eq!(init.name.name, "init");
+        assert!(matches!(init.body.as_slice(), [Stmt::Expression(Expr::Set { .. })]));
+        assert_eq!(norm.name.name, "norm");
+    }
+
+    #[test]
+    fn parses_superclasses() {
+        let statements = parse("class B < A { f() { return super.f(); } }").unwrap();
+
+        let [Stmt::Class { superclass: Some(superclass), methods, .. }] = statements.as_slice() else {
+            panic!("Expected a subclass, got {statements:?}");
+        };
+        assert_eq!(superclass.name.name, "A");
+        let [Stmt::Return { value: Some(Expr::Call { callee, .. }), .. }] = methods[0].body.as_slice() else {
+            panic!("Expected a call, got {methods:?}");
+        };
+        assert!(matches!(&**callee, Expr::Super { method, .. } if method.name == "f"));
+    }
+
+    #[test]
+    fn reports_classes_inheriting_from_themselves() {
+        assert_eq!(error_kinds("class A < A {}"), &[ErrorKind::InheritsFromItself]);
+    }
+
+    #[test]
+    fn reports_super_without_method() {
+        assert_eq!(error_kinds("super;"), &[ErrorKind::Expect("'.' after 'super'")]);
+    }
+
+    #[test]
+    fn parses_property_chains() {
+        let statements = parse("a.b(1).c = 2;").unwrap();
+
+        let [Stmt::Expression(Expr::Set { object, name, .. })] = statements.as_slice() else {
+            panic!("Expected a property assignment, got {statements:?}");
+        };
+        assert_eq!(name.name, "c");
+        assert!(matches!(**object, Expr::Call { .. }));
+    }
+
+    #[test]
+    fn parses_lists_and_index_assignments() {
+        let statements = parse("xs[0][1] = [1, [2], []];").unwrap();
+
+        let [Stmt::Expression(Expr::SetIndex { object, value, .. })] = statements.as_slice() else {
+            panic!("Expected an index assignment, got {statements:?}");
+        };
+        assert!(matches!(**object, Expr::Index { .. }));
+        assert!(matches!(&**value, Expr::List(elements) if elements.len() == 3));
+    }
+
+    #[test]
+    fn parses_maps_in_expressions_and_blocks_in_statements() {
+        let statements = parse("{ print {1: {}, \"a\": [2]}; }").unwrap();
+
+        let [Stmt::Block(block)] = statements.as_slice() else {
+            panic!("Expected a block, got {statements:?}");
+        };
+        let [Stmt::Print(Expr::Map { entries, .. })] = block.as_slice() else {
+            panic!("Expected a map, got {block:?}");
+        };
+        assert!(matches!(entries.as_slice(), [(_, Expr::Map { .. }), (_, Expr::List(_))]));
+        assert_eq!(error_kinds("print {1 2};"), &[ErrorKind::Expect("':' after map key")]);
+    }
+
+    #[test]
+    fn reports_too_many_elements() {
+        let elements = vec!["1"; 256].join(", ");
+
+        assert_eq!(error_kinds(&format!("[{elements}];")), &[ErrorKind::TooManyElements]);
+        assert_eq!(error_kinds("[1, 2;"), &[ErrorKind::Expect("']' after list elements")]);
+    }
+
+    #[test]
+    fn reports_too_many_arguments() {
+        let arguments = vec!["1"; 256].join(", ");
+        let code = format!("f({arguments});");
+
+        assert_eq!(error_kinds(&code), &[ErrorKind::TooManyArguments]);
+    }
+
+    #[test]
+    fn reports_missing_expression() {
+        assert_eq!(error_kinds("print ;"), &[ErrorKind::ExpectExpression]);
+    }
+
+    #[test]
+    fn reports_invalid_assignment_target() {
+        assert_eq!(error_kinds("1 = 2;"), &[ErrorKind::InvalidAssignmentTarget]);
+    }
+
+    #[test]
+    fn recovers_to_report_several_errors() {
+        let code = "
+            print 1
+            var a = 2;
+            var = 3;
+            print 4;
+        ";
+
+        assert_eq!(
+            error_kinds(code),
+            &[
+                ErrorKind::Expect("';' after value"),
+                ErrorKind::Expect("variable name"),
+            ],
+        )
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::HashMap;
+
+use super::ast::{Expr, Function, Identifier, Stmt, Variable};
+use super::code::Code;
+use super::symbol::Symbol;
+use super::token::Span;
+
+/// Binds each use of a variable to the scope that declares it, before the
+/// code runs, and reports misuses of names that the parser can't detect.
+pub(crate) struct Resolver {
+    /// Local scopes, innermost last.
+    scopes: Vec<HashMap<Symbol, Local>>,
+    function: FunctionKind,
+    class: ClassKind,
+    /// Whether the code is in the body of a loop of the current function.
+    in_loop: bool,
+    errors: Vec<Error>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Error {
+    pub(crate) kind: ErrorKind,
+    pub(crate) span: Span,
+    /// Where the name was declared before, for redeclarations.
+    pub(crate) previous: Option<Span>,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum ErrorKind {
+    ReadInOwnInitializer,
+    AlreadyDeclared,
+    ReturnFromTopLevel,
+    ReturnValueFromInitializer,
+    ThisOutsideClass,
+    SuperOutsideClass,
+    SuperWithoutSuperclass,
+    BreakOutsideLoop,
+    ContinueOutsideLoop,
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::ReadInOwnInitializer => write!(f, "Can't read local variable in its own initializer."),
+            ErrorKind::AlreadyDeclared => write!(f, "Already a variable with this name in this scope."),
+            ErrorKind::ReturnFromTopLevel => write!(f, "Can't return from top-level code."),
+            ErrorKind::ReturnValueFromInitializer => write!(f, "Can't return a value from an initializer."),
+            ErrorKind::ThisOutsideClass => write!(f, "Can't use 'this' outside of a class."),
+            ErrorKind::SuperOutsideClass => write!(f, "Can't use 'super' outside of a class."),
+            ErrorKind::SuperWithoutSuperclass => write!(f, "Can't use 'super' in a class with no superclass."),
+            ErrorKind::BreakOutsideLoop => write!(f, "Can't use 'break' outside of a loop."),
+            ErrorKind::ContinueOutsideLoop => write!(f, "Can't use 'continue' outside of a loop."),
+        }
+    }
+}
+
+impl ErrorKind {
+    pub(crate) fn code(&self) -> Code {
+        match self {
+            ErrorKind::ReadInOwnInitializer => Code::READ_IN_OWN_INITIALIZER,
+            ErrorKind::AlreadyDeclared => Code::ALREADY_DECLARED,
+            ErrorKind::ReturnFromTopLevel => Code::RETURN_FROM_TOP_LEVEL,
+            ErrorKind::ReturnValueFromInitializer => Code::RETURN_VALUE_FROM_INITIALIZER,
+            ErrorKind::ThisOutsideClass => Code::THIS_OUTSIDE_CLASS,
+            ErrorKind::SuperOutsideClass => Code::SUPER_OUTSIDE_CLASS,
+            ErrorKind::SuperWithoutSuperclass => Code::SUPER_WITHOUT_SUPERCLASS,
+            ErrorKind::BreakOutsideLoop => Code::BREAK_OUTSIDE_LOOP,
+            ErrorKind::ContinueOutsideLoop => Code::CONTINUE_OUTSIDE_LOOP,
+        }
+    }
+}
+
+struct Local {
+    /// Whether the initializer of the variable has finished.
+    is_defined: bool,
+    span: Span,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassKind {
+    None,
+    Class,
+    Subclass,
+}
+
+impl Resolver {
+    pub(crate) fn new() -> Resolver {
+        Resolver {
+            scopes: vec![],
+            function: FunctionKind::None,
+            class: ClassKind::None,
+            in_loop: false,
+            errors: vec![],
+        }
+    }
+
+    pub(crate) fn resolve(mut self, statements: &[Stmt]) -> Result<(), Vec<Error>> {
+        self.resolve_statements(statements);
+
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+
+    fn resolve_statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.resolve_statement(statement);
+        }
+    }
+
+    fn resolve_statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Expression(expr) | Stmt::Print(expr) => self.resolve_expression(expr),
+            Stmt::Var { name, initializer } => {
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.resolve_expression(initializer);
+                }
+                self.define(name);
+            }
+            Stmt::Block(statements) => {
+                self.begin_scope();
+                self.resolve_statements(statements);
+                self.end_scope();
+            }
+            Stmt::If { condition, then_branch, else_branch } => {
+                self.resolve_expression(condition);
+                self.resolve_statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_statement(else_branch);
+                }
+            }
+            Stmt::While { condition, body, increment } => {
+                self.resolve_expression(condition);
+                let enclosing_loop = std::mem::replace(&mut self.in_loop, true);
+                self.resolve_statement(body);
+                self.in_loop = enclosing_loop;
+                if let Some(increment) = increment {
+                    self.resolve_expression(increment);
+                }
+            }
+            Stmt::Function(function) => {
+                // Defined before its body so it can call itself
+                self.declare(&function.name);
+                self.define(&function.name);
+                self.resolve_function(function, FunctionKind::Function);
+            }
+            Stmt::Class { name, superclass, methods } => {
+                let enclosing_class = self.class;
+                self.class = ClassKind::Class;
+                self.declare(name);
+                self.define(name);
+
+                if let Some(superclass) = superclass {
+                    self.class = ClassKind::Subclass;
+                    self.resolve_variable(superclass);
+                    self.begin_scope();
+                    self.define_implicit("super", superclass.name.span);
+                }
+
+                self.begin_scope();
+                self.define_implicit("this", name.span);
+                for method in methods {
+                    let kind = if method.name.name == "init" {
+                        FunctionKind::Initializer
+                    } else {
+                        FunctionKind::Method
+                    };
+                    self.resolve_function(method, kind);
+                }
+                self.end_scope();
+
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+                self.class = enclosing_class;
+            }
+            Stmt::Return { keyword, value } => {
+                if self.function == FunctionKind::None {
+                    self.error(ErrorKind::ReturnFromTopLevel, *keyword);
+                }
+                if let Some(value) = value {
+                    if self.function == FunctionKind::Initializer {
+                        self.error(ErrorKind::ReturnValueFromInitializer, *keyword);
+                    }
+                    self.resolve_expression(value);
+                }
+            }
+            Stmt::Break { keyword } => {
+                if !self.in_loop {
+                    self.error(ErrorKind::BreakOutsideLoop, *keyword);
+                }
+            }
+            Stmt::Continue { keyword } => {
+                if !self.in_loop {
+                    self.error(ErrorKind::ContinueOutsideLoop, *keyword);
+                }
+            }
+        }
+    }
+
+    fn resolve_function(&mut self, function: &Function, kind: FunctionKind) {
+        let enclosing_function = self.function;
+        self.function = kind;
+        // Loops around the declaration can't be stopped from inside
+        let enclosing_loop = std::mem::replace(&mut self.in_loop, false);
+
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve_statements(&function.body);
+        self.end_scope();
+
+        self.function = enclosing_function;
+        self.in_loop = enclosing_loop;
+    }
+
+    fn resolve_expression(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Literal(_) => (),
+            Expr::Grouping(expr) => self.resolve_expression(expr),
+            Expr::Unary { right, .. } => self.resolve_expression(right),
+            Expr::Binary { left, right, .. } | Expr::Logical { left, right, .. } => {
+                self.resolve_expression(left);
+                self.resolve_expression(right);
+            }
+            Expr::Variable(variable) => {
+                let name = &variable.name;
+                let local = self.scopes.last().and_then(|scope| scope.get(&name.name));
+                if local.is_some_and(|local| !local.is_defined) {
+                    self.error(ErrorKind::ReadInOwnInitializer, name.span);
+                }
+                self.resolve_variable(variable);
+            }
+            Expr::Assign { variable, value } => {
+                self.resolve_expression(value);
+                self.resolve_variable(variable);
+            }
+            Expr::Call { callee, arguments, .. } => {
+                self.resolve_expression(callee);
+                for argument in arguments {
+                    self.resolve_expression(argument);
+                }
+            }
+            Expr::Get { object, .. } => self.resolve_expression(object),
+            Expr::List(elements) => {
+                for element in elements {
+                    self.resolve_expression(element);
+                }
+            }
+            Expr::Conditional { condition, then_branch, else_branch } => {
+                self.resolve_expression(condition);
+                self.resolve_expression(then_branch);
+                self.resolve_expression(else_branch);
+            }
+            Expr::Function(function) => self.resolve_function(function, FunctionKind::Function),
+            Expr::Map { entries, .. } => {
+                for (key, value) in entries {
+                    self.resolve_expression(key);
+                    self.resolve_expression(value);
+                }
+            }
+            Expr::Index { object, index, .. } => {
+                self.resolve_expression(object);
+                self.resolve_expression(index);
+            }
+            Expr::SetIndex { object, index, value, .. } => {
+                self.resolve_expression(object);
+                self.resolve_expression(index);
+                self.resolve_expression(value);
+            }
+            Expr::Set { object, value, .. } => {
+                self.resolve_expression(value);
+                self.resolve_expression(object);
+            }
+            Expr::This(keyword) => {
+                if self.class == ClassKind::None {
+                    self.error(ErrorKind::ThisOutsideClass, keyword.name.span);
+                } else {
+                    self.resolve_variable(keyword);
+                }
+            }
+            Expr::Super { keyword, .. } => {
+                match self.class {
+                    ClassKind::None => self.error(ErrorKind::SuperOutsideClass, keyword.name.span),
+                    ClassKind::Class => self.error(ErrorKind::SuperWithoutSuperclass, keyword.name.span),
+                    ClassKind::Subclass => (),
+                }
+                self.resolve_variable(keyword);
+            }
+        }
+    }
+
+    /// Leaves globals unresolved, as they are looked up by name.
+    fn resolve_variable(&mut self, variable: &Variable) {
+        let depth = self.scopes.iter().rev().position(|scope| scope.contains_key(&variable.name.name));
+        variable.depth.set(depth);
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    /// Adds a local variable that can't be read until defined.
+    fn declare(&mut self, name: &Identifier) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+        let local = Local { is_defined: false, span: name.span };
+        if let Some(previous) = scope.insert(name.name.clone(), local) {
+            let error = Error { kind: ErrorKind::AlreadyDeclared, span: name.span, previous: Some(previous.span) };
+            self.errors.push(error);
+        }
+    }
+
+    fn define(&mut self, name: &Identifier) {
+        if let Some(local) = self.scopes.last_mut().and_then(|scope| scope.get_mut(&name.name)) {
+            local.is_defined = true;
+        }
+    }
+
+    /// Defines `this` and `super`, which are not declared in the code.
+    ///
+    /// `span` is what introduced them, like the class declaration.
+    fn define_implicit(&mut self, name: &str, span: Span) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(Symbol::intern(name), Local { is_defined: true, span });
+        }
+    }
+
+    fn error(&mut self, kind: ErrorKind, span: Span) {
+        self.errors.push(Error { kind, span, previous: None });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::parser::Parser;
+    use crate::interpreter::Scanner;
+
+    use super::*;
+
+    fn error_kinds(source: &str) -> Vec<ErrorKind> {
+        let tokens = Scanner::new(source).scan_tokens();
+        let statements = Parser::new(tokens, source.len()).parse().unwrap();
+        let errors = Resolver::new().resolve(&statements).expect_err("Resolved invalid code");
+        errors.into_iter().map(|error| error.kind).collect()
+    }
+
+    #[test]
+    fn reports_reading_local_in_its_own_initializer() {
+        assert_eq!(error_kinds("{ var a = a; }"), &[ErrorKind::ReadInOwnInitializer]);
+    }
+
+    #[test]
+    fn reports_redeclared_locals() {
+        assert_eq!(error_kinds("{ var a = 1; var a = 2; }"), &[ErrorKind::AlreadyDeclared]);
+        assert_eq!(error_kinds("fun f(a, a) {}"), &[ErrorKind::AlreadyDeclared]);
+    }
+
+    #[test]
+    fn reports_misplaced_returns() {
+        assert_eq!(error_kinds("return 1;"), &[ErrorKind::ReturnFromTopLevel]);
+        assert_eq!(error_kinds("class A { init() { return 1; } }"), &[ErrorKind::ReturnValueFromInitializer]);
+    }
+
+    #[test]
+    fn reports_this_and_super_outside_classes() {
+        assert_eq!(error_kinds("print this;"), &[ErrorKind::ThisOutsideClass]);
+        assert_eq!(error_kinds("fun f() { super.g(); }"), &[ErrorKind::SuperOutsideClass]);
+        assert_eq!(error_kinds("class A { f() { super.f(); } }"), &[ErrorKind::SuperWithoutSuperclass]);
+    }
+
+    #[test]
+    fn reports_break_and_continue_outside_loops() {
+        assert_eq!(error_kinds("break;"), &[ErrorKind::BreakOutsideLoop]);
+        assert_eq!(error_kinds("if (true) { continue; }"), &[ErrorKind::ContinueOutsideLoop]);
+        assert_eq!(error_kinds("while (true) { fun f() { break; } }"), &[ErrorKind::BreakOutsideLoop]);
+    }
+}
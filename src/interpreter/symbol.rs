@@ -0,0 +1,130 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// Unused strings are only purged from the interner once it has this many.
+const MIN_PURGE: usize = 1024;
+
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner { strings: HashSet::new(), next_purge: MIN_PURGE });
+}
+
+/// An interned string, such as a name or the contents of a string literal.
+///
+/// Equal symbols share one allocation, so they are compared and hashed by
+/// address instead of by contents.
+#[derive(Clone)]
+pub(crate) struct Symbol(Rc<str>);
+
+/// Every string that was interned on this thread and may still be in use.
+struct Interner {
+    strings: HashSet<Rc<str>>,
+    /// Size at which strings that no symbol uses anymore are dropped.
+    next_purge: usize,
+}
+
+impl Symbol {
+    /// Returns the symbol for `s`, which is the same as any other symbol for
+    /// an equal string while either is alive.
+    pub(crate) fn intern(s: &str) -> Symbol {
+        INTERNER.with_borrow_mut(|interner| Symbol(interner.intern(s)))
+    }
+}
+
+impl Interner {
+    fn intern(&mut self, s: &str) -> Rc<str> {
+        if let Some(string) = self.strings.get(s) {
+            return Rc::clone(string);
+        }
+
+        if self.strings.len() >= self.next_purge {
+            // Strings that only the interner refers to are not in any symbol
+            self.strings.retain(|string| Rc::strong_count(string) > 1);
+            self.next_purge = (self.strings.len() * 2).max(MIN_PURGE);
+        }
+        let string: Rc<str> = Rc::from(s);
+        self.strings.insert(Rc::clone(&string));
+        string
+    }
+}
+
+impl std::ops::Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl std::hash::Hash for Symbol {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::ptr::hash(Rc::as_ptr(&self.0).cast::<u8>(), state);
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
+
+impl From<Symbol> for Rc<str> {
+    fn from(symbol: Symbol) -> Rc<str> {
+        symbol.0
+    }
+}
+
+impl std::fmt::Display for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_equal_strings() {
+        let a = Symbol::intern("name");
+        let b = Symbol::intern(&String::from("name"));
+
+        assert_eq!(a, b);
+        assert!(Rc::ptr_eq(&a.0, &b.0));
+        assert_ne!(a, Symbol::intern("other"));
+        assert_eq!(a, "name");
+    }
+
+    #[test]
+    fn purges_unused_strings() {
+        let kept = Symbol::intern("kept");
+        for n in 0..MIN_PURGE * 2 {
+            Symbol::intern(&n.to_string());
+        }
+
+        let size = INTERNER.with_borrow(|interner| interner.strings.len());
+
+        assert!(size < MIN_PURGE * 2);
+        assert_eq!(Symbol::intern("kept"), kept);
+    }
+}
@@ -0,0 +1,90 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use super::{Backend, Error, Lox, Natives, OptLevel};
+
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Output shared with a `Lox` so tests can read what was printed.
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs in a thread with a big stack, as deeply recursive code needs it.
+fn run_capturing(source: &str, backend: Backend, level: OptLevel) -> (String, Result<(), Error>) {
+    let source = source.to_string();
+    let thread = std::thread::Builder::new().stack_size(STACK_SIZE).spawn(move || {
+        let output = Output::default();
+        let mut lox = Lox::with_backend(Box::new(output.clone()), &Natives::new(), backend);
+        lox.set_gc_stress(true);
+        lox.set_opt_level(level);
+        let result = lox.run(&source);
+        let printed = String::from_utf8(output.0.take()).unwrap();
+
+        (printed, result)
+    });
+
+    thread.unwrap().join().unwrap()
+}
+
+/// Runs `source` and returns what it printed, panicking on errors.
+pub(crate) fn run(source: &str) -> String {
+    run_on(source, Backend::TreeWalker, OptLevel::default())
+}
+
+/// Like `run`, but on the bytecode VM, collecting garbage as often as
+/// possible.
+pub(crate) fn run_vm(source: &str) -> String {
+    run_on(source, Backend::Vm, OptLevel::default())
+}
+
+/// Like `run_vm`, but compiling without optimizations.
+pub(crate) fn run_vm_unoptimized(source: &str) -> String {
+    run_on(source, Backend::Vm, OptLevel::O0)
+}
+
+fn run_on(source: &str, backend: Backend, level: OptLevel) -> String {
+    let (printed, result) = run_capturing(source, backend, level);
+    if let Err(error) = result {
+        panic!("Failed to run code: {error:?}\nPrinted: {printed:?}");
+    }
+    printed
+}
+
+/// Runs `source` expecting it to fail and returns each diagnostic as
+/// `line:column: message`, joined by newlines.
+pub(crate) fn run_err(source: &str) -> String {
+    run_err_on(source, Backend::TreeWalker, OptLevel::default())
+}
+
+/// Like `run_err`, but on the bytecode VM.
+pub(crate) fn run_vm_err(source: &str) -> String {
+    run_err_on(source, Backend::Vm, OptLevel::default())
+}
+
+/// Like `run_vm_err`, but compiling without optimizations.
+pub(crate) fn run_vm_err_unoptimized(source: &str) -> String {
+    run_err_on(source, Backend::Vm, OptLevel::O0)
+}
+
+fn run_err_on(source: &str, backend: Backend, level: OptLevel) -> String {
+    let Err(error) = run_capturing(source, backend, level).1 else {
+        panic!("Code ran without errors");
+    };
+
+    let diagnostics = error.diagnostics().iter().map(|diagnostic| {
+        let (line, column) = diagnostic.span.location(source);
+        format!("{line}:{column}: {}", diagnostic.message)
+    });
+    diagnostics.collect::<Vec<_>>().join("\n")
+}
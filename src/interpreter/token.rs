@@ -1,5 +1,10 @@
+use super::code::Code;
+use super::symbol::Symbol;
+
+#[derive(Clone)]
 pub(crate) struct Token {
     pub(crate) r#type: Type,
+    pub(crate) span: Span,
 }
 
 impl PartialEq for Token {
@@ -14,13 +19,43 @@ impl std::fmt::Debug for Token {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[cfg(test)]
+impl From<Type> for Token {
+    fn from(r#type: Type) -> Self {
+        Token { r#type, span: Span::default() }
+    }
+}
+
+/// Byte range of a lexeme in the source code, excluding `end`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct Span {
+    pub(crate) start: usize,
+    pub(crate) end: usize,
+}
+
+impl Span {
+    /// Returns the 1-based line and column where the span starts, with
+    /// columns counted in characters.
+    pub(crate) fn location(&self, source: &str) -> (usize, usize) {
+        let before = &source[..self.start.min(source.len())];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        let line = before.matches('\n').count() + 1;
+        let column = before[line_start..].chars().count() + 1;
+
+        (line, column)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub(crate) enum Type {
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
@@ -31,34 +66,40 @@ pub(crate) enum Type {
     BangEqual,
     Equal,
     EqualEqual,
+    /// `=>` of arrow functions.
+    Arrow,
     Greater,
     GreaterEqual,
     Less,
     LessEqual,
+    Question,
+    QuestionQuestion,
 
     Slash,
     SlashSlash,  // Only for internal use
     Whitespace,  // Only for internal use
 
-    StringLiteral(String),
+    StringLiteral(Symbol),
     NumberLiteral(NumberLiteral),
 
     Error(Error),
 
     Keyword(Keyword),
-    Identifier(String),
+    Identifier(Symbol),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub(crate) enum NumberLiteral {
     Integer(i32),
     Float(f64),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub(crate) enum Keyword {
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     For,
@@ -75,7 +116,26 @@ pub(crate) enum Keyword {
     While,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub(crate) enum Error {
     UnterminatedString,
+    UnexpectedCharacter,
+}
+
+impl Error {
+    pub(crate) fn code(&self) -> Code {
+        match self {
+            Error::UnterminatedString => Code::UNTERMINATED_STRING,
+            Error::UnexpectedCharacter => Code::UNEXPECTED_CHARACTER,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnterminatedString => write!(f, "Unterminated string."),
+            Error::UnexpectedCharacter => write!(f, "Unexpected character."),
+        }
+    }
 }
@@ -0,0 +1,1384 @@
+mod chunk;
+mod compiler;
+mod disassembler;
+mod file;
+mod heap;
+mod object;
+mod optimizer;
+mod shape;
+mod value;
+
+pub(crate) use compiler::compile;
+pub(crate) use compiler::Error;
+pub(crate) use disassembler::disassemble;
+pub use file::LoadError;
+pub(crate) use file::Program;
+pub(crate) use heap::{Heap, ObjRef};
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::atomic::{self, AtomicU64};
+
+use chunk::OpCode;
+use object::{BoundMethod, Cache, Class, Closure, Instance, ListMethod, MapMethod, Object, Upvalue};
+use shape::Shapes;
+use value::Value;
+
+use super::diagnostic::Frame;
+use super::evaluator::{self, ErrorKind, MAX_DEPTH};
+use super::list::{self, Return};
+use super::map::{self, Key};
+use super::native::{self, Natives};
+use super::OptLevel;
+use super::token::Span;
+
+/// Runs bytecode compiled from Lox code.
+///
+/// Runtime errors are the same as those of the tree-walker.
+pub(crate) struct Vm {
+    out: Box<dyn Write>,
+    heap: Heap,
+    /// Values being operated on, and locals of the functions being called.
+    stack: Vec<Value>,
+    /// Functions being called, innermost last.
+    frames: Vec<CallFrame>,
+    globals: HashMap<ObjRef, Value>,
+    /// Upvalues of locals still on the stack, by increasing slot.
+    open_upvalues: Vec<ObjRef>,
+    /// Name of initializers, interned once.
+    init: ObjRef,
+    opt_level: OptLevel,
+    /// Tells the objects given to natives apart from those of other VMs.
+    id: u64,
+    /// How many times natives were called, to tell apart the calls that
+    /// objects were given to.
+    native_calls: u64,
+}
+
+/// What a property of an instance is.
+enum Property {
+    Field(Value),
+    Method(ObjRef),
+}
+
+struct CallFrame {
+    closure: ObjRef,
+    /// Function of `closure`, to not look it up for every instruction.
+    function: ObjRef,
+    /// Offset of the next instruction to run.
+    ip: usize,
+    /// Position of the first slot of the frame in the stack, which holds
+    /// the function being called.
+    slots: usize,
+}
+
+type Result<T = ()> = std::result::Result<T, evaluator::Error>;
+
+/// How many VMs were made, to tell their objects apart.
+static VMS: AtomicU64 = AtomicU64::new(0);
+
+impl Vm {
+    /// `print` statements write to `out`.
+    pub(crate) fn new(out: Box<dyn Write>, natives: &Natives) -> Vm {
+        let mut heap = Heap::default();
+        let init = heap.intern("init");
+        let mut vm = Vm {
+            out,
+            heap,
+            stack: vec![],
+            frames: vec![],
+            globals: HashMap::new(),
+            open_upvalues: vec![],
+            init,
+            opt_level: OptLevel::default(),
+            id: VMS.fetch_add(1, atomic::Ordering::Relaxed),
+            native_calls: 0,
+        };
+        for native in natives.iter() {
+            let name = vm.heap.intern(&native.name);
+            let function = vm.heap.alloc(Object::Native(Rc::clone(native)));
+            vm.globals.insert(name, Value::object(function));
+        }
+        vm
+    }
+
+    /// Makes the VM collect garbage before every allocation.
+    pub(crate) fn set_gc_stress(&mut self, stress: bool) {
+        self.heap.set_stress(stress);
+    }
+
+    pub(crate) fn set_opt_level(&mut self, level: OptLevel) {
+        self.opt_level = level;
+    }
+
+    /// Compiles the code of `source` from byte `start` on into the heap of
+    /// the VM.
+    pub(crate) fn compile(
+        &mut self,
+        source: &str,
+        start: usize,
+    ) -> std::result::Result<ObjRef, Vec<super::Diagnostic>> {
+        let roots = self.roots();
+        compile(source, start, &mut self.heap, roots, self.opt_level)
+    }
+
+    /// Loads a compiled program into the heap of the VM, returning its
+    /// script.
+    pub(crate) fn load(&mut self, program: &Program) -> ObjRef {
+        program.load(&mut self.heap)
+    }
+
+    /// Runs a script returned by `compile` or `load`.
+    pub(crate) fn interpret(&mut self, script: ObjRef) -> Result {
+        let closure = self.alloc(Object::Closure(Closure { function: script, upvalues: vec![] }));
+        self.stack.push(Value::object(closure));
+        self.frames.push(CallFrame { closure, function: script, ip: 0, slots: 0 });
+
+        let result = self.run(0);
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result
+    }
+
+    /// Runs until the frames are back to `base`, leaving the result of the
+    /// last return on the stack unless that was the script's.
+    fn run(&mut self, base: usize) -> Result {
+        loop {
+            let byte = self.read_byte();
+            let opcode = OpCode::try_from(byte).unwrap_or_else(|byte| panic!("Unknown opcode {byte}"));
+            match opcode {
+                OpCode::Constant => {
+                    let constant = self.read_constant();
+                    self.stack.push(constant);
+                }
+                OpCode::Nil => self.stack.push(Value::NIL),
+                OpCode::True => self.stack.push(Value::bool(true)),
+                OpCode::False => self.stack.push(Value::bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::PopN => {
+                    let count = usize::from(self.read_byte());
+                    self.stack.truncate(self.stack.len() - count);
+                }
+                OpCode::GetLocal => {
+                    let slot = self.frame().slots + usize::from(self.read_byte());
+                    self.stack.push(self.stack[slot]);
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().slots + usize::from(self.read_byte());
+                    self.stack[slot] = self.peek(0);
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_string();
+                    match self.globals.get(&name) {
+                        Some(&value) => self.stack.push(value),
+                        None => return Err(self.undefined_variable(name)),
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_string();
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_string();
+                    let value = self.peek(0);
+                    match self.globals.get_mut(&name) {
+                        Some(global) => *global = value,
+                        None => return Err(self.undefined_variable(name)),
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let index = self.read_byte();
+                    let upvalue = self.upvalue(index);
+                    let value = match self.heap.get(upvalue) {
+                        Object::Upvalue(Upvalue::Open(slot)) => self.stack[*slot],
+                        Object::Upvalue(Upvalue::Closed(value)) => *value,
+                        object => panic!("Expected an upvalue, found {object:?}"),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = self.read_byte();
+                    let upvalue = self.upvalue(index);
+                    let value = self.peek(0);
+                    match self.heap.get_mut(upvalue) {
+                        Object::Upvalue(Upvalue::Open(slot)) => self.stack[*slot] = value,
+                        Object::Upvalue(Upvalue::Closed(closed)) => *closed = value,
+                        object => panic!("Expected an upvalue, found {object:?}"),
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_string();
+                    let cache = self.read_short();
+                    match self.peek(0).as_object() {
+                        Some(list) if self.is_list(list) => {
+                            let Some(method) = list::Method::find(self.heap.string(name)) else {
+                                return Err(self.undefined_property(name));
+                            };
+                            let method = self.alloc(Object::ListMethod(ListMethod { list, method }));
+                            self.pop();
+                            self.stack.push(Value::object(method));
+                        }
+                        Some(map) if self.is_map(map) => {
+                            let Some(method) = map::Method::find(self.heap.string(name)) else {
+                                return Err(self.undefined_property(name));
+                            };
+                            let method = self.alloc(Object::MapMethod(MapMethod { map, method }));
+                            self.pop();
+                            self.stack.push(Value::object(method));
+                        }
+                        Some(instance) if self.is_instance(instance) => match self.property(instance, name, cache) {
+                            Some(Property::Field(value)) => {
+                                self.pop();
+                                self.stack.push(value);
+                            }
+                            Some(Property::Method(method)) => {
+                                let receiver = self.pop();
+                                let bound = self.alloc(Object::BoundMethod(BoundMethod { receiver, method }));
+                                self.stack.push(Value::object(bound));
+                            }
+                            None => return Err(self.undefined_property(name)),
+                        },
+                        _ => return Err(self.error(ErrorKind::NotAnInstance)),
+                    }
+                }
+                OpCode::SetProperty => {
+                    let name = self.read_string();
+                    let cache = self.read_short();
+                    let Some(instance) = self.peek(1).as_object().filter(|&object| self.is_instance(object)) else {
+                        return Err(self.error(ErrorKind::FieldsOnNonInstance));
+                    };
+                    let value = self.pop();
+                    self.set_field(instance, name, value, cache);
+                    self.pop();
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name = self.read_string();
+                    let Some(superclass) = self.pop().as_object() else {
+                        unreachable!("Compiler checks that `super` is a class");
+                    };
+                    self.bind_method(superclass, name)?;
+                }
+                OpCode::BuildList => {
+                    let count = usize::from(self.read_byte());
+                    let elements = self.stack[self.stack.len() - count..].to_vec();
+                    let list = self.alloc(Object::List(elements));
+                    self.stack.truncate(self.stack.len() - count);
+                    self.stack.push(Value::object(list));
+                }
+                OpCode::BuildMap => {
+                    let count = usize::from(self.read_byte());
+                    let mut map = map::Map::default();
+                    for entry in self.stack[self.stack.len() - 2 * count..].chunks(2) {
+                        let key = self.key(entry[0]).ok_or_else(|| self.error(ErrorKind::InvalidKey))?;
+                        map.insert(key, entry[1]);
+                    }
+                    let map = self.alloc(Object::Map(map));
+                    self.stack.truncate(self.stack.len() - 2 * count);
+                    self.stack.push(Value::object(map));
+                }
+                OpCode::GetIndex => {
+                    let value = match self.peek(1).as_object().map(|object| self.heap.get(object)) {
+                        Some(Object::Map(map)) => {
+                            let key = self.key(self.peek(0)).ok_or_else(|| self.error(ErrorKind::InvalidKey))?;
+                            map.get(&key).copied().ok_or_else(|| self.undefined_key(self.peek(0)))?
+                        }
+                        _ => {
+                            let (list, index) = self.element(self.peek(1), self.peek(0))?;
+                            let Object::List(elements) = self.heap.get(list) else {
+                                unreachable!("Elements are in lists");
+                            };
+                            elements[index]
+                        }
+                    };
+                    self.pop();
+                    self.pop();
+                    self.stack.push(value);
+                }
+                OpCode::SetIndex => {
+                    let value = self.peek(0);
+                    match self.peek(2).as_object() {
+                        Some(map) if self.is_map(map) => {
+                            let key = self.key(self.peek(1)).ok_or_else(|| self.error(ErrorKind::InvalidKey))?;
+                            let Object::Map(map) = self.heap.get_mut(map) else {
+                                unreachable!("Checked that it is a map");
+                            };
+                            map.insert(key, value);
+                        }
+                        _ => {
+                            let (list, index) = self.element(self.peek(2), self.peek(1))?;
+                            let Object::List(elements) = self.heap.get_mut(list) else {
+                                unreachable!("Elements are in lists");
+                            };
+                            elements[index] = value;
+                        }
+                    }
+                    self.stack.truncate(self.stack.len() - 3);
+                    self.stack.push(value);
+                }
+                OpCode::Equal => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.stack.push(Value::bool(a == b));
+                }
+                OpCode::Greater => self.binary(|a, b| Value::bool(a > b))?,
+                OpCode::GreaterEqual => self.binary(|a, b| Value::bool(a >= b))?,
+                OpCode::Less => self.binary(|a, b| Value::bool(a < b))?,
+                OpCode::LessEqual => self.binary(|a, b| Value::bool(a <= b))?,
+                OpCode::NotEqual => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.stack.push(Value::bool(a != b));
+                }
+                // Unlike `<=` and `>=`, true when comparing NaNs
+                OpCode::NotGreater => self.binary(|a, b| Value::bool(a.partial_cmp(&b) != Some(Ordering::Greater)))?,
+                OpCode::NotLess => self.binary(|a, b| Value::bool(a.partial_cmp(&b) != Some(Ordering::Less)))?,
+                OpCode::Add => self.add()?,
+                OpCode::Subtract => self.binary(|a, b| Value::number(a - b))?,
+                OpCode::Multiply => self.binary(|a, b| Value::number(a * b))?,
+                OpCode::Divide => self.binary(|a, b| Value::number(a / b))?,
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::bool(value.is_falsey()));
+                }
+                OpCode::Negate => {
+                    let Some(n) = self.peek(0).as_number() else {
+                        return Err(self.error(ErrorKind::OperandMustBeNumber));
+                    };
+                    self.pop();
+                    self.stack.push(Value::number(-n));
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    // Nothing sensible to do if the output is gone
+                    let _ = writeln!(self.out, "{}", value.display(&self.heap));
+                }
+                OpCode::Jump => {
+                    let jump = self.read_short();
+                    self.frame_mut().ip += jump;
+                }
+                OpCode::JumpIfFalse => {
+                    let jump = self.read_short();
+                    if self.peek(0).is_falsey() {
+                        self.frame_mut().ip += jump;
+                    }
+                }
+                OpCode::JumpIfNil => {
+                    let jump = self.read_short();
+                    if self.peek(0).is_nil() {
+                        self.frame_mut().ip += jump;
+                    }
+                }
+                OpCode::Loop => {
+                    let jump = self.read_short();
+                    self.frame_mut().ip -= jump;
+                }
+                OpCode::Call => {
+                    let count = usize::from(self.read_byte());
+                    self.call_value(self.peek(count), count)?;
+                }
+                OpCode::Invoke => {
+                    let name = self.read_string();
+                    let cache = self.read_short();
+                    let count = usize::from(self.read_byte());
+                    self.invoke(name, cache, count)?;
+                }
+                OpCode::Closure => {
+                    let Some(function) = self.read_constant().as_object() else {
+                        unreachable!("Closures are made of functions");
+                    };
+                    let upvalues = (0..self.heap.function(function).upvalue_count)
+                        .map(|_| {
+                            let is_local = self.read_byte() == 1;
+                            let index = self.read_byte();
+                            if is_local {
+                                self.capture_upvalue(self.frame().slots + usize::from(index))
+                            } else {
+                                self.upvalue(index)
+                            }
+                        })
+                        .collect();
+                    let closure = self.alloc(Object::Closure(Closure { function, upvalues }));
+                    self.stack.push(Value::object(closure));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("Returns are from a frame");
+                    self.close_upvalues(frame.slots);
+                    self.stack.truncate(frame.slots);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                    if self.frames.len() == base {
+                        return Ok(());
+                    }
+                }
+                OpCode::Class => {
+                    let name = self.read_string();
+                    let class = self.alloc(Object::Class(Class { name, methods: HashMap::new() }));
+                    self.stack.push(Value::object(class));
+                }
+                OpCode::Inherit => {
+                    let superclass = match self.peek(1).as_object().map(|superclass| self.heap.get(superclass)) {
+                        Some(Object::Class(superclass)) => superclass.methods.clone(),
+                        _ => return Err(self.error(ErrorKind::SuperclassMustBeClass)),
+                    };
+                    let Some(subclass) = self.pop().as_object() else {
+                        unreachable!("Classes inherit right after being declared");
+                    };
+                    let Object::Class(subclass) = self.heap.get_mut(subclass) else {
+                        unreachable!("Classes inherit right after being declared");
+                    };
+                    subclass.methods.extend(superclass);
+                }
+                OpCode::Method => {
+                    let name = self.read_string();
+                    let (Some(class), Some(method)) = (self.peek(1).as_object(), self.peek(0).as_object()) else {
+                        unreachable!("Methods are defined on the class being declared");
+                    };
+                    let Object::Class(class) = self.heap.get_mut(class) else {
+                        unreachable!("Methods are defined on the class being declared");
+                    };
+                    class.methods.insert(name, method);
+                    self.pop();
+                }
+            }
+        }
+    }
+
+    /// Calls `callee`, which is below its `count` arguments on the stack.
+    fn call_value(&mut self, callee: Value, count: usize) -> Result {
+        let Some(callee) = callee.as_object() else {
+            return Err(self.error(ErrorKind::NotCallable));
+        };
+        let callee_slot = self.stack.len() - count - 1;
+        match self.heap.get(callee) {
+            Object::Closure(_) => self.call(callee, count),
+            Object::BoundMethod(bound) => {
+                let method = bound.method;
+                self.stack[callee_slot] = bound.receiver;
+                self.call(method, count)
+            }
+            Object::Class(class) => {
+                let initializer = class.methods.get(&self.init).copied();
+                let instance = Instance { class: callee, shape: Shapes::EMPTY, fields: vec![] };
+                self.stack[callee_slot] = Value::object(self.alloc(Object::Instance(instance)));
+                match initializer {
+                    Some(initializer) => self.call(initializer, count),
+                    None if count != 0 => Err(self.error(ErrorKind::WrongArity { expected: 0, got: count })),
+                    None => Ok(()),
+                }
+            }
+            Object::ListMethod(ListMethod { list, method }) => self.call_list_method(*list, *method, count),
+            Object::MapMethod(MapMethod { map, method }) => self.call_map_method(*map, *method, count),
+            Object::Native(native) => {
+                let native = Rc::clone(native);
+                if count != native.arity {
+                    let kind = ErrorKind::WrongArity { expected: native.arity, got: count };
+                    return Err(self.error(kind));
+                }
+                if self.frames.len() > MAX_DEPTH {
+                    return Err(self.error(ErrorKind::StackOverflow));
+                }
+
+                self.native_calls += 1;
+                let arguments: Vec<native::Value> =
+                    self.stack[callee_slot + 1..].iter().map(|&argument| self.to_native(argument)).collect();
+                match (native.body)(&arguments) {
+                    Ok(result) => {
+                        let Some(result) = self.native_result(result) else {
+                            return Err(self.native_error(ErrorKind::InvalidObject, &native.name));
+                        };
+                        self.stack.truncate(callee_slot);
+                        self.stack.push(result);
+                        Ok(())
+                    }
+                    Err(message) => Err(self.native_error(ErrorKind::Native(message), &native.name)),
+                }
+            }
+            _ => Err(self.error(ErrorKind::NotCallable)),
+        }
+    }
+
+    /// Pushes a frame for `closure`, which is below its `count` arguments on
+    /// the stack.
+    fn call(&mut self, closure: ObjRef, count: usize) -> Result {
+        let function = self.heap.closure(closure).function;
+        let arity = self.heap.function(function).arity;
+        if count != arity {
+            return Err(self.error(ErrorKind::WrongArity { expected: arity, got: count }));
+        }
+        // The script has a frame too
+        if self.frames.len() > MAX_DEPTH {
+            return Err(self.error(ErrorKind::StackOverflow));
+        }
+
+        let slots = self.stack.len() - count - 1;
+        self.frames.push(CallFrame { closure, function, ip: 0, slots });
+        Ok(())
+    }
+
+    /// Runs `method` on `list`, which is below its `count` arguments on the
+    /// stack, like a native function.
+    fn call_list_method(&mut self, list: ObjRef, method: list::Method, count: usize) -> Result {
+        if count != method.arity() {
+            return Err(self.error(ErrorKind::WrongArity { expected: method.arity(), got: count }));
+        }
+        if self.frames.len() > MAX_DEPTH {
+            return Err(self.error(ErrorKind::StackOverflow));
+        }
+
+        if let list::Method::Map | list::Method::Filter = method {
+            return self.map_list(list, method);
+        }
+        let arguments = self.stack[self.stack.len() - count..].to_vec();
+        let Object::List(elements) = self.heap.get_mut(list) else {
+            unreachable!("List methods are read from lists");
+        };
+        let result = match method.call(elements, &arguments, |argument| argument.as_number()) {
+            Ok(Return::Nil) => Value::NIL,
+            Ok(Return::Number(n)) => Value::number(n),
+            Ok(Return::Element(element)) => element,
+            Ok(Return::List(elements)) => Value::object(self.alloc(Object::List(elements))),
+            Err(kind) => return Err(self.native_error(kind, method.name())),
+        };
+        self.stack.truncate(self.stack.len() - count - 1);
+        self.stack.push(result);
+        Ok(())
+    }
+
+    /// Runs `map` or `filter` on `list`, calling the function on top of the
+    /// stack on each element, and replaces the call with the new list.
+    fn map_list(&mut self, list: ObjRef, method: list::Method) -> Result {
+        let callee_slot = self.stack.len() - 2;
+        let function = self.peek(0);
+        // On the stack, so that collections keep it
+        let results = self.alloc(Object::List(vec![]));
+        self.stack.push(Value::object(results));
+        // The function may change the list, so elements are read as they are reached
+        for index in 0.. {
+            let Object::List(elements) = self.heap.get(list) else {
+                unreachable!("List methods are read from lists");
+            };
+            let Some(&element) = elements.get(index) else {
+                break;
+            };
+            // Kept below the call too, in case the function drops its argument
+            self.stack.extend([element, function, element]);
+            let base = self.frames.len();
+            let mut called = self.call_value(function, 1);
+            if called.is_ok() && self.frames.len() > base {
+                called = self.run(base);
+            }
+            if let Err(mut error) = called {
+                // Like natives, the method shows in the trace below what it called
+                let at = error.trace.len() - base;
+                let frame = Frame { function: Some(method.name().to_string()), span: error.trace[at].span };
+                error.trace.insert(at, frame);
+                return Err(error);
+            }
+            let result = self.pop();
+            self.pop();
+            let result = match method {
+                list::Method::Filter if result.is_falsey() => continue,
+                list::Method::Filter => element,
+                _ => result,
+            };
+            let Object::List(results) = self.heap.get_mut(results) else {
+                unreachable!("Results are a list");
+            };
+            results.push(result);
+        }
+        self.stack.truncate(callee_slot);
+        self.stack.push(Value::object(results));
+        Ok(())
+    }
+
+    /// Runs `method` on `map`, which is below its `count` arguments on the
+    /// stack, like a native function.
+    fn call_map_method(&mut self, map: ObjRef, method: map::Method, count: usize) -> Result {
+        if count != method.arity() {
+            return Err(self.error(ErrorKind::WrongArity { expected: method.arity(), got: count }));
+        }
+        if self.frames.len() > MAX_DEPTH {
+            return Err(self.error(ErrorKind::StackOverflow));
+        }
+
+        let argument = (count == 1).then(|| self.peek(0));
+        let key = match argument.map(|argument| self.key(argument)) {
+            Some(None) => return Err(self.native_error(ErrorKind::InvalidKey, method.name())),
+            key => key.flatten(),
+        };
+        let Object::Map(entries) = self.heap.get_mut(map) else {
+            unreachable!("Map methods are read from maps");
+        };
+        let result = match method.call(entries, key.as_ref()) {
+            Some(map::Return::Bool(b)) => Value::bool(b),
+            Some(map::Return::Number(n)) => Value::number(n),
+            Some(map::Return::Value(value)) => value,
+            Some(map::Return::Keys(keys)) => {
+                Value::object(self.alloc(Object::List(keys.into_iter().map(Value::from).collect())))
+            }
+            Some(map::Return::Values(values)) => Value::object(self.alloc(Object::List(values))),
+            None => {
+                let key = argument.expect("Only removing a key can fail");
+                let kind = ErrorKind::UndefinedKey(key.display(&self.heap).to_string());
+                return Err(self.native_error(kind, method.name()));
+            }
+        };
+        self.stack.truncate(self.stack.len() - count - 1);
+        self.stack.push(result);
+        Ok(())
+    }
+
+    /// Builds an error of a native function or method named `function`,
+    /// which is the innermost call even without a frame.
+    fn native_error(&self, kind: ErrorKind, function: &str) -> evaluator::Error {
+        let mut error = self.error(kind);
+        let frame = Frame { function: Some(function.to_string()), span: error.span };
+        error.trace.insert(0, frame);
+        error
+    }
+
+    /// Calls the property `name` of the value below the `count` arguments on
+    /// top of the stack, like `GetProperty` then `Call` would, but without
+    /// binding methods.
+    fn invoke(&mut self, name: ObjRef, cache: usize, count: usize) -> Result {
+        // Property errors point at the name, before the argument count
+        if let Some(list) = self.peek(count).as_object().filter(|&object| self.is_list(object)) {
+            let Some(method) = list::Method::find(self.heap.string(name)) else {
+                let name = self.heap.string(name).to_string();
+                return Err(self.error_at(ErrorKind::UndefinedProperty(name), self.frame().ip - 2));
+            };
+            return self.call_list_method(list, method, count);
+        }
+        if let Some(map) = self.peek(count).as_object().filter(|&object| self.is_map(object)) {
+            let Some(method) = map::Method::find(self.heap.string(name)) else {
+                let name = self.heap.string(name).to_string();
+                return Err(self.error_at(ErrorKind::UndefinedProperty(name), self.frame().ip - 2));
+            };
+            return self.call_map_method(map, method, count);
+        }
+        let Some(instance) = self.peek(count).as_object().filter(|&object| self.is_instance(object)) else {
+            return Err(self.error_at(ErrorKind::NotAnInstance, self.frame().ip - 2));
+        };
+        match self.property(instance, name, cache) {
+            Some(Property::Field(value)) => {
+                let callee_slot = self.stack.len() - count - 1;
+                self.stack[callee_slot] = value;
+                self.call_value(value, count)
+            }
+            // The instance is already where the method expects `this`
+            Some(Property::Method(method)) => self.call(method, count),
+            None => {
+                let name = self.heap.string(name).to_string();
+                Err(self.error_at(ErrorKind::UndefinedProperty(name), self.frame().ip - 2))
+            }
+        }
+    }
+
+    /// Looks up the property `name` of `instance`, which is a field, or
+    /// else a method of its class, using and updating the cache at `cache`
+    /// in the function being run.
+    fn property(&mut self, instance: ObjRef, name: ObjRef, cache: usize) -> Option<Property> {
+        let Object::Instance(Instance { class, shape, fields }) = self.heap.get(instance) else {
+            unreachable!("Properties are looked up in instances");
+        };
+        let (class, shape) = (*class, *shape);
+        match self.heap.function(self.frame().function).caches[cache] {
+            Cache::Field { shape: cached, slot } if cached == shape => return Some(Property::Field(fields[slot])),
+            Cache::Method { class: cached_class, shape: cached_shape, method }
+                if cached_class == class && cached_shape == shape =>
+            {
+                return Some(Property::Method(method));
+            }
+            _ => (),
+        }
+
+        let (property, entry) = if let Some(slot) = self.heap.shapes.slot(shape, name) {
+            (Property::Field(fields[slot]), Cache::Field { shape, slot })
+        } else {
+            let Object::Class(Class { methods, .. }) = self.heap.get(class) else {
+                unreachable!("Methods are looked up in classes");
+            };
+            let method = *methods.get(&name)?;
+            (Property::Method(method), Cache::Method { class, shape, method })
+        };
+        self.set_cache(cache, entry);
+        Some(property)
+    }
+
+    /// Sets the field `name` of `instance`, adding it if it doesn't have it,
+    /// using and updating the cache at `cache` in the function being run.
+    fn set_field(&mut self, instance: ObjRef, name: ObjRef, value: Value, cache: usize) {
+        let Object::Instance(Instance { shape, fields, .. }) = self.heap.get(instance) else {
+            unreachable!("Fields are set on instances");
+        };
+        let (shape, length) = (*shape, fields.len());
+        let (slot, next) = match self.heap.function(self.frame().function).caches[cache] {
+            Cache::Field { shape: cached, slot } if cached == shape => (slot, shape),
+            Cache::Transition { from, to } if from == shape => (length, to),
+            _ => {
+                if let Some(slot) = self.heap.shapes.slot(shape, name) {
+                    self.set_cache(cache, Cache::Field { shape, slot });
+                    (slot, shape)
+                } else {
+                    let next = self.heap.shapes.with_field(shape, name);
+                    self.set_cache(cache, Cache::Transition { from: shape, to: next });
+                    (length, next)
+                }
+            }
+        };
+
+        let Object::Instance(instance) = self.heap.get_mut(instance) else {
+            unreachable!("Fields are set on instances");
+        };
+        if slot == length {
+            instance.fields.push(value);
+        } else {
+            instance.fields[slot] = value;
+        }
+        instance.shape = next;
+    }
+
+    fn set_cache(&mut self, index: usize, cache: Cache) {
+        let function = self.frame().function;
+        self.heap.function_mut(function).caches[index] = cache;
+    }
+
+    /// Replaces the instance on top of the stack with its method called
+    /// `name`, looked up in `class`.
+    fn bind_method(&mut self, class: ObjRef, name: ObjRef) -> Result {
+        let Object::Class(class) = self.heap.get(class) else {
+            unreachable!("Methods are looked up in classes");
+        };
+        let Some(&method) = class.methods.get(&name) else {
+            return Err(self.undefined_property(name));
+        };
+
+        let receiver = self.pop();
+        let bound = self.alloc(Object::BoundMethod(BoundMethod { receiver, method }));
+        self.stack.push(Value::object(bound));
+        Ok(())
+    }
+
+    /// Returns the upvalue at `index` in the closure being run.
+    fn upvalue(&self, index: u8) -> ObjRef {
+        self.heap.closure(self.frame().closure).upvalues[usize::from(index)]
+    }
+
+    /// Returns the upvalue of the local at `slot`, so closures capturing the
+    /// same local share it.
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        let position = self.open_upvalues.partition_point(|&upvalue| self.open_slot(upvalue) < slot);
+        match self.open_upvalues.get(position) {
+            Some(&upvalue) if self.open_slot(upvalue) == slot => upvalue,
+            _ => {
+                let upvalue = self.alloc(Object::Upvalue(Upvalue::Open(slot)));
+                self.open_upvalues.insert(position, upvalue);
+                upvalue
+            }
+        }
+    }
+
+    /// Moves locals from `slot` upwards off the stack, into their upvalues.
+    fn close_upvalues(&mut self, slot: usize) {
+        let position = self.open_upvalues.partition_point(|&upvalue| self.open_slot(upvalue) < slot);
+        for upvalue in self.open_upvalues.split_off(position) {
+            let value = self.stack[self.open_slot(upvalue)];
+            *self.heap.get_mut(upvalue) = Object::Upvalue(Upvalue::Closed(value));
+        }
+    }
+
+    fn open_slot(&self, upvalue: ObjRef) -> usize {
+        match self.heap.get(upvalue) {
+            Object::Upvalue(Upvalue::Open(slot)) => *slot,
+            object => panic!("Expected an open upvalue, found {object:?}"),
+        }
+    }
+
+    /// Adds numbers, or concatenates strings.
+    fn add(&mut self) -> Result {
+        let (a, b) = (self.peek(1), self.peek(0));
+        let result = if let (Some(a), Some(b)) = (a.as_number(), b.as_number()) {
+            Value::number(a + b)
+        } else {
+            let (Some(a), Some(b)) = (a.as_object(), b.as_object()) else {
+                return Err(self.error(ErrorKind::OperandsMustBeNumbersOrStrings));
+            };
+            let (Object::String(a), Object::String(b)) = (self.heap.get(a), self.heap.get(b)) else {
+                return Err(self.error(ErrorKind::OperandsMustBeNumbersOrStrings));
+            };
+            let s = format!("{a}{b}");
+            Value::object(self.intern(&s))
+        };
+        self.pop();
+        self.pop();
+        self.stack.push(result);
+        Ok(())
+    }
+
+    fn binary(&mut self, operation: fn(f64, f64) -> Value) -> Result {
+        let (Some(a), Some(b)) = (self.peek(1).as_number(), self.peek(0).as_number()) else {
+            return Err(self.error(ErrorKind::OperandsMustBeNumbers));
+        };
+        self.pop();
+        self.pop();
+        self.stack.push(operation(a, b));
+        Ok(())
+    }
+
+    fn is_instance(&self, object: ObjRef) -> bool {
+        matches!(self.heap.get(object), Object::Instance(_))
+    }
+
+    fn is_list(&self, object: ObjRef) -> bool {
+        matches!(self.heap.get(object), Object::List(_))
+    }
+
+    fn is_map(&self, object: ObjRef) -> bool {
+        matches!(self.heap.get(object), Object::Map(_))
+    }
+
+    /// Returns `value` as the key of a map, if it can be one.
+    fn key(&self, value: Value) -> Option<Key<ObjRef>> {
+        if let Some(n) = value.as_number() {
+            Some(Key::number(n))
+        } else if let Some(b) = value.as_bool() {
+            Some(Key::Bool(b))
+        } else {
+            value.as_object().filter(|&s| matches!(self.heap.get(s), Object::String(_))).map(Key::String)
+        }
+    }
+
+    fn undefined_key(&self, key: Value) -> evaluator::Error {
+        self.error(ErrorKind::UndefinedKey(key.display(&self.heap).to_string()))
+    }
+
+    /// Checks that `list` is a list with an element at `index`, returning
+    /// them as the list object and the position of the element.
+    fn element(&self, list: Value, index: Value) -> Result<(ObjRef, usize)> {
+        let Some((list, Object::List(elements))) = list.as_object().map(|list| (list, self.heap.get(list))) else {
+            return Err(self.error(ErrorKind::NotIndexable));
+        };
+        let index = list::index(index.as_number(), elements.len()).map_err(|kind| self.error(kind))?;
+        Ok((list, index))
+    }
+
+    fn to_native(&self, value: Value) -> native::Value {
+        if let Some(b) = value.as_bool() {
+            return native::Value::Bool(b);
+        } else if let Some(n) = value.as_number() {
+            return native::Value::Number(n);
+        }
+        let Some(reference) = value.as_object() else {
+            return native::Value::Nil;
+        };
+        match self.heap.get(reference) {
+            Object::String(s) => native::Value::String(s.clone().into()),
+            _ => {
+                let description = value.display(&self.heap).to_string().into();
+                let handle = native::Handle::Vm { vm: self.id, call: self.native_calls, reference, description };
+                native::Value::Object(native::Object(handle))
+            }
+        }
+    }
+
+    /// Returns `None` for objects that were not given to the call returning
+    /// them, which may have been freed since.
+    fn native_result(&mut self, value: native::Value) -> Option<Value> {
+        match value {
+            native::Value::Nil => Some(Value::NIL),
+            native::Value::Bool(b) => Some(Value::bool(b)),
+            native::Value::Number(n) => Some(Value::number(n)),
+            native::Value::String(s) => Some(Value::object(self.intern(&s))),
+            native::Value::Object(native::Object(native::Handle::Vm { vm, call, reference, .. })) => {
+                (vm == self.id && call == self.native_calls).then_some(Value::object(reference))
+            }
+            // Objects of other interpreters don't exist here
+            native::Value::Object(native::Object(native::Handle::Evaluator(_))) => None,
+        }
+    }
+
+    /// Allocates `object`, collecting garbage first if needed.
+    ///
+    /// Objects that `object` refers to are kept alive, even if nothing else
+    /// refers to them.
+    fn alloc(&mut self, object: Object) -> ObjRef {
+        if self.heap.should_collect() {
+            let mut roots = self.roots();
+            object.trace(&mut roots);
+            self.heap.collect(roots);
+        }
+        self.heap.alloc(object)
+    }
+
+    /// Like `alloc`, for strings.
+    fn intern(&mut self, s: &str) -> ObjRef {
+        if self.heap.should_collect() {
+            let roots = self.roots();
+            self.heap.collect(roots);
+        }
+        self.heap.intern(s)
+    }
+
+    /// Returns the objects that the VM can still use.
+    fn roots(&self) -> Vec<ObjRef> {
+        let values = self.stack.iter().chain(self.globals.values());
+        let mut roots: Vec<ObjRef> = values.filter_map(|value| value.as_object()).collect();
+        roots.extend(self.globals.keys());
+        roots.extend(self.frames.iter().map(|frame| frame.closure));
+        roots.extend(&self.open_upvalues);
+        roots.push(self.init);
+        roots
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("Code runs in a frame")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("Code runs in a frame")
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frames.last_mut().expect("Code runs in a frame");
+        let byte = self.heap.function(frame.function).chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_short(&mut self) -> usize {
+        let bytes = [self.read_byte(), self.read_byte()];
+        usize::from(u16::from_be_bytes(bytes))
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let index = self.read_short();
+        self.heap.function(self.frame().function).chunk.constants[index]
+    }
+
+    /// Reads a constant that the compiler made a string, like names.
+    fn read_string(&mut self) -> ObjRef {
+        let constant = self.read_constant();
+        constant.as_object().unwrap_or_else(|| panic!("Expected a string constant, found {constant:?}"))
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("Compiler balances the stack")
+    }
+
+    /// Returns the value `distance` slots below the top of the stack.
+    fn peek(&self, distance: usize) -> Value {
+        self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn undefined_variable(&self, name: ObjRef) -> evaluator::Error {
+        self.error(ErrorKind::UndefinedVariable(self.heap.string(name).to_string()))
+    }
+
+    fn undefined_property(&self, name: ObjRef) -> evaluator::Error {
+        self.error(ErrorKind::UndefinedProperty(self.heap.string(name).to_string()))
+    }
+
+    /// Builds an error pointing at the current instruction, with the trace
+    /// of the frames.
+    fn error(&self, kind: ErrorKind) -> evaluator::Error {
+        // The last byte read is part of the instruction that is running
+        self.error_at(kind, self.frame().ip - 1)
+    }
+
+    /// Like `error`, but pointing at where the byte at `offset` of the
+    /// current function came from.
+    fn error_at(&self, kind: ErrorKind, offset: usize) -> evaluator::Error {
+        let mut trace: Vec<Frame> = self
+            .frames
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(depth, frame)| {
+                let function = self.heap.function(frame.function);
+                Frame {
+                    function: function.name.map(|name| self.heap.string(name).to_string()),
+                    // Calling frames are running the call right before `ip`
+                    span: function.chunk.span(if depth == 0 { offset } else { frame.ip - 1 }),
+                }
+            })
+            .collect();
+        let span = trace.first().map_or(Span::default(), |frame| frame.span);
+        // Errors in top-level code have no trace, as with the tree-walker
+        if trace.len() == 1 {
+            trace[0].function = None;
+        }
+        evaluator::Error { kind, span, trace }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::test_support::{run, run_vm, run_vm_err, run_vm_unoptimized};
+
+    /// Runs `source` on both backends, checking they print the same.
+    fn run_both(source: &str) -> String {
+        let printed = run_vm(source);
+        assert_eq!(printed, run(source), "Backends printed differently");
+        printed
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        assert_eq!(run_vm("print 1 + 2 * 3 - 4 / 2;"), "5\n");
+        assert_eq!(run_vm("print !(1 >= 2) == true;"), "true\n");
+        assert_eq!(run_vm("print \"con\" + \"cat\";"), "concat\n");
+        assert_eq!(run_vm("print nil or \"default\"; print 1 and 2;"), "default\n2\n");
+        assert_eq!(run_both("var a; print a ?? \"default\"; print false ?? -nil;"), "default\nfalse\n");
+        assert_eq!(run_both("print 1 > 2 ? -nil : nil ? 2 : 3;"), "3\n");
+        assert_eq!(run_vm("print 0.1 + 0.2; print 3 / 0; print -0;"), "0.30000000000000004\ninf\n-0\n");
+        assert_eq!(run_vm("print 3000000000 + 1;"), "3000000001\n");
+        assert_eq!(run_both("var n = 0 / 0; print n <= 1; print n >= 1; print !(n > 1);"), "false\nfalse\ntrue\n");
+        let globals: String = (0..300).map(|n| format!("var v{n} = {n};")).collect();
+        assert_eq!(run_vm(&(globals + "print v0 + v299;")), "299\n");
+    }
+
+    #[test]
+    fn keeps_globals_and_locals() {
+        let source = "var a = 1; { var b = a + 1; { var c = b + 1; a = c; } } print a;";
+
+        assert_eq!(run_vm(source), "3\n");
+    }
+
+    #[test]
+    fn runs_control_flow() {
+        let source = "
+            var total = 0;
+            for (var i = 0; i < 5; i = i + 1) {
+                if (i == 2) total = total + 10; else total = total + i;
+            }
+            while (total > 15) total = total - 1;
+            print total;";
+
+        assert_eq!(run_vm(source), "15\n");
+    }
+
+    #[test]
+    fn breaks_and_continues_loops() {
+        let source = "
+            var closures = \"\";
+            for (var i = 0; i < 6; i = i + 1) {
+                var captured = i;
+                fun f() { return captured; }
+                if (i == 1) continue;
+                { var inner = i; if (inner == 4) break; }
+                closures = closures + \"x\";
+                print f();
+            }
+            var n = 0;
+            while (true) { n = n + 1; if (n < 3) continue; break; }
+            print n;
+            print closures;";
+
+        assert_eq!(run_both(source), "0\n2\n3\n3\nxxx\n");
+        assert_eq!(run_vm_unoptimized(source), "0\n2\n3\n3\nxxx\n");
+    }
+
+    #[test]
+    fn calls_functions() {
+        let source = "
+            fun fib(n) { if (n < 2) return n; return fib(n - 2) + fib(n - 1); }
+            print fib(15);
+            print fib;
+            print clock;";
+
+        assert_eq!(run_vm(source), "610\n<fn fib>\n<native fn>\n");
+    }
+
+    #[test]
+    fn runs_classes() {
+        let source = "
+            class Animal {
+                init(name) { this.name = name; }
+                speak() { return this.name + \" makes a sound\"; }
+            }
+            class Dog < Animal {
+                speak() { return super.speak() + \", woof\"; }
+            }
+            var dog = Dog(\"Rex\");
+            print dog.speak();
+            var speak = dog.speak;
+            dog.name = \"Max\";
+            print speak();
+            print Dog;
+            print dog;
+            print dog.init(\"Bo\").name;";
+
+        assert_eq!(
+            run_vm(source),
+            "Rex makes a sound, woof\nMax makes a sound, woof\nDog\nDog instance\nBo\n",
+        );
+    }
+
+    #[test]
+    fn caches_properties_of_instances() {
+        // The same instructions run on instances of different classes and
+        // shapes, and on fields that shadow methods
+        let source = "
+            class A { name() { return \"A\"; } }
+            class B { name() { return \"B\"; } }
+            fun describe(o) { return o.name() + \" \" + o.x; }
+            fun set(o, x) { o.x = x; }
+            fun field() { return \"field\"; }
+            var a = A(); set(a, \"1\");
+            var b = B(); b.y = 0; set(b, \"2\");
+            var c = A(); c.name = field; set(c, \"3\");
+            print describe(a); print describe(b); print describe(a); print describe(c);
+            set(a, \"4\"); set(b, \"5\");
+            a.name = field;
+            print describe(a); print describe(b);
+            var name = b.name;
+            print name();";
+
+        assert_eq!(run_both(source), "A 1\nB 2\nA 1\nfield 3\nfield 4\nB 5\nB\n");
+    }
+
+    #[test]
+    fn invokes_methods_without_binding_them() {
+        let mut vm = Vm::new(Box::new(std::io::sink()), &Natives::empty());
+        let source = "class A { m() { return this; } } var a = A(); for (var i = 0; i < 100; i = i + 1) a.m().m();";
+
+        let script = vm.compile(source, 0).unwrap();
+        vm.interpret(script).unwrap();
+
+        // Nothing was collected, and nothing was allocated for each call
+        assert!(vm.heap.live_objects() < 20);
+    }
+
+    #[test]
+    fn keeps_shapes_of_many_fields_small() {
+        let mut vm = Vm::new(Box::new(std::io::sink()), &Natives::empty());
+        let fields: String = (0..2000).map(|i| format!("a.f{i} = {i};")).collect();
+        let source = format!("class A {{}} var a = A(); {fields} print a.f0 + a.f1999;");
+
+        let script = vm.compile(&source, 0).unwrap();
+        vm.interpret(script).unwrap();
+
+        // Each shape takes about as much as a field, rather than all the fields before it
+        assert!(vm.heap.shapes.size() < 2000 * 256);
+    }
+
+    #[test]
+    fn keeps_captured_variables_after_returning() {
+        let source = "
+            fun counter() {
+                var count = 0;
+                fun increment() { count = count + 1; return count; }
+                return increment;
+            }
+            var a = counter();
+            var b = counter();
+            a(); a();
+            print a();
+            print b();";
+
+        assert_eq!(run_both(source), "3\n1\n");
+    }
+
+    #[test]
+    fn shares_captured_variables_between_closures() {
+        let source = "
+            var get; var set;
+            fun make() {
+                var value = \"before\";
+                fun g() { return value; }
+                fun s(v) { value = v; }
+                get = g; set = s;
+            }
+            make();
+            set(\"after\");
+            print get();";
+
+        assert_eq!(run_both(source), "after\n");
+    }
+
+    #[test]
+    fn captures_through_several_functions() {
+        let source = "
+            fun outer() {
+                var x = \"outer\";
+                fun middle() {
+                    fun inner() { return x; }
+                    return inner;
+                }
+                return middle;
+            }
+            print outer()()();";
+
+        assert_eq!(run_both(source), "outer\n");
+    }
+
+    #[test]
+    fn captures_loop_variables() {
+        // The loop variable is one for all iterations, but locals of the body
+        // are new in each one
+        let source = "
+            var first; var second;
+            for (var i = 0; i < 2; i = i + 1) {
+                var j = i;
+                fun f() { print i; print j; }
+                if (first == nil) first = f; else second = f;
+            }
+            first();
+            second();";
+
+        assert_eq!(run_both(source), "2\n0\n2\n1\n");
+    }
+
+    #[test]
+    fn captures_this_and_super() {
+        let source = "
+            class A { name() { return \"A\"; } }
+            class B < A {
+                name() {
+                    fun prefixed() { return \"B of \" + super.name(); }
+                    return prefixed;
+                }
+                later() { fun f() { return this; } return f; }
+            }
+            var b = B();
+            print b.name()();
+            print b.later()() == b;";
+
+        assert_eq!(run_both(source), "B of A\ntrue\n");
+    }
+
+    #[test]
+    fn runs_lists() {
+        let source = "
+            var xs = [1, [2, 3]];
+            xs[1][0] = xs;
+            xs.push(\"four\");
+            xs.insert(1, nil);
+            print xs.remove(0) + xs.len();
+            var pop = xs.pop;
+            print pop();
+            print xs.slice(1, 2);
+            print xs;
+            print xs == xs[1][0];";
+
+        assert_eq!(run_both(source), "4\nfour\n[[[nil, [...]], 3]]\n[nil, [[...], 3]]\ntrue\n");
+        assert_eq!(run_vm_unoptimized(source), "4\nfour\n[[[nil, [...]], 3]]\n[nil, [[...], 3]]\ntrue\n");
+    }
+
+    #[test]
+    fn maps_and_filters_lists() {
+        let source = "
+            var xs = [1, 2, 3];
+            print xs.map((x) => x * 2).filter((x) => x > 2);
+            fun grow(x) { if (x < 3) xs.push(x + 10); return x > 1; }
+            print xs.filter(grow);
+            class A { init(x) { this.x = x; } }
+            print xs.map(A).map((a) => a.x);
+            print [[1], [2, 3]].map((ys) => ys.map((y) => -y));
+            var len = [].len;
+            print [[1], []].filter([].push).map(len);";
+
+        let expected = "[4, 6]\n[2, 3, 11, 12]\n[1, 2, 3, 11, 12]\n[[-1], [-2, -3]]\n[]\n";
+        assert_eq!(run_both(source), expected);
+        assert_eq!(run_vm_unoptimized(source), expected);
+        assert_eq!(run_vm_err("print [1].map((x) => x.y);"), "1:24: Only instances have properties.");
+    }
+
+    #[test]
+    fn runs_lambdas() {
+        let source = "
+            var add = fun (a, b) { return a + b; };
+            var twice = (f) => (x) => f(f(x));
+            print twice((x) => add(x, 3))(1);
+            fun (s) { print s; }(\"now\");
+            class A { init() { this.x = 1; } getter() { return () => this.x; } }
+            print A().getter()();
+            print (() => nil)();";
+
+        assert_eq!(run_both(source), "7\nnow\n1\nnil\n");
+        assert_eq!(run_vm_err("var f = (a) => -a;\nf(nil);"), "1:16: Operand must be a number.");
+    }
+
+    #[test]
+    fn runs_maps() {
+        let source = "
+            var m = {\"a\": 1, 2: \"two\", true: [3]};
+            m[-0] = m[\"a\" + \"\"];
+            m[\"self\"] = m;
+            print m[2];
+            print m.len() + m[0];
+            print m.remove(2);
+            var keys = m.keys;
+            print keys();
+            print m.values()[2];
+            print m.has(0) and !m.has(2);
+            print m;";
+
+        let expected = "two\n6\ntwo\n[\"a\", true, 0, \"self\"]\n1\ntrue\n\
+                        {\"a\": 1, true: [3], 0: 1, \"self\": {...}}\n";
+        assert_eq!(run_both(source), expected);
+        assert_eq!(run_vm_unoptimized(source), expected);
+        assert_eq!(run_both("print {\"1\": \"a\", 1: [\"b\"]};"), "{\"1\": \"a\", 1: [\"b\"]}\n");
+    }
+
+    #[test]
+    fn frees_garbage_while_running() {
+        let mut vm = Vm::new(Box::new(std::io::sink()), &Natives::empty());
+        vm.set_gc_stress(true);
+        let source = "for (var i = 0; i < 100; i = i + 1) { class A {} var a = A(); a.name = \"a\" + \"b\"; }";
+
+        let script = vm.compile(source, 0).unwrap();
+        vm.interpret(script).unwrap();
+
+        assert!(vm.heap.live_objects() < 20);
+    }
+
+    #[test]
+    fn reports_runtime_errors_like_the_tree_walker() {
+        assert_eq!(run_vm_err("print -\"a\";"), "1:7: Operand must be a number.");
+        assert_eq!(run_vm_err("print 1 + nil;"), "1:9: Operands must be two numbers or two strings.");
+        assert_eq!(run_vm_err("print missing;"), "1:7: Undefined variable 'missing'.");
+        assert_eq!(run_vm_err("missing = 1;"), "1:1: Undefined variable 'missing'.");
+        assert_eq!(run_vm_err("fun f(a) {}\nf();"), "2:3: Expected 1 arguments but got 0.");
+        assert_eq!(run_vm_err("\"a\"();"), "1:5: Can only call functions and classes.");
+        assert_eq!(run_vm_err("class A {}\nA().b;"), "2:5: Undefined property 'b'.");
+        assert_eq!(run_vm_err("1.5 .b;"), "1:6: Only instances have properties.");
+        assert_eq!(run_vm_err("class A {}\nA().b();"), "2:5: Undefined property 'b'.");
+        assert_eq!(run_vm_err("1.5 .b();"), "1:6: Only instances have properties.");
+        assert_eq!(run_vm_err("class A { m(a) {} }\nA().m();"), "2:7: Expected 1 arguments but got 0.");
+        assert_eq!(run_vm_err("class A {}\nvar a = A();\na.f = 1; a.f();"), "3:14: Can only call functions and classes.");
+        assert_eq!(run_vm_err("var a = 1;\na.b = 2;"), "2:3: Only instances have fields.");
+        assert_eq!(run_vm_err("var A = 1;\nclass B < A {}"), "2:11: Superclass must be a class.");
+        assert_eq!(run_vm_err("fun f() { f(); }\nf();"), "1:13: Stack overflow.");
+        assert_eq!(run_vm_err("var xs = [1];\nprint xs[1];"), "2:11: Index 1 is out of bounds for a list of length 1.");
+        assert_eq!(run_vm_err("[1][0.5] = 2;"), "1:8: Index must be an integer.");
+        assert_eq!(run_vm_err("print 1[0];"), "1:10: Only lists and maps can be indexed.");
+        assert_eq!(run_vm_err("[].pop();"), "1:8: Can't pop from an empty list.");
+        assert_eq!(run_vm_err("[].len(1);"), "1:9: Expected 0 arguments but got 1.");
+        assert_eq!(run_vm_err("[].size();"), "1:4: Undefined property 'size'.");
+        assert_eq!(run_vm_err("[].size;"), "1:4: Undefined property 'size'.");
+        assert_eq!(run_vm_err("var m = {1: 2};\nprint m[2];"), "2:10: Undefined key '2'.");
+        assert_eq!(run_vm_err("print {}[nil] = 1;"), "1:13: Map keys must be strings, numbers or booleans.");
+        assert_eq!(run_vm_err("var m = {[]: 1};"), "1:9: Map keys must be strings, numbers or booleans.");
+        assert_eq!(run_vm_err("print {}.remove(\"a\");"), "1:20: Undefined key 'a'.");
+        assert_eq!(run_vm_err("var has = {}.has;\nhas({});"), "2:7: Map keys must be strings, numbers or booleans.");
+        assert_eq!(run_vm_err("print {}.get(1);"), "1:10: Undefined property 'get'.");
+    }
+}
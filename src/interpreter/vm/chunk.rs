@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+
+use super::heap::ObjRef;
+use super::value::Value;
+use crate::interpreter::token::Span;
+
+/// Instructions of the VM, each followed by its operands in the code.
+///
+/// Constant operands are a big-endian `u16` indexing the constant pool, jump
+/// operands are a big-endian `u16` distance from the end of the instruction,
+/// and cache operands are a big-endian `u16` indexing the caches of the
+/// function.
+///
+/// Compiled files store these bytes, so changing them needs a new
+/// `file::VERSION`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub(crate) enum OpCode {
+    /// Pushes the constant of its operand.
+    Constant,
+    Nil,
+    True,
+    False,
+    Pop,
+    /// Operand is how many values to pop.
+    PopN,
+    /// Operand is the stack slot of the local, from the frame start.
+    GetLocal,
+    SetLocal,
+    /// Operand is the constant holding the name of the global.
+    GetGlobal,
+    DefineGlobal,
+    SetGlobal,
+    /// Operand is the index of the upvalue in the closure being run.
+    GetUpvalue,
+    SetUpvalue,
+    /// Operands are the constant holding the name of the property, and the
+    /// cache of the instruction.
+    GetProperty,
+    SetProperty,
+    GetSuper,
+    /// Operand is the number of elements, which are on top of the stack.
+    BuildList,
+    /// Operand is the number of entries, whose keys and values are on top of
+    /// the stack in turn.
+    BuildMap,
+    GetIndex,
+    SetIndex,
+    Equal,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    /// Fused `Equal` and `Not`.
+    NotEqual,
+    /// Fused `Greater` and `Not`, which is `<=` except for NaNs.
+    NotGreater,
+    /// Fused `Less` and `Not`, which is `>=` except for NaNs.
+    NotLess,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    Jump,
+    JumpIfFalse,
+    /// Like `JumpIfFalse`, but only for `nil`.
+    JumpIfNil,
+    /// Jumps backwards.
+    Loop,
+    /// Operand is the number of arguments.
+    Call,
+    /// Calls a property without binding methods to the instance. Operands
+    /// are those of `GetProperty`, then the number of arguments.
+    Invoke,
+    /// Operand is the constant holding the function, followed by a pair of
+    /// bytes for each upvalue: whether it captures a local of the enclosing
+    /// function, and the slot of that local or the index of its upvalue.
+    Closure,
+    /// Moves the local on top of the stack into the upvalues capturing it,
+    /// then pops it.
+    CloseUpvalue,
+    Return,
+    /// Operand is the constant holding the name of the class.
+    Class,
+    Inherit,
+    /// Operand is the constant holding the name of the method.
+    Method,
+}
+
+impl OpCode {
+    /// Every opcode, in the order of their bytes.
+    const ALL: [OpCode; 47] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::PopN,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::SetProperty,
+        OpCode::GetSuper,
+        OpCode::BuildList,
+        OpCode::BuildMap,
+        OpCode::GetIndex,
+        OpCode::SetIndex,
+        OpCode::Equal,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::NotEqual,
+        OpCode::NotGreater,
+        OpCode::NotLess,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::JumpIfNil,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Invoke,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::Inherit,
+        OpCode::Method,
+    ];
+
+    pub(crate) fn operand(self) -> Operand {
+        match self {
+            OpCode::Constant
+            | OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetSuper
+            | OpCode::Class
+            | OpCode::Method => Operand::Constant,
+            OpCode::PopN
+            | OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::BuildList
+            | OpCode::BuildMap
+            | OpCode::Call => Operand::Byte,
+            OpCode::GetProperty | OpCode::SetProperty => Operand::Property,
+            OpCode::Invoke => Operand::Invoke,
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::JumpIfNil | OpCode::Loop => Operand::Jump,
+            OpCode::Closure => Operand::Closure,
+            OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::Pop
+            | OpCode::GetIndex
+            | OpCode::SetIndex
+            | OpCode::Equal
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::NotEqual
+            | OpCode::NotGreater
+            | OpCode::NotLess
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Not
+            | OpCode::Negate
+            | OpCode::Print
+            | OpCode::CloseUpvalue
+            | OpCode::Return
+            | OpCode::Inherit => Operand::None,
+        }
+    }
+}
+
+/// What follows an opcode in the code.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Operand {
+    None,
+    /// Index in the constant pool, as a big-endian `u16`.
+    Constant,
+    /// Any other byte, like a stack slot.
+    Byte,
+    /// Index in the constant pool, then index of a cache, both as big-endian
+    /// `u16`s.
+    Property,
+    /// Like `Property`, then the number of arguments.
+    Invoke,
+    /// Distance as a big-endian `u16`.
+    Jump,
+    /// Constant holding the function, then a pair of bytes per upvalue.
+    Closure,
+}
+
+impl TryFrom<u8> for OpCode {
+    /// The byte that is not an opcode.
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        OpCode::ALL.get(usize::from(byte)).copied().ok_or(byte)
+    }
+}
+
+/// Compiled code of a function.
+#[derive(Debug, Default)]
+pub(crate) struct Chunk {
+    pub(crate) code: Vec<u8>,
+    /// Only grows with `add_constant`, as `indices` must follow it.
+    /// `with_constants` makes chunks with other constants.
+    pub(crate) constants: Vec<Value>,
+    /// Where each byte of `code` came from, in runs of consecutive bytes
+    /// from the same place.
+    pub(crate) locations: Vec<Run>,
+    /// Index of each of `constants`, so that adding one doesn't compare it
+    /// with all the others.
+    indices: HashMap<ConstantKey, usize>,
+}
+
+/// Tells constants apart, numbers by their bits so that 0 and -0 stay
+/// different.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Number(u64),
+    Object(ObjRef),
+    /// `nil` is `None`.
+    Literal(Option<bool>),
+}
+
+impl From<Value> for ConstantKey {
+    fn from(value: Value) -> ConstantKey {
+        match (value.as_number(), value.as_object()) {
+            (Some(n), _) => ConstantKey::Number(n.to_bits()),
+            (_, Some(object)) => ConstantKey::Object(object),
+            _ => ConstantKey::Literal(value.as_bool()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Run {
+    pub(crate) line: usize,
+    pub(crate) span: Span,
+    pub(crate) length: usize,
+}
+
+impl Chunk {
+    /// A chunk without code, whose constant pool is `constants`.
+    pub(crate) fn with_constants(constants: Vec<Value>) -> Chunk {
+        let indices = constants.iter().enumerate().map(|(index, &constant)| (constant.into(), index)).collect();
+        Chunk { constants, indices, ..Chunk::default() }
+    }
+
+    /// `line` and `span` are where the byte came from in the source code.
+    pub(crate) fn write(&mut self, byte: u8, line: usize, span: Span) {
+        self.code.push(byte);
+        match self.locations.last_mut() {
+            Some(run) if run.line == line && run.span == span => run.length += 1,
+            _ => self.locations.push(Run { line, span, length: 1 }),
+        }
+    }
+
+    /// Returns the index of `value` in the constant pool, adding it if it
+    /// isn't there.
+    pub(crate) fn add_constant(&mut self, value: Value) -> usize {
+        *self.indices.entry(value.into()).or_insert_with(|| {
+            self.constants.push(value);
+            self.constants.len() - 1
+        })
+    }
+
+    pub(crate) fn line(&self, offset: usize) -> usize {
+        self.run(offset).line
+    }
+
+    pub(crate) fn span(&self, offset: usize) -> Span {
+        self.run(offset).span
+    }
+
+    fn run(&self, offset: usize) -> &Run {
+        let mut start = 0;
+        for run in &self.locations {
+            start += run.length;
+            if offset < start {
+                return run;
+            }
+        }
+        panic!("Offset {offset} is past the end of the chunk");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_every_opcode() {
+        for (byte, opcode) in OpCode::ALL.into_iter().enumerate() {
+            assert_eq!(opcode as usize, byte);
+            assert_eq!(OpCode::try_from(byte as u8), Ok(opcode));
+        }
+        assert_eq!(OpCode::try_from(OpCode::ALL.len() as u8), Err(OpCode::ALL.len() as u8));
+    }
+
+    #[test]
+    fn encodes_locations_in_runs() {
+        let mut chunk = Chunk::default();
+        let (a, b) = (Span { start: 0, end: 1 }, Span { start: 4, end: 5 });
+
+        chunk.write(OpCode::Constant as u8, 1, a);
+        chunk.write(0, 1, a);
+        chunk.write(OpCode::Negate as u8, 1, b);
+        chunk.write(OpCode::Return as u8, 2, b);
+
+        assert_eq!(chunk.locations.len(), 3);
+        assert_eq!((chunk.line(1), chunk.span(1)), (1, a));
+        assert_eq!((chunk.line(2), chunk.span(2)), (1, b));
+        assert_eq!(chunk.line(3), 2);
+    }
+
+    #[test]
+    fn reuses_equal_constants() {
+        let mut chunk = Chunk::default();
+
+        assert_eq!(chunk.add_constant(Value::number(1.0)), 0);
+        assert_eq!(chunk.add_constant(Value::number(2.0)), 1);
+        assert_eq!(chunk.add_constant(Value::number(1.0)), 0);
+        assert_eq!(chunk.add_constant(Value::number(0.0)), 2);
+        assert_eq!(chunk.add_constant(Value::number(-0.0)), 3);
+
+        let mut chunk = Chunk::with_constants(chunk.constants);
+        assert_eq!(chunk.add_constant(Value::number(-0.0)), 3);
+        assert_eq!(chunk.add_constant(Value::NIL), 4);
+        assert_eq!(chunk.add_constant(Value::bool(false)), 5);
+        assert_eq!(chunk.add_constant(Value::NIL), 4);
+    }
+}
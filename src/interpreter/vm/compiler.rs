@@ -0,0 +1,1639 @@
+use super::chunk::{Chunk, OpCode, Operand};
+use super::heap::{Heap, ObjRef};
+use super::object::{Cache, Function, Object};
+use super::optimizer;
+use super::value::Value;
+use crate::interpreter::ast::Identifier;
+use crate::interpreter::code::Code;
+use crate::interpreter::diagnostic::Diagnostic;
+use crate::interpreter::parser::{self, ErrorKind as ParseErrorKind};
+use crate::interpreter::resolver::{self, ErrorKind as ResolveErrorKind};
+use crate::interpreter::symbol::Symbol;
+use crate::interpreter::token::{self, Keyword, Span, Token, Type};
+use crate::interpreter::OptLevel;
+
+/// Locals are addressed by a byte, so a function can't have more than this.
+const MAX_LOCALS: usize = 256;
+
+/// Upvalues are addressed by a byte, so a closure can't capture more than this.
+const MAX_UPVALUES: usize = 256;
+
+/// Constants are addressed by a `u16`, so a chunk can't have more than this.
+const MAX_CONSTANTS: usize = 1 << 16;
+
+/// Calls and functions can't have more arguments or parameters than this,
+/// nor list literals more elements.
+const MAX_ARGUMENTS: usize = 255;
+
+/// Limits of the bytecode format, which the tree-walker doesn't have.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Error {
+    pub(crate) kind: ErrorKind,
+    pub(crate) span: Span,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum ErrorKind {
+    TooManyConstants,
+    TooManyLocals,
+    JumpTooLarge,
+    LoopTooLarge,
+    TooManyUpvalues,
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::TooManyConstants => write!(f, "Too many constants in one chunk."),
+            ErrorKind::TooManyLocals => write!(f, "Too many local variables in function."),
+            ErrorKind::JumpTooLarge => write!(f, "Too much code to jump over."),
+            ErrorKind::LoopTooLarge => write!(f, "Loop body too large."),
+            ErrorKind::TooManyUpvalues => write!(f, "Too many closure variables in function."),
+        }
+    }
+}
+
+impl ErrorKind {
+    pub(crate) fn code(&self) -> Code {
+        match self {
+            ErrorKind::TooManyConstants => Code::TOO_MANY_CONSTANTS,
+            ErrorKind::TooManyLocals => Code::TOO_MANY_LOCALS,
+            ErrorKind::JumpTooLarge => Code::JUMP_TOO_LARGE,
+            ErrorKind::LoopTooLarge => Code::LOOP_TOO_LARGE,
+            ErrorKind::TooManyUpvalues => Code::TOO_MANY_UPVALUES,
+        }
+    }
+}
+
+/// Syntax errors stop compiling the current statement.
+type Result<T = ()> = std::result::Result<T, parser::Error>;
+
+/// Compiles the code of `source` from byte `start` on into the function of a
+/// script, allocated in `heap`.
+///
+/// Reports the same errors as the parser and the resolver would, in the same
+/// format, as they come.
+///
+/// Garbage may be collected while compiling, keeping alive `roots` and the
+/// objects of the functions being compiled.
+pub(crate) fn compile(
+    source: &str,
+    start: usize,
+    heap: &mut Heap,
+    roots: Vec<ObjRef>,
+    level: OptLevel,
+) -> std::result::Result<ObjRef, Vec<Diagnostic>> {
+    let (tokens, errors) = crate::interpreter::scan(source, start);
+    let mut compiler = Compiler::new(tokens, source, heap, roots, level);
+    compiler.errors = errors;
+
+    while !compiler.is_at_end() {
+        compiler.declaration();
+    }
+    let end = compiler.end;
+    compiler.emit_return(end);
+
+    let mut script = compiler.functions.pop().expect("Script is always being compiled");
+    compiler.optimize(&mut script);
+    if compiler.errors.is_empty() {
+        Ok(compiler.alloc(Object::Function(script.function)))
+    } else {
+        Err(compiler.errors)
+    }
+}
+
+/// Names the anonymous function starting at `span`, like the parser does.
+fn lambda(span: Span) -> Identifier {
+    Identifier { name: Symbol::intern("lambda"), span }
+}
+
+/// Emits bytecode as it parses, without building a syntax tree.
+struct Compiler<'a> {
+    heap: &'a mut Heap,
+    /// Objects of the VM that collections must keep alive.
+    roots: Vec<ObjRef>,
+    tokens: Vec<Token>,
+    position: usize,
+    /// Byte offset where each line of the source starts.
+    line_starts: Vec<usize>,
+    /// Where errors at the end of the source point.
+    end: Span,
+    /// Functions being compiled, innermost last, starting with the script.
+    functions: Vec<FunctionCompiler>,
+    /// Classes being compiled, innermost last.
+    classes: Vec<ClassCompiler>,
+    errors: Vec<Diagnostic>,
+    level: OptLevel,
+}
+
+struct FunctionCompiler {
+    function: Function,
+    kind: FunctionKind,
+    /// Locals in the order of their stack slots.
+    locals: Vec<Local>,
+    /// Variables of enclosing functions that the function uses.
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    /// Loops being compiled, innermost last.
+    loops: Vec<Loop>,
+}
+
+struct Local {
+    name: Symbol,
+    span: Span,
+    /// `None` until its initializer finishes.
+    depth: Option<usize>,
+    /// Whether a closure uses it, so it must outlive the stack slot.
+    is_captured: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct Upvalue {
+    /// Slot of the local, or index of the upvalue, in the enclosing function.
+    index: u8,
+    /// Whether it captures a local of the enclosing function, rather than
+    /// one of its upvalues.
+    is_local: bool,
+}
+
+struct Loop {
+    /// Where `continue` jumps to, which is the increment of `for` loops.
+    start: usize,
+    /// Scope depth around the body, whose deeper locals `break` and
+    /// `continue` pop.
+    scope_depth: usize,
+    /// Operands of the jumps of `break`, to patch at the end of the loop.
+    breaks: Vec<usize>,
+}
+
+struct ClassCompiler {
+    has_superclass: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+/// Binding power of infix operators, from weakest to strongest.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Precedence {
+    None,
+    Assignment,
+    Conditional,
+    Coalesce,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Term,
+    Factor,
+    Unary,
+    Call,
+}
+
+impl Precedence {
+    /// Operands of left-associative operators bind one level stronger.
+    fn next(self) -> Precedence {
+        match self {
+            Precedence::None => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Conditional,
+            Precedence::Conditional => Precedence::Coalesce,
+            Precedence::Coalesce => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary | Precedence::Call => Precedence::Call,
+        }
+    }
+
+    /// Is `None` for tokens that aren't infix operators.
+    fn of_infix(r#type: &Type) -> Precedence {
+        match r#type {
+            Type::LeftParen | Type::Dot | Type::LeftBracket => Precedence::Call,
+            Type::Star | Type::Slash => Precedence::Factor,
+            Type::Plus | Type::Minus => Precedence::Term,
+            Type::Greater | Type::GreaterEqual | Type::Less | Type::LessEqual => Precedence::Comparison,
+            Type::EqualEqual | Type::BangEqual => Precedence::Equality,
+            Type::Keyword(Keyword::And) => Precedence::And,
+            Type::Keyword(Keyword::Or) => Precedence::Or,
+            Type::QuestionQuestion => Precedence::Coalesce,
+            Type::Question => Precedence::Conditional,
+            _ => Precedence::None,
+        }
+    }
+}
+
+impl FunctionCompiler {
+    fn new(kind: FunctionKind, name: Option<ObjRef>) -> FunctionCompiler {
+        // The first slot holds the function being called, or the receiver of
+        // methods
+        let slot_name = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+        let callee = Local { name: Symbol::intern(slot_name), span: Span::default(), depth: Some(0), is_captured: false };
+        FunctionCompiler {
+            function: Function { name, ..Function::default() },
+            kind,
+            locals: vec![callee],
+            upvalues: vec![],
+            scope_depth: 0,
+            loops: vec![],
+        }
+    }
+}
+
+impl Compiler<'_> {
+    fn new<'a>(
+        tokens: Vec<Token>,
+        source: &str,
+        heap: &'a mut Heap,
+        roots: Vec<ObjRef>,
+        level: OptLevel,
+    ) -> Compiler<'a> {
+        let line_starts = std::iter::once(0).chain(source.match_indices('\n').map(|(i, _)| i + 1)).collect();
+        Compiler {
+            heap,
+            roots,
+            tokens,
+            position: 0,
+            line_starts,
+            end: Span { start: source.len(), end: source.len() },
+            functions: vec![FunctionCompiler::new(FunctionKind::Script, None)],
+            classes: vec![],
+            errors: vec![],
+            level,
+        }
+    }
+
+    fn declaration(&mut self) {
+        let result = if self.matches(&Type::Keyword(Keyword::Class)) {
+            self.class_declaration()
+        } else if self.check(&Type::Keyword(Keyword::Fun)) && !self.check_next(&Type::LeftParen) {
+            self.advance();
+            self.fun_declaration()
+        } else if self.matches(&Type::Keyword(Keyword::Var)) {
+            self.var_declaration()
+        } else {
+            self.statement()
+        };
+
+        if let Err(error) = result {
+            self.errors.push(error.into());
+            self.synchronize();
+        }
+    }
+
+    fn class_declaration(&mut self) -> Result {
+        let name = self.consume_identifier("class name")?;
+        let global = self.declare_variable(&name);
+        let name_constant = self.identifier_constant(&name);
+        self.emit_with(OpCode::Class, name_constant, name.span);
+        self.define_variable(global, name.span);
+
+        let superclass = if self.matches(&Type::Less) {
+            let superclass = self.consume_identifier("superclass name")?;
+            if superclass.name == name.name {
+                self.errors.push(parser::Error { kind: ParseErrorKind::InheritsFromItself, span: superclass.span }.into());
+            }
+            Some(superclass)
+        } else {
+            None
+        };
+
+        // Popped even after errors, like functions
+        self.classes.push(ClassCompiler { has_superclass: superclass.is_some() });
+        if let Some(superclass) = &superclass {
+            // The superclass stays on the stack as `super`, while the class
+            // is compiled
+            self.named_variable(superclass, false)?;
+            self.begin_scope();
+            let super_local = Identifier { name: Symbol::intern("super"), span: superclass.span };
+            self.declare_variable(&super_local);
+            self.mark_initialized();
+        }
+        let result = self.class_body(&name, superclass.as_ref());
+        if superclass.is_some() {
+            let brace = self.previous().span;
+            self.end_scope(brace);
+        }
+        self.classes.pop();
+        result
+    }
+
+    fn class_body(&mut self, name: &Identifier, superclass: Option<&Identifier>) -> Result {
+        if let Some(superclass) = superclass {
+            self.named_variable(name, false)?;
+            self.emit(OpCode::Inherit, superclass.span);
+        }
+
+        // Methods are added to the class on top of the stack
+        self.named_variable(name, false)?;
+        self.consume(Type::LeftBrace, "'{' before class body")?;
+        while !self.check(&Type::RightBrace) && !self.is_at_end() {
+            self.method()?;
+        }
+        let brace = self.consume(Type::RightBrace, "'}' after class body")?.span;
+        self.emit(OpCode::Pop, brace);
+        Ok(())
+    }
+
+    fn method(&mut self) -> Result {
+        let name = self.consume_identifier("method name")?;
+        let constant = self.identifier_constant(&name);
+        let kind = if name.name == "init" { FunctionKind::Initializer } else { FunctionKind::Method };
+        self.function(kind, &name, Self::function_body)?;
+        self.emit_with(OpCode::Method, constant, name.span);
+        Ok(())
+    }
+
+    fn fun_declaration(&mut self) -> Result {
+        let name = self.consume_identifier("function name")?;
+        let global = self.declare_variable(&name);
+        // Defined before its body so it can call itself
+        self.mark_initialized();
+        self.function(FunctionKind::Function, &name, Self::function_body)?;
+        self.define_variable(global, name.span);
+        Ok(())
+    }
+
+    /// Compiles the parameters and body of a function with `body`, and
+    /// leaves it on the stack.
+    fn function(&mut self, kind: FunctionKind, name: &Identifier, body: fn(&mut Self) -> Result) -> Result {
+        let name_constant = self.intern(&name.name);
+        self.functions.push(FunctionCompiler::new(kind, Some(name_constant)));
+        // Popped even after errors, to keep compiling the enclosing function
+        let result = body(self);
+        let mut compiler = self.functions.pop().expect("Function was pushed above");
+        result?;
+
+        compiler.function.upvalue_count = compiler.upvalues.len();
+        self.optimize(&mut compiler);
+        let function = self.alloc(Object::Function(compiler.function));
+        let constant = self.make_constant(Value::object(function), name.span);
+        self.emit_with(OpCode::Closure, constant, name.span);
+        for upvalue in compiler.upvalues {
+            self.emit_byte(u8::from(upvalue.is_local), name.span);
+            self.emit_byte(upvalue.index, name.span);
+        }
+        Ok(())
+    }
+
+    /// Compiles the rest of a declaration, whose name was already consumed.
+    fn function_body(&mut self) -> Result {
+        let (paren, brace) = match self.current().kind {
+            FunctionKind::Method | FunctionKind::Initializer => ("'(' after method name", "'{' before method body"),
+            FunctionKind::Script | FunctionKind::Function => ("'(' after function name", "'{' before function body"),
+        };
+        self.begin_scope();
+        self.consume(Type::LeftParen, paren)?;
+        self.parameters()?;
+        self.block_body(brace)
+    }
+
+    /// Compiles the rest of `fun (a) { ... }`, after the `fun`.
+    fn lambda_body(&mut self) -> Result {
+        self.begin_scope();
+        self.consume(Type::LeftParen, "'(' after 'fun'")?;
+        self.parameters()?;
+        self.block_body("'{' before function body")
+    }
+
+    /// Compiles `(a) => ...`, which `is_arrow` found at the current `(`.
+    fn arrow_body(&mut self) -> Result {
+        self.begin_scope();
+        self.advance();
+        self.parameters()?;
+        let arrow = self.consume(Type::Arrow, "'=>' after parameters")?.span;
+        self.expression()?;
+        self.emit(OpCode::Return, arrow);
+        Ok(())
+    }
+
+    /// Should be called after consuming the `(`, and consumes the `)`.
+    fn parameters(&mut self) -> Result {
+        if !self.check(&Type::RightParen) {
+            loop {
+                if self.current().function.arity >= MAX_ARGUMENTS {
+                    let error = self.error(ParseErrorKind::TooManyParameters);
+                    self.errors.push(error.into());
+                }
+                self.current().function.arity += 1;
+                let param = self.consume_identifier("parameter name")?;
+                self.declare_variable(&param);
+                self.mark_initialized();
+                if !self.matches(&Type::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(Type::RightParen, "')' after parameters")?;
+        Ok(())
+    }
+
+    fn block_body(&mut self, brace: &'static str) -> Result {
+        self.consume(Type::LeftBrace, brace)?;
+        self.block()?;
+
+        // Locals don't need to be popped, as returning discards the whole frame
+        let brace = self.previous().span;
+        self.emit_return(brace);
+        Ok(())
+    }
+
+    fn var_declaration(&mut self) -> Result {
+        let name = self.consume_identifier("variable name")?;
+        let global = self.declare_variable(&name);
+
+        let result = self.var_initializer(&name);
+        // Defined even after errors, to not report it again when used
+        self.define_variable(global, name.span);
+        result
+    }
+
+    fn var_initializer(&mut self, name: &Identifier) -> Result {
+        if self.matches(&Type::Equal) {
+            self.expression()?;
+        } else {
+            self.emit(OpCode::Nil, name.span);
+        }
+        self.consume(Type::Semicolon, "';' after variable declaration")?;
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result {
+        if self.matches(&Type::Keyword(Keyword::For)) {
+            self.for_statement()
+        } else if self.matches(&Type::Keyword(Keyword::If)) {
+            self.if_statement()
+        } else if self.matches(&Type::Keyword(Keyword::Print)) {
+            self.print_statement()
+        } else if self.matches(&Type::Keyword(Keyword::Return)) {
+            self.return_statement()
+        } else if self.matches(&Type::Keyword(Keyword::While)) {
+            self.while_statement()
+        } else if self.matches(&Type::Keyword(Keyword::Break)) {
+            self.break_statement()
+        } else if self.matches(&Type::Keyword(Keyword::Continue)) {
+            self.continue_statement()
+        } else if self.matches(&Type::LeftBrace) {
+            self.begin_scope();
+            let result = self.block();
+            let brace = self.previous().span;
+            self.end_scope(brace);
+            result
+        } else {
+            self.expression_statement()
+        }
+    }
+
+    fn for_statement(&mut self) -> Result {
+        let keyword = self.previous().span;
+        // Variables declared in the initializer are scoped to the loop
+        self.begin_scope();
+        let result = self.for_clauses_and_body(keyword);
+        self.end_scope(keyword);
+        result
+    }
+
+    fn for_clauses_and_body(&mut self, keyword: Span) -> Result {
+        self.consume(Type::LeftParen, "'(' after 'for'")?;
+        if self.matches(&Type::Semicolon) {
+            // No initializer
+        } else if self.matches(&Type::Keyword(Keyword::Var)) {
+            self.var_declaration()?;
+        } else {
+            self.expression_statement()?;
+        }
+
+        let mut loop_start = self.chunk().code.len();
+        let mut exit_jump = None;
+        if !self.check(&Type::Semicolon) {
+            self.expression()?;
+            exit_jump = Some(self.emit_jump(OpCode::JumpIfFalse, keyword));
+            self.emit(OpCode::Pop, keyword);
+        }
+        self.consume(Type::Semicolon, "';' after loop condition")?;
+
+        // The increment comes before the body in the code, but runs after it
+        if !self.check(&Type::RightParen) {
+            let body_jump = self.emit_jump(OpCode::Jump, keyword);
+            let increment_start = self.chunk().code.len();
+            self.expression()?;
+            self.emit(OpCode::Pop, keyword);
+            self.emit_loop(loop_start, keyword);
+            loop_start = increment_start;
+            self.patch_jump(body_jump, keyword);
+        }
+        self.consume(Type::RightParen, "')' after for clauses")?;
+
+        self.loop_body(loop_start, keyword)?;
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump, keyword);
+            self.emit(OpCode::Pop, keyword);
+        }
+        self.end_loop(keyword);
+        Ok(())
+    }
+
+    fn if_statement(&mut self) -> Result {
+        let keyword = self.previous().span;
+        self.consume(Type::LeftParen, "'(' after 'if'")?;
+        self.expression()?;
+        self.consume(Type::RightParen, "')' after if condition")?;
+
+        let then_jump = self.emit_jump(OpCode::JumpIfFalse, keyword);
+        self.emit(OpCode::Pop, keyword);
+        self.statement()?;
+        let else_jump = self.emit_jump(OpCode::Jump, keyword);
+
+        self.patch_jump(then_jump, keyword);
+        self.emit(OpCode::Pop, keyword);
+        if self.matches(&Type::Keyword(Keyword::Else)) {
+            self.statement()?;
+        }
+        self.patch_jump(else_jump, keyword);
+        Ok(())
+    }
+
+    fn print_statement(&mut self) -> Result {
+        let keyword = self.previous().span;
+        self.expression()?;
+        self.consume(Type::Semicolon, "';' after value")?;
+        self.emit(OpCode::Print, keyword);
+        Ok(())
+    }
+
+    fn return_statement(&mut self) -> Result {
+        let keyword = self.previous().span;
+        if self.current().kind == FunctionKind::Script {
+            self.resolve_error(ResolveErrorKind::ReturnFromTopLevel, keyword);
+        }
+
+        if self.check(&Type::Semicolon) {
+            self.advance();
+            self.emit_return(keyword);
+        } else {
+            if self.current().kind == FunctionKind::Initializer {
+                self.resolve_error(ResolveErrorKind::ReturnValueFromInitializer, keyword);
+            }
+            self.expression()?;
+            self.consume(Type::Semicolon, "';' after return value")?;
+            self.emit(OpCode::Return, keyword);
+        }
+        Ok(())
+    }
+
+    fn while_statement(&mut self) -> Result {
+        let keyword = self.previous().span;
+        let loop_start = self.chunk().code.len();
+        self.consume(Type::LeftParen, "'(' after 'while'")?;
+        self.expression()?;
+        self.consume(Type::RightParen, "')' after condition")?;
+
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse, keyword);
+        self.emit(OpCode::Pop, keyword);
+        self.loop_body(loop_start, keyword)?;
+
+        self.patch_jump(exit_jump, keyword);
+        self.emit(OpCode::Pop, keyword);
+        self.end_loop(keyword);
+        Ok(())
+    }
+
+    /// Compiles the body of a loop, which continues at `start`, and jumps
+    /// back there after it.
+    ///
+    /// Must be followed by `end_loop` where breaks land, even if this fails.
+    fn loop_body(&mut self, start: usize, keyword: Span) -> Result {
+        let scope_depth = self.current().scope_depth;
+        self.current().loops.push(Loop { start, scope_depth, breaks: vec![] });
+        self.statement()?;
+        self.emit_loop(start, keyword);
+        Ok(())
+    }
+
+    fn end_loop(&mut self, keyword: Span) {
+        let Some(Loop { breaks, .. }) = self.current().loops.pop() else {
+            unreachable!("Loops end after their body");
+        };
+        for jump in breaks {
+            self.patch_jump(jump, keyword);
+        }
+    }
+
+    fn break_statement(&mut self) -> Result {
+        let keyword = self.previous().span;
+        self.consume(Type::Semicolon, "';' after 'break'")?;
+        let Some(scope_depth) = self.current().loops.last().map(|innermost| innermost.scope_depth) else {
+            self.resolve_error(ResolveErrorKind::BreakOutsideLoop, keyword);
+            return Ok(());
+        };
+
+        self.pop_locals(scope_depth, keyword);
+        let jump = self.emit_jump(OpCode::Jump, keyword);
+        if let Some(innermost) = self.current().loops.last_mut() {
+            innermost.breaks.push(jump);
+        }
+        Ok(())
+    }
+
+    fn continue_statement(&mut self) -> Result {
+        let keyword = self.previous().span;
+        self.consume(Type::Semicolon, "';' after 'continue'")?;
+        let Some(&Loop { start, scope_depth, .. }) = self.current().loops.last() else {
+            self.resolve_error(ResolveErrorKind::ContinueOutsideLoop, keyword);
+            return Ok(());
+        };
+
+        self.pop_locals(scope_depth, keyword);
+        self.emit_loop(start, keyword);
+        Ok(())
+    }
+
+    /// Should be called after consuming the `{`.
+    fn block(&mut self) -> Result {
+        while !self.check(&Type::RightBrace) && !self.is_at_end() {
+            self.declaration();
+        }
+        self.consume(Type::RightBrace, "'}' after block")?;
+        Ok(())
+    }
+
+    fn expression_statement(&mut self) -> Result {
+        self.expression()?;
+        self.consume(Type::Semicolon, "';' after expression")?;
+        let semicolon = self.previous().span;
+        self.emit(OpCode::Pop, semicolon);
+        Ok(())
+    }
+
+    fn expression(&mut self) -> Result {
+        self.parse_precedence(Precedence::Assignment)
+    }
+
+    /// Compiles an expression whose operators bind at least as strongly as
+    /// `precedence`.
+    fn parse_precedence(&mut self, precedence: Precedence) -> Result {
+        let can_assign = precedence <= Precedence::Assignment;
+        self.prefix(can_assign)?;
+
+        while let Some(token) = self.peek() {
+            if Precedence::of_infix(&token.r#type) < precedence {
+                break;
+            }
+            self.advance();
+            self.infix(can_assign)?;
+        }
+
+        if can_assign && self.matches(&Type::Equal) {
+            // Reported without unwinding, since the compiler is not confused
+            let equals = self.previous().span;
+            self.errors.push(parser::Error { kind: ParseErrorKind::InvalidAssignmentTarget, span: equals }.into());
+            self.expression()?;
+        }
+        Ok(())
+    }
+
+    fn prefix(&mut self, can_assign: bool) -> Result {
+        let Some(token) = self.peek() else {
+            return Err(self.error(ParseErrorKind::ExpectExpression));
+        };
+        let span = token.span;
+
+        match &token.r#type {
+            Type::LeftParen if self.is_arrow() => {
+                self.function(FunctionKind::Function, &lambda(span), Self::arrow_body)?;
+            }
+            Type::LeftParen => {
+                self.advance();
+                self.expression()?;
+                self.consume(Type::RightParen, "')' after expression")?;
+            }
+            Type::LeftBracket => {
+                self.advance();
+                self.list(span)?;
+            }
+            Type::Keyword(Keyword::Fun) => {
+                self.advance();
+                self.function(FunctionKind::Function, &lambda(span), Self::lambda_body)?;
+            }
+            Type::LeftBrace => {
+                self.advance();
+                self.map(span)?;
+            }
+            Type::Minus | Type::Bang => {
+                let opcode = if token.r#type == Type::Minus { OpCode::Negate } else { OpCode::Not };
+                self.advance();
+                self.parse_precedence(Precedence::Unary)?;
+                self.emit(opcode, span);
+            }
+            Type::NumberLiteral(number) => {
+                let n = match number {
+                    // Values only have doubles, which hold every `i32` exactly, so integer literals
+                    // become the same numbers as the floats they equal, also when NaN-boxed
+                    token::NumberLiteral::Integer(n) => f64::from(*n),
+                    token::NumberLiteral::Float(n) => *n,
+                };
+                self.advance();
+                self.emit_constant(Value::number(n), span);
+            }
+            Type::StringLiteral(s) => {
+                let s = s.clone();
+                self.advance();
+                let s = self.intern(&s);
+                self.emit_constant(Value::object(s), span);
+            }
+            Type::Keyword(Keyword::Nil) => {
+                self.advance();
+                self.emit(OpCode::Nil, span);
+            }
+            Type::Keyword(Keyword::True) => {
+                self.advance();
+                self.emit(OpCode::True, span);
+            }
+            Type::Keyword(Keyword::False) => {
+                self.advance();
+                self.emit(OpCode::False, span);
+            }
+            Type::Identifier(name) => {
+                let name = Identifier { name: name.clone(), span };
+                self.advance();
+                self.named_variable(&name, can_assign)?;
+            }
+            Type::Keyword(Keyword::This) => {
+                self.advance();
+                if self.classes.is_empty() {
+                    self.resolve_error(ResolveErrorKind::ThisOutsideClass, span);
+                }
+                self.named_variable(&Identifier { name: Symbol::intern("this"), span }, false)?;
+            }
+            Type::Keyword(Keyword::Super) => {
+                self.advance();
+                self.super_expression(span)?;
+            }
+            _ => return Err(self.error(ParseErrorKind::ExpectExpression)),
+        }
+        Ok(())
+    }
+
+    /// Should be called after consuming `super`, at `keyword`.
+    fn super_expression(&mut self, keyword: Span) -> Result {
+        self.consume(Type::Dot, "'.' after 'super'")?;
+        let method = self.consume_identifier("superclass method name")?;
+        match self.classes.last() {
+            None => self.resolve_error(ResolveErrorKind::SuperOutsideClass, keyword),
+            Some(ClassCompiler { has_superclass: false }) => {
+                self.resolve_error(ResolveErrorKind::SuperWithoutSuperclass, keyword);
+            }
+            Some(ClassCompiler { has_superclass: true }) => (),
+        }
+
+        let constant = self.identifier_constant(&method);
+        self.named_variable(&Identifier { name: Symbol::intern("this"), span: keyword }, false)?;
+        self.named_variable(&Identifier { name: Symbol::intern("super"), span: keyword }, false)?;
+        self.emit_with(OpCode::GetSuper, constant, method.span);
+        Ok(())
+    }
+
+    /// Should be called after consuming the operator.
+    fn infix(&mut self, can_assign: bool) -> Result {
+        let operator = self.previous().clone();
+        let span = operator.span;
+        let precedence = Precedence::of_infix(&operator.r#type);
+
+        match operator.r#type {
+            Type::LeftParen => self.call()?,
+            Type::Dot => {
+                let name = self.consume_identifier("property name after '.'")?;
+                let constant = self.identifier_constant(&name);
+                if can_assign && self.matches(&Type::Equal) {
+                    self.expression()?;
+                    self.emit_property(OpCode::SetProperty, constant, name.span);
+                } else if self.matches(&Type::LeftParen) {
+                    let (count, paren) = self.arguments()?;
+                    self.emit_property(OpCode::Invoke, constant, name.span);
+                    self.emit_byte(count, paren);
+                } else {
+                    self.emit_property(OpCode::GetProperty, constant, name.span);
+                }
+            }
+            Type::LeftBracket => {
+                self.expression()?;
+                let bracket = self.consume(Type::RightBracket, "']' after index")?.span;
+                if can_assign && self.matches(&Type::Equal) {
+                    self.expression()?;
+                    self.emit(OpCode::SetIndex, bracket);
+                } else {
+                    self.emit(OpCode::GetIndex, bracket);
+                }
+            }
+            Type::Keyword(Keyword::And) => {
+                let end_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit(OpCode::Pop, span);
+                self.parse_precedence(Precedence::And.next())?;
+                self.patch_jump(end_jump, span);
+            }
+            Type::Keyword(Keyword::Or) => {
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                let end_jump = self.emit_jump(OpCode::Jump, span);
+                self.patch_jump(else_jump, span);
+                self.emit(OpCode::Pop, span);
+                self.parse_precedence(Precedence::Or.next())?;
+                self.patch_jump(end_jump, span);
+            }
+            Type::QuestionQuestion => {
+                let else_jump = self.emit_jump(OpCode::JumpIfNil, span);
+                let end_jump = self.emit_jump(OpCode::Jump, span);
+                self.patch_jump(else_jump, span);
+                self.emit(OpCode::Pop, span);
+                self.parse_precedence(Precedence::Coalesce.next())?;
+                self.patch_jump(end_jump, span);
+            }
+            Type::Question => {
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse, span);
+                self.emit(OpCode::Pop, span);
+                self.expression()?;
+                let colon = self.consume(Type::Colon, "':' after then branch of conditional")?.span;
+                let end_jump = self.emit_jump(OpCode::Jump, colon);
+                self.patch_jump(else_jump, span);
+                self.emit(OpCode::Pop, colon);
+                // Right-associative, so that conditionals chain like `else if`
+                self.parse_precedence(Precedence::Conditional)?;
+                self.patch_jump(end_jump, colon);
+            }
+            r#type => {
+                self.parse_precedence(precedence.next())?;
+                let opcodes: &[OpCode] = match r#type {
+                    Type::Plus => &[OpCode::Add],
+                    Type::Minus => &[OpCode::Subtract],
+                    Type::Star => &[OpCode::Multiply],
+                    Type::Slash => &[OpCode::Divide],
+                    Type::EqualEqual => &[OpCode::Equal],
+                    Type::BangEqual => &[OpCode::Equal, OpCode::Not],
+                    Type::Greater => &[OpCode::Greater],
+                    Type::GreaterEqual => &[OpCode::GreaterEqual],
+                    Type::Less => &[OpCode::Less],
+                    Type::LessEqual => &[OpCode::LessEqual],
+                    _ => unreachable!("Only operators have an infix precedence"),
+                };
+                for &opcode in opcodes {
+                    self.emit(opcode, span);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Should be called after consuming the `[`, at `bracket`.
+    fn list(&mut self, bracket: Span) -> Result {
+        let mut count = 0;
+        if !self.check(&Type::RightBracket) {
+            loop {
+                if count >= MAX_ARGUMENTS {
+                    let error = self.error(ParseErrorKind::TooManyElements);
+                    self.errors.push(error.into());
+                }
+                self.expression()?;
+                count += 1;
+                if !self.matches(&Type::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(Type::RightBracket, "']' after list elements")?;
+        self.emit_with(OpCode::BuildList, count.min(MAX_ARGUMENTS) as u16, bracket);
+        Ok(())
+    }
+
+    /// Should be called after consuming the `{`, at `brace`.
+    fn map(&mut self, brace: Span) -> Result {
+        let mut count = 0;
+        if !self.check(&Type::RightBrace) {
+            loop {
+                if count >= MAX_ARGUMENTS {
+                    let error = self.error(ParseErrorKind::TooManyElements);
+                    self.errors.push(error.into());
+                }
+                self.expression()?;
+                self.consume(Type::Colon, "':' after map key")?;
+                self.expression()?;
+                count += 1;
+                if !self.matches(&Type::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(Type::RightBrace, "'}' after map entries")?;
+        self.emit_with(OpCode::BuildMap, count.min(MAX_ARGUMENTS) as u16, brace);
+        Ok(())
+    }
+
+    /// Should be called after consuming the `(`.
+    fn call(&mut self) -> Result {
+        let (count, paren) = self.arguments()?;
+        self.emit_with(OpCode::Call, count.into(), paren);
+        Ok(())
+    }
+
+    /// Compiles the arguments of a call, after its `(`, and returns how many
+    /// there are and the span of its `)`.
+    fn arguments(&mut self) -> Result<(u8, Span)> {
+        let mut count = 0;
+        if !self.check(&Type::RightParen) {
+            loop {
+                if count >= MAX_ARGUMENTS {
+                    let error = self.error(ParseErrorKind::TooManyArguments);
+                    self.errors.push(error.into());
+                }
+                self.expression()?;
+                count += 1;
+                if !self.matches(&Type::Comma) {
+                    break;
+                }
+            }
+        }
+        let paren = self.consume(Type::RightParen, "')' after arguments")?.span;
+        Ok((count.min(MAX_ARGUMENTS) as u8, paren))
+    }
+
+    fn named_variable(&mut self, name: &Identifier, can_assign: bool) -> Result {
+        let current = self.functions.len() - 1;
+        let (get, set, operand) = if let Some(slot) = self.resolve_local(current, name) {
+            (OpCode::GetLocal, OpCode::SetLocal, u16::from(slot))
+        } else if let Some(index) = self.resolve_upvalue(current, name) {
+            (OpCode::GetUpvalue, OpCode::SetUpvalue, u16::from(index))
+        } else {
+            (OpCode::GetGlobal, OpCode::SetGlobal, self.identifier_constant(name))
+        };
+
+        if can_assign && self.matches(&Type::Equal) {
+            self.expression()?;
+            self.emit_with(set, operand, name.span);
+        } else {
+            self.emit_with(get, operand, name.span);
+        }
+        Ok(())
+    }
+
+    /// Returns the stack slot of the local called `name` in the function at
+    /// `function` in `self.functions`, if any.
+    fn resolve_local(&mut self, function: usize, name: &Identifier) -> Option<u8> {
+        let locals = &self.functions[function].locals;
+        let slot = locals.iter().rposition(|local| local.name == name.name)?;
+        // Functions declared in the initializer only run after it finishes
+        if locals[slot].depth.is_none() && function == self.functions.len() - 1 {
+            self.resolve_error(ResolveErrorKind::ReadInOwnInitializer, name.span);
+        }
+        Some(slot as u8)
+    }
+
+    /// Returns the index of the upvalue capturing the variable called `name`
+    /// of a function enclosing the one at `function`, if any, adding upvalues
+    /// to every function in between.
+    fn resolve_upvalue(&mut self, function: usize, name: &Identifier) -> Option<u8> {
+        let enclosing = function.checked_sub(1)?;
+        if let Some(slot) = self.resolve_local(enclosing, name) {
+            self.functions[enclosing].locals[usize::from(slot)].is_captured = true;
+            return Some(self.add_upvalue(function, Upvalue { index: slot, is_local: true }, name.span));
+        }
+
+        let index = self.resolve_upvalue(enclosing, name)?;
+        Some(self.add_upvalue(function, Upvalue { index, is_local: false }, name.span))
+    }
+
+    fn add_upvalue(&mut self, function: usize, upvalue: Upvalue, span: Span) -> u8 {
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(index) = upvalues.iter().position(|&existing| existing == upvalue) {
+            return index as u8;
+        }
+        if upvalues.len() >= MAX_UPVALUES {
+            self.compile_error(ErrorKind::TooManyUpvalues, span);
+            return 0;
+        }
+        upvalues.push(upvalue);
+        (upvalues.len() - 1) as u8
+    }
+
+    /// Declares locals in the current scope, and returns the constant with the
+    /// name of globals.
+    fn declare_variable(&mut self, name: &Identifier) -> Option<u16> {
+        if self.current().scope_depth == 0 {
+            return Some(self.identifier_constant(name));
+        }
+
+        let compiler = self.current();
+        let scope_depth = compiler.scope_depth;
+        let previous = compiler
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|depth| depth == scope_depth))
+            .find(|local| local.name == name.name)
+            .map(|local| local.span);
+        if let Some(previous) = previous {
+            let error = resolver::Error { kind: ResolveErrorKind::AlreadyDeclared, span: name.span, previous: Some(previous) };
+            self.errors.push(error.into());
+        }
+
+        if self.current().locals.len() >= MAX_LOCALS {
+            self.compile_error(ErrorKind::TooManyLocals, name.span);
+        } else {
+            let local = Local { name: name.name.clone(), span: name.span, depth: None, is_captured: false };
+            self.current().locals.push(local);
+        }
+        None
+    }
+
+    /// `global` is what `declare_variable` returned for the variable.
+    fn define_variable(&mut self, global: Option<u16>, span: Span) {
+        match global {
+            Some(constant) => self.emit_with(OpCode::DefineGlobal, constant, span),
+            None => self.mark_initialized(),
+        }
+    }
+
+    /// Makes the last declared local usable.
+    fn mark_initialized(&mut self) {
+        let compiler = self.current();
+        let scope_depth = compiler.scope_depth;
+        if let Some(local) = compiler.locals.last_mut().filter(|_| scope_depth > 0) {
+            local.depth = Some(scope_depth);
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    /// `span` is where the pops of the locals of the scope come from.
+    fn end_scope(&mut self, span: Span) {
+        self.current().scope_depth -= 1;
+        let scope_depth = self.current().scope_depth;
+        let count = self.pop_locals(scope_depth, span);
+        let locals = &mut self.current().locals;
+        locals.truncate(locals.len() - count);
+    }
+
+    /// Emits the pops of the locals in scopes deeper than `scope_depth`,
+    /// innermost first, and returns how many there are.
+    fn pop_locals(&mut self, scope_depth: usize, span: Span) -> usize {
+        let compiler = self.current();
+        let in_scope = compiler.locals.iter().rev().take_while(|local| local.depth.is_none_or(|depth| depth > scope_depth));
+        let captured: Vec<bool> = in_scope.map(|local| local.is_captured).collect();
+        // Captured locals move off the stack, into their upvalues
+        for &is_captured in &captured {
+            self.emit(if is_captured { OpCode::CloseUpvalue } else { OpCode::Pop }, span);
+        }
+        captured.len()
+    }
+
+    fn identifier_constant(&mut self, name: &Identifier) -> u16 {
+        let name_object = self.intern(&name.name);
+        self.make_constant(Value::object(name_object), name.span)
+    }
+
+    /// Optimizes a function once it is compiled, unless errors made its code
+    /// invalid.
+    fn optimize(&mut self, compiler: &mut FunctionCompiler) {
+        if self.level == OptLevel::O1 && self.errors.is_empty() {
+            optimizer::optimize(self.heap, &mut compiler.function.chunk);
+        }
+    }
+
+    /// Allocates `object`, collecting garbage first if needed, like the VM.
+    fn alloc(&mut self, object: Object) -> ObjRef {
+        if self.heap.should_collect() {
+            let mut roots = self.roots();
+            object.trace(&mut roots);
+            self.heap.collect(roots);
+        }
+        self.heap.alloc(object)
+    }
+
+    /// Like `alloc`, for strings.
+    fn intern(&mut self, s: &str) -> ObjRef {
+        if self.heap.should_collect() {
+            let roots = self.roots();
+            self.heap.collect(roots);
+        }
+        self.heap.intern(s)
+    }
+
+    /// Returns the objects of the VM and of the functions being compiled.
+    fn roots(&self) -> Vec<ObjRef> {
+        let mut roots = self.roots.clone();
+        for compiler in &self.functions {
+            roots.extend(compiler.function.name);
+            roots.extend(compiler.function.chunk.constants.iter().filter_map(|constant| constant.as_object()));
+        }
+        roots
+    }
+
+    fn current(&mut self) -> &mut FunctionCompiler {
+        self.functions.last_mut().expect("Script is always being compiled")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current().function.chunk
+    }
+
+    /// `span` is what the byte was compiled from.
+    fn emit_byte(&mut self, byte: u8, span: Span) {
+        let line = self.line_starts.partition_point(|&start| start <= span.start);
+        self.chunk().write(byte, line, span);
+    }
+
+    fn emit(&mut self, opcode: OpCode, span: Span) {
+        self.emit_byte(opcode as u8, span);
+    }
+
+    /// Emits `operand` as a byte or as a big-endian `u16`, depending on
+    /// `opcode`.
+    fn emit_with(&mut self, opcode: OpCode, operand: u16, span: Span) {
+        self.emit(opcode, span);
+        match opcode.operand() {
+            Operand::Byte => self.emit_byte(operand as u8, span),
+            _ => {
+                for byte in operand.to_be_bytes() {
+                    self.emit_byte(byte, span);
+                }
+            }
+        }
+    }
+
+    /// Emits a property instruction, with a new cache.
+    fn emit_property(&mut self, opcode: OpCode, constant: u16, span: Span) {
+        let caches = &mut self.current().function.caches;
+        // Instructions past the last cache share it, which only makes them
+        // slower
+        if caches.len() <= usize::from(u16::MAX) {
+            caches.push(Cache::Empty);
+        }
+        let cache = (caches.len() - 1) as u16;
+        self.emit_with(opcode, constant, span);
+        for byte in cache.to_be_bytes() {
+            self.emit_byte(byte, span);
+        }
+    }
+
+    fn emit_constant(&mut self, value: Value, span: Span) {
+        let constant = self.make_constant(value, span);
+        self.emit_with(OpCode::Constant, constant, span);
+    }
+
+    fn make_constant(&mut self, value: Value, span: Span) -> u16 {
+        let constant = self.chunk().add_constant(value);
+        if constant >= MAX_CONSTANTS {
+            self.compile_error(ErrorKind::TooManyConstants, span);
+            return 0;
+        }
+        constant as u16
+    }
+
+    fn emit_return(&mut self, span: Span) {
+        if self.current().kind == FunctionKind::Initializer {
+            // Initializers return the instance, even when returning early
+            self.emit_with(OpCode::GetLocal, 0, span);
+        } else {
+            self.emit(OpCode::Nil, span);
+        }
+        self.emit(OpCode::Return, span);
+    }
+
+    /// Emits a jump to be patched, and returns the offset of its operand.
+    fn emit_jump(&mut self, opcode: OpCode, span: Span) -> usize {
+        self.emit(opcode, span);
+        self.emit_byte(0xff, span);
+        self.emit_byte(0xff, span);
+        self.chunk().code.len() - 2
+    }
+
+    /// Makes the jump whose operand is at `offset` land on the next
+    /// instruction to be emitted.
+    fn patch_jump(&mut self, offset: usize, span: Span) {
+        let jump = self.chunk().code.len() - offset - 2;
+        let Ok(jump) = u16::try_from(jump) else {
+            return self.compile_error(ErrorKind::JumpTooLarge, span);
+        };
+        self.chunk().code[offset..offset + 2].copy_from_slice(&jump.to_be_bytes());
+    }
+
+    fn emit_loop(&mut self, loop_start: usize, span: Span) {
+        self.emit(OpCode::Loop, span);
+        // Also jumps over the operand itself
+        let jump = self.chunk().code.len() - loop_start + 2;
+        let jump = u16::try_from(jump).unwrap_or_else(|_| {
+            self.compile_error(ErrorKind::LoopTooLarge, span);
+            0
+        });
+        for byte in jump.to_be_bytes() {
+            self.emit_byte(byte, span);
+        }
+    }
+
+    fn consume(&mut self, r#type: Type, expected: &'static str) -> Result<&Token> {
+        if self.check(&r#type) {
+            self.advance();
+            Ok(self.previous())
+        } else {
+            Err(self.error(ParseErrorKind::Expect(expected)))
+        }
+    }
+
+    /// Like `consume`, but for any identifier.
+    ///
+    /// `expected` describes the role of the identifier, as in "variable name".
+    fn consume_identifier(&mut self, expected: &'static str) -> Result<Identifier> {
+        match self.peek() {
+            Some(Token { r#type: Type::Identifier(name), span }) => {
+                let identifier = Identifier { name: name.clone(), span: *span };
+                self.advance();
+                Ok(identifier)
+            }
+            _ => Err(self.error(ParseErrorKind::Expect(expected))),
+        }
+    }
+
+    /// Consumes the current token if it is of type `r#type`.
+    fn matches(&mut self, r#type: &Type) -> bool {
+        let is_match = self.check(r#type);
+        if is_match {
+            self.advance();
+        }
+        is_match
+    }
+
+    fn check(&self, r#type: &Type) -> bool {
+        self.peek().is_some_and(|token| &token.r#type == r#type)
+    }
+
+    /// Like `check`, but for the token after the current one.
+    fn check_next(&self, r#type: &Type) -> bool {
+        self.tokens.get(self.position + 1).is_some_and(|token| &token.r#type == r#type)
+    }
+
+    /// Whether the current `(` starts the parameters of an arrow function,
+    /// rather than a grouping.
+    fn is_arrow(&self) -> bool {
+        let mut rest = self.tokens[self.position + 1..]
+            .iter()
+            .map(|token| &token.r#type)
+            .skip_while(|r#type| matches!(r#type, Type::Identifier(_) | Type::Comma));
+        rest.next() == Some(&Type::RightParen) && rest.next() == Some(&Type::Arrow)
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn previous(&self) -> &Token {
+        &self.tokens[self.position - 1]
+    }
+
+    fn advance(&mut self) {
+        if !self.is_at_end() {
+            self.position += 1;
+        }
+    }
+
+    /// Builds an error pointing at the current token.
+    fn error(&self, kind: ParseErrorKind) -> parser::Error {
+        let span = self.peek().map_or(self.end, |token| token.span);
+        parser::Error { kind, span }
+    }
+
+    fn resolve_error(&mut self, kind: ResolveErrorKind, span: Span) {
+        self.errors.push(resolver::Error { kind, span, previous: None }.into());
+    }
+
+    fn compile_error(&mut self, kind: ErrorKind, span: Span) {
+        self.errors.push(Error { kind, span }.into());
+    }
+
+    /// Discards tokens until the probable start of the next statement, so
+    /// that one mistake does not cause a cascade of errors.
+    fn synchronize(&mut self) {
+        self.advance();
+        while !self.is_at_end() {
+            if self.previous().r#type == Type::Semicolon {
+                return;
+            }
+            match self.peek().map(|token| &token.r#type) {
+                Some(Type::Keyword(
+                    Keyword::Class
+                    | Keyword::Fun
+                    | Keyword::Var
+                    | Keyword::For
+                    | Keyword::If
+                    | Keyword::While
+                    | Keyword::Print
+                    | Keyword::Return
+                    | Keyword::Break
+                    | Keyword::Continue,
+                )) => return,
+                _ => self.advance(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::vm::disassembler::disassemble;
+
+    fn listing(source: &str) -> String {
+        let mut heap = Heap::default();
+        let script = compile(source, 0, &mut heap, vec![], OptLevel::O0).expect("Compiled invalid code");
+        disassemble(&heap, script)
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        let Err(diagnostics) = compile(source, 0, &mut Heap::default(), vec![], OptLevel::O1) else {
+            panic!("Compiled invalid code");
+        };
+        diagnostics.iter().map(|diagnostic| diagnostic.message().to_string()).collect()
+    }
+
+    #[test]
+    fn compiles_expressions_by_precedence() {
+        assert_eq!(
+            listing("print -1 + 2 * 3 >= 4;"),
+            "\
+== <script> ==
+0000    1 OP_CONSTANT         0 '1'
+0003    | OP_NEGATE
+0004    | OP_CONSTANT         1 '2'
+0007    | OP_CONSTANT         2 '3'
+0010    | OP_MULTIPLY
+0011    | OP_ADD
+0012    | OP_CONSTANT         3 '4'
+0015    | OP_GREATER_EQUAL
+0016    | OP_PRINT
+0017    | OP_NIL
+0018    | OP_RETURN
+",
+        );
+    }
+
+    #[test]
+    fn compiles_globals_and_locals() {
+        assert_eq!(
+            listing("var a = \"global\";\n{\n  var b = a;\n  b = nil;\n}"),
+            "\
+== <script> ==
+0000    1 OP_CONSTANT         1 'global'
+0003    | OP_DEFINE_GLOBAL    0 'a'
+0006    3 OP_GET_GLOBAL       0 'a'
+0009    4 OP_NIL
+0010    | OP_SET_LOCAL        1
+0012    | OP_POP
+0013    5 OP_POP
+0014    | OP_NIL
+0015    | OP_RETURN
+",
+        );
+    }
+
+    #[test]
+    fn compiles_control_flow_into_jumps() {
+        assert_eq!(
+            listing("while (true) if (false) print 1; else print 2;"),
+            "\
+== <script> ==
+0000    1 OP_TRUE
+0001    | OP_JUMP_IF_FALSE    1 -> 25
+0004    | OP_POP
+0005    | OP_FALSE
+0006    | OP_JUMP_IF_FALSE    6 -> 17
+0009    | OP_POP
+0010    | OP_CONSTANT         0 '1'
+0013    | OP_PRINT
+0014    | OP_JUMP            14 -> 22
+0017    | OP_POP
+0018    | OP_CONSTANT         1 '2'
+0021    | OP_PRINT
+0022    | OP_LOOP            22 -> 0
+0025    | OP_POP
+0026    | OP_NIL
+0027    | OP_RETURN
+",
+        );
+    }
+
+    #[test]
+    fn compiles_conditionals_and_coalescing_into_jumps() {
+        assert_eq!(
+            listing("var a;\nprint a ? 1 : a ?? 2;"),
+            "\
+== <script> ==
+0000    1 OP_NIL
+0001    | OP_DEFINE_GLOBAL    0 'a'
+0004    2 OP_GET_GLOBAL       0 'a'
+0007    | OP_JUMP_IF_FALSE    7 -> 17
+0010    | OP_POP
+0011    | OP_CONSTANT         1 '1'
+0014    | OP_JUMP            14 -> 31
+0017    | OP_POP
+0018    | OP_GET_GLOBAL       0 'a'
+0021    | OP_JUMP_IF_NIL     21 -> 27
+0024    | OP_JUMP            24 -> 31
+0027    | OP_POP
+0028    | OP_CONSTANT         2 '2'
+0031    | OP_PRINT
+0032    | OP_NIL
+0033    | OP_RETURN
+",
+        );
+    }
+
+    #[test]
+    fn compiles_functions_into_closures() {
+        assert_eq!(
+            listing("fun add(a, b) {\n  return a + b;\n}\nprint add(1, 2);"),
+            "\
+== <script> ==
+0000    1 OP_CLOSURE          1 '<fn add>'
+0003    | OP_DEFINE_GLOBAL    0 'add'
+0006    4 OP_GET_GLOBAL       0 'add'
+0009    | OP_CONSTANT         2 '1'
+0012    | OP_CONSTANT         3 '2'
+0015    | OP_CALL             2
+0017    | OP_PRINT
+0018    | OP_NIL
+0019    | OP_RETURN
+== <fn add> ==
+0000    2 OP_GET_LOCAL        1
+0002    | OP_GET_LOCAL        2
+0004    | OP_ADD
+0005    | OP_RETURN
+0006    3 OP_NIL
+0007    | OP_RETURN
+",
+        );
+    }
+
+    #[test]
+    fn compiles_arrow_functions_into_closures() {
+        assert_eq!(
+            listing("var double = (x) =>\n  x * 2;"),
+            "\
+== <script> ==
+0000    1 OP_CLOSURE          1 '<fn lambda>'
+0003    | OP_DEFINE_GLOBAL    0 'double'
+0006    2 OP_NIL
+0007    | OP_RETURN
+== <fn lambda> ==
+0000    2 OP_GET_LOCAL        1
+0002    | OP_CONSTANT         0 '2'
+0005    | OP_MULTIPLY
+0006    1 OP_RETURN
+",
+        );
+    }
+
+    #[test]
+    fn captures_variables_of_enclosing_functions() {
+        assert_eq!(
+            listing("{\n  var x = 1;\n  fun f() {\n    fun g() { x = x + 1; }\n  }\n}"),
+            "\
+== <script> ==
+0000    2 OP_CONSTANT         0 '1'
+0003    3 OP_CLOSURE          1 '<fn f>'
+0006    |                     local 1
+0008    6 OP_POP
+0009    | OP_CLOSE_UPVALUE
+0010    | OP_NIL
+0011    | OP_RETURN
+== <fn f> ==
+0000    4 OP_CLOSURE          0 '<fn g>'
+0003    |                     upvalue 0
+0005    5 OP_NIL
+0006    | OP_RETURN
+== <fn g> ==
+0000    4 OP_GET_UPVALUE      0
+0002    | OP_CONSTANT         0 '1'
+0005    | OP_ADD
+0006    | OP_SET_UPVALUE      0
+0008    | OP_POP
+0009    | OP_NIL
+0010    | OP_RETURN
+",
+        );
+    }
+
+    #[test]
+    fn compiles_method_calls_into_invokes() {
+        assert_eq!(
+            listing("var a;\na.b = a.c;\na.d(1);"),
+            "\
+== <script> ==
+0000    1 OP_NIL
+0001    | OP_DEFINE_GLOBAL    0 'a'
+0004    2 OP_GET_GLOBAL       0 'a'
+0007    | OP_GET_GLOBAL       0 'a'
+0010    | OP_GET_PROPERTY     2 'c'
+0015    | OP_SET_PROPERTY     1 'b'
+0020    | OP_POP
+0021    3 OP_GET_GLOBAL       0 'a'
+0024    | OP_CONSTANT         4 '1'
+0027    | OP_INVOKE        (1 args)    3 'd'
+0033    | OP_POP
+0034    | OP_NIL
+0035    | OP_RETURN
+",
+        );
+    }
+
+    #[test]
+    fn compiles_lists_and_indices() {
+        assert_eq!(
+            listing("var xs = [1, 2];\nxs[0] = xs[1];"),
+            "\
+== <script> ==
+0000    1 OP_CONSTANT         1 '1'
+0003    | OP_CONSTANT         2 '2'
+0006    | OP_BUILD_LIST       2
+0008    | OP_DEFINE_GLOBAL    0 'xs'
+0011    2 OP_GET_GLOBAL       0 'xs'
+0014    | OP_CONSTANT         3 '0'
+0017    | OP_GET_GLOBAL       0 'xs'
+0020    | OP_CONSTANT         1 '1'
+0023    | OP_GET_INDEX
+0024    | OP_SET_INDEX
+0025    | OP_POP
+0026    | OP_NIL
+0027    | OP_RETURN
+",
+        );
+    }
+
+    #[test]
+    fn compiles_maps() {
+        assert_eq!(
+            listing("print {\"a\": 1, true: {}};"),
+            "\
+== <script> ==
+0000    1 OP_CONSTANT         0 'a'
+0003    | OP_CONSTANT         1 '1'
+0006    | OP_TRUE
+0007    | OP_BUILD_MAP        0
+0009    | OP_BUILD_MAP        2
+0011    | OP_PRINT
+0012    | OP_NIL
+0013    | OP_RETURN
+",
+        );
+    }
+
+    #[test]
+    fn reports_errors_like_the_tree_walker() {
+        assert_eq!(errors("print 1 +;"), ["Expect expression."]);
+        assert_eq!(errors("1 + 2 = 3;"), ["Invalid assignment target."]);
+        assert_eq!(errors("{ var a = a; }"), ["Can't read local variable in its own initializer."]);
+        assert_eq!(errors("{ var a; var a; }"), ["Already a variable with this name in this scope."]);
+        assert_eq!(errors("return;"), ["Can't return from top-level code."]);
+        assert_eq!(errors("class A < A {}"), ["A class can't inherit from itself."]);
+        assert_eq!(errors("class A { init() { return 1; } }"), ["Can't return a value from an initializer."]);
+        assert_eq!(errors("print this;"), ["Can't use 'this' outside of a class."]);
+        assert_eq!(errors("class A { f() { super.f(); } }"), ["Can't use 'super' in a class with no superclass."]);
+        assert_eq!(errors("class A { f( {} }"), ["Expect parameter name."]);
+        assert_eq!(errors("[1, 2;"), ["Expect ']' after list elements."]);
+        assert_eq!(errors("print {1 2};"), ["Expect ':' after map key."]);
+        assert_eq!(errors("print fun f() {};"), ["Expect '(' after 'fun'."]);
+        assert_eq!(errors("print a ? b;"), ["Expect ':' after then branch of conditional."]);
+        assert_eq!(errors("a ? b : c = d;"), ["Invalid assignment target."]);
+        assert_eq!(errors("break;"), ["Can't use 'break' outside of a loop."]);
+        assert_eq!(errors("while (true) { fun f() { continue; } }"), ["Can't use 'continue' outside of a loop."]);
+    }
+
+    #[test]
+    fn recovers_to_report_several_errors() {
+        assert_eq!(
+            errors("var 1;\nfun f() { print; }\nprint \"ok\";\n{ var x = ; }"),
+            ["Expect variable name.", "Expect expression.", "Expect expression."],
+        );
+    }
+
+    #[test]
+    fn addresses_constants_past_a_byte() {
+        let source: String = (0..300).map(|n| format!("var v{n} = {n};\n")).collect();
+
+        let listing = listing(&(source + "print v299;"));
+
+        assert!(listing.contains("0000    1 OP_CONSTANT         1 '0'\n0003    | OP_DEFINE_GLOBAL    0 'v0'"));
+        assert!(listing.contains("301 OP_GET_GLOBAL     598 'v299'\n"));
+    }
+}
@@ -0,0 +1,233 @@
+use std::fmt::Write;
+
+use super::chunk::{Chunk, OpCode, Operand};
+use super::heap::{Heap, ObjRef};
+use super::object::Object;
+use super::value::Value;
+
+/// Lists the instructions of `function`, then those of the functions declared
+/// in it.
+pub(crate) fn disassemble(heap: &Heap, function: ObjRef) -> String {
+    let mut output = String::new();
+    let mut functions = vec![function];
+    while let Some(function) = functions.pop() {
+        let chunk = &heap.function(function).chunk;
+        let _ = writeln!(output, "== {} ==", Value::object(function).display(heap));
+        let mut offset = 0;
+        while offset < chunk.code.len() {
+            offset = instruction(&mut output, heap, chunk, offset);
+        }
+
+        // Declared functions are constants of the chunk declaring them
+        let nested = chunk.constants.iter().rev().filter_map(|constant| constant.as_object());
+        let nested = nested.filter(|object| matches!(heap.get(*object), Object::Function(_)));
+        functions.extend(nested);
+    }
+
+    output
+}
+
+/// Writes the instruction at `offset` as a line, and returns the offset of the
+/// next one.
+pub(crate) fn instruction(output: &mut String, heap: &Heap, chunk: &Chunk, offset: usize) -> usize {
+    let _ = write!(output, "{offset:04} ");
+    let line = chunk.line(offset);
+    if offset > 0 && line == chunk.line(offset - 1) {
+        let _ = write!(output, "   | ");
+    } else {
+        let _ = write!(output, "{line:4} ");
+    }
+
+    let byte = chunk.code[offset];
+    let Ok(opcode) = OpCode::try_from(byte) else {
+        let _ = writeln!(output, "Unknown opcode {byte}");
+        return offset + 1;
+    };
+    let name = name(opcode);
+    match opcode.operand() {
+        Operand::Constant => {
+            let constant = short(chunk, offset + 1);
+            let value = chunk.constants[constant].display(heap);
+            let _ = writeln!(output, "{name:<16} {constant:4} '{value}'");
+            offset + 3
+        }
+        // Caches are not shown, as they only change how fast code runs
+        Operand::Property => {
+            let constant = short(chunk, offset + 1);
+            let value = chunk.constants[constant].display(heap);
+            let _ = writeln!(output, "{name:<16} {constant:4} '{value}'");
+            offset + 5
+        }
+        Operand::Invoke => {
+            let (constant, count) = (short(chunk, offset + 1), chunk.code[offset + 5]);
+            let value = chunk.constants[constant].display(heap);
+            let _ = writeln!(output, "{name:<16} ({count} args) {constant:4} '{value}'");
+            offset + 6
+        }
+        Operand::Closure => {
+            let constant = short(chunk, offset + 1);
+            let function = chunk.constants[constant];
+            let _ = writeln!(output, "{name:<16} {constant:4} '{}'", function.display(heap));
+            let Some(function) = function.as_object() else {
+                unreachable!("Closures are made of functions");
+            };
+
+            let mut offset = offset + 3;
+            for _ in 0..heap.function(function).upvalue_count {
+                let kind = if chunk.code[offset] == 1 { "local" } else { "upvalue" };
+                let index = chunk.code[offset + 1];
+                let _ = writeln!(output, "{offset:04}    |                     {kind} {index}");
+                offset += 2;
+            }
+            offset
+        }
+        Operand::Byte => {
+            let _ = writeln!(output, "{name:<16} {:4}", chunk.code[offset + 1]);
+            offset + 2
+        }
+        Operand::Jump => {
+            let jump = short(chunk, offset + 1);
+            let next = offset + 3;
+            let target = if opcode == OpCode::Loop { next - jump } else { next + jump };
+            let _ = writeln!(output, "{name:<16} {offset:4} -> {target}");
+            next
+        }
+        Operand::None => {
+            let _ = writeln!(output, "{name}");
+            offset + 1
+        }
+    }
+}
+
+/// Reads the big-endian `u16` operand at `offset`.
+fn short(chunk: &Chunk, offset: usize) -> usize {
+    usize::from(u16::from_be_bytes([chunk.code[offset], chunk.code[offset + 1]]))
+}
+
+fn name(opcode: OpCode) -> &'static str {
+    match opcode {
+        OpCode::Constant => "OP_CONSTANT",
+        OpCode::Nil => "OP_NIL",
+        OpCode::True => "OP_TRUE",
+        OpCode::False => "OP_FALSE",
+        OpCode::Pop => "OP_POP",
+        OpCode::PopN => "OP_POP_N",
+        OpCode::GetLocal => "OP_GET_LOCAL",
+        OpCode::SetLocal => "OP_SET_LOCAL",
+        OpCode::GetGlobal => "OP_GET_GLOBAL",
+        OpCode::DefineGlobal => "OP_DEFINE_GLOBAL",
+        OpCode::SetGlobal => "OP_SET_GLOBAL",
+        OpCode::GetUpvalue => "OP_GET_UPVALUE",
+        OpCode::SetUpvalue => "OP_SET_UPVALUE",
+        OpCode::GetProperty => "OP_GET_PROPERTY",
+        OpCode::SetProperty => "OP_SET_PROPERTY",
+        OpCode::GetSuper => "OP_GET_SUPER",
+        OpCode::BuildList => "OP_BUILD_LIST",
+        OpCode::BuildMap => "OP_BUILD_MAP",
+        OpCode::GetIndex => "OP_GET_INDEX",
+        OpCode::SetIndex => "OP_SET_INDEX",
+        OpCode::Equal => "OP_EQUAL",
+        OpCode::Greater => "OP_GREATER",
+        OpCode::GreaterEqual => "OP_GREATER_EQUAL",
+        OpCode::Less => "OP_LESS",
+        OpCode::LessEqual => "OP_LESS_EQUAL",
+        OpCode::NotEqual => "OP_NOT_EQUAL",
+        OpCode::NotGreater => "OP_NOT_GREATER",
+        OpCode::NotLess => "OP_NOT_LESS",
+        OpCode::Add => "OP_ADD",
+        OpCode::Subtract => "OP_SUBTRACT",
+        OpCode::Multiply => "OP_MULTIPLY",
+        OpCode::Divide => "OP_DIVIDE",
+        OpCode::Not => "OP_NOT",
+        OpCode::Negate => "OP_NEGATE",
+        OpCode::Print => "OP_PRINT",
+        OpCode::Jump => "OP_JUMP",
+        OpCode::JumpIfFalse => "OP_JUMP_IF_FALSE",
+        OpCode::JumpIfNil => "OP_JUMP_IF_NIL",
+        OpCode::Loop => "OP_LOOP",
+        OpCode::Call => "OP_CALL",
+        OpCode::Invoke => "OP_INVOKE",
+        OpCode::Closure => "OP_CLOSURE",
+        OpCode::CloseUpvalue => "OP_CLOSE_UPVALUE",
+        OpCode::Return => "OP_RETURN",
+        OpCode::Class => "OP_CLASS",
+        OpCode::Inherit => "OP_INHERIT",
+        OpCode::Method => "OP_METHOD",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::token::Span;
+    use crate::interpreter::vm::object::Function;
+
+    #[test]
+    fn lists_offsets_lines_and_operands() {
+        let mut heap = Heap::default();
+        let mut function = Function::default();
+        let chunk = &mut function.chunk;
+        let span = Span::default();
+        let constant = chunk.add_constant(Value::number(1.5)) as u8;
+        let name = chunk.add_constant(Value::object(heap.intern("a"))) as u8;
+        for (byte, line) in [
+            (OpCode::Constant as u8, 1),
+            (0, 1),
+            (constant, 1),
+            (OpCode::DefineGlobal as u8, 1),
+            (0, 1),
+            (name, 1),
+            (OpCode::GetLocal as u8, 2),
+            (1, 2),
+            (OpCode::JumpIfFalse as u8, 2),
+            (0, 2),
+            (1, 2),
+            (OpCode::Pop as u8, 2),
+            (OpCode::Loop as u8, 3),
+            (0, 3),
+            (7, 3),
+            (OpCode::Return as u8, 3),
+        ] {
+            chunk.write(byte, line, span);
+        }
+        let function = heap.alloc(Object::Function(function));
+
+        assert_eq!(
+            disassemble(&heap, function),
+            "\
+== <script> ==
+0000    1 OP_CONSTANT         0 '1.5'
+0003    | OP_DEFINE_GLOBAL    1 'a'
+0006    2 OP_GET_LOCAL        1
+0008    | OP_JUMP_IF_FALSE    8 -> 12
+0011    | OP_POP
+0012    3 OP_LOOP            12 -> 8
+0015    | OP_RETURN
+",
+        );
+    }
+
+    #[test]
+    fn lists_nested_functions_after_their_declaration() {
+        let mut heap = Heap::default();
+        let mut inner = Function { name: Some(heap.intern("inner")), ..Function::default() };
+        inner.chunk.write(OpCode::Nil as u8, 2, Span::default());
+        let inner = heap.alloc(Object::Function(inner));
+        let mut script = Function::default();
+        let constant = script.chunk.add_constant(Value::object(inner)) as u8;
+        script.chunk.write(OpCode::Constant as u8, 1, Span::default());
+        script.chunk.write(0, 1, Span::default());
+        script.chunk.write(constant, 1, Span::default());
+        let script = heap.alloc(Object::Function(script));
+
+        assert_eq!(
+            disassemble(&heap, script),
+            "\
+== <script> ==
+0000    1 OP_CONSTANT         0 '<fn inner>'
+== <fn inner> ==
+0000    2 OP_NIL
+",
+        );
+    }
+}
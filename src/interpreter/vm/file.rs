@@ -0,0 +1,640 @@
+//! Compiled scripts, as saved in `.loxc` files to run them without compiling
+//! them again.
+//!
+//! Files start with `MAGIC`, the `VERSION` of the format and a CRC-32 of the
+//! rest, which is the source of the script, to report errors in, and then its
+//! functions. Numbers are little-endian, and lengths and indices are `u32`s.
+
+use std::collections::HashMap;
+
+use super::chunk::{Chunk, OpCode, Operand, Run};
+use super::heap::{Heap, ObjRef};
+use super::object::{Cache, Function, Object};
+use super::value::Value;
+use crate::interpreter::token::Span;
+
+const MAGIC: [u8; 4] = *b"LOXC";
+
+/// Changes whenever files of previous versions would run differently.
+pub(crate) const VERSION: u16 = 8;
+
+/// Why bytes couldn't be read as a compiled script.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadError {
+    /// Doesn't start with the magic bytes.
+    NotCompiled,
+    /// Compiled for another version of the format, which is given.
+    UnsupportedVersion(u16),
+    /// Truncated, or modified after being written.
+    Corrupted,
+    /// Checksummed, but with code that would read past the code, the
+    /// constants or the upvalues of its function.
+    InvalidCode,
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::NotCompiled => write!(f, "not a compiled Lox script"),
+            LoadError::UnsupportedVersion(version) => {
+                write!(f, "compiled for bytecode version {version}, but version {VERSION} is supported")
+            }
+            LoadError::Corrupted => write!(f, "the file is corrupted"),
+            LoadError::InvalidCode => write!(f, "the bytecode is invalid"),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/// A compiled script, independent of any heap.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Program {
+    pub(crate) source: String,
+    /// Each after the functions declared in it, so the script is last.
+    functions: Vec<Prototype>,
+}
+
+#[derive(Debug, PartialEq)]
+struct Prototype {
+    name: Option<String>,
+    arity: usize,
+    upvalue_count: usize,
+    /// How many caches the code indexes, which start empty.
+    cache_count: usize,
+    code: Vec<u8>,
+    constants: Vec<Constant>,
+    locations: Vec<Run>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Constant {
+    Number(f64),
+    String(String),
+    /// Index of a function of the program, declared before this one.
+    Function(usize),
+}
+
+impl Program {
+    /// Copies `script`, compiled from `source`, out of `heap`.
+    pub(crate) fn new(heap: &Heap, script: ObjRef, source: &str) -> Program {
+        let mut functions = vec![];
+        declared_functions(heap, script, &mut functions);
+        let indices: HashMap<ObjRef, usize> =
+            functions.iter().enumerate().map(|(index, function)| (*function, index)).collect();
+
+        let functions = functions
+            .into_iter()
+            .map(|function| {
+                let function = heap.function(function);
+                let constants = function.chunk.constants.iter().map(|constant| {
+                    if let Some(n) = constant.as_number() {
+                        return Constant::Number(n);
+                    }
+                    match constant.as_object().map(|object| (object, heap.get(object))) {
+                        Some((_, Object::String(s))) => Constant::String(s.to_string()),
+                        Some((function, Object::Function(_))) => Constant::Function(indices[&function]),
+                        _ => unreachable!("Constants are numbers, strings and functions"),
+                    }
+                });
+                Prototype {
+                    name: function.name.map(|name| heap.string(name).to_string()),
+                    arity: function.arity,
+                    upvalue_count: function.upvalue_count,
+                    cache_count: function.caches.len(),
+                    code: function.chunk.code.clone(),
+                    constants: constants.collect(),
+                    locations: function.chunk.locations.clone(),
+                }
+            })
+            .collect();
+
+        Program { source: source.to_string(), functions }
+    }
+
+    /// Allocates the functions of the program in `heap`, and returns the
+    /// script.
+    ///
+    /// Doesn't collect garbage, so the functions are alive until the script
+    /// is run.
+    pub(crate) fn load(&self, heap: &mut Heap) -> ObjRef {
+        let mut functions: Vec<ObjRef> = vec![];
+        for prototype in &self.functions {
+            let constants = prototype.constants.iter().map(|constant| match constant {
+                Constant::Number(n) => Value::number(*n),
+                Constant::String(s) => Value::object(heap.intern(s)),
+                Constant::Function(index) => Value::object(functions[*index]),
+            });
+            let mut chunk = Chunk::with_constants(constants.collect());
+            chunk.code.clone_from(&prototype.code);
+            chunk.locations.clone_from(&prototype.locations);
+            let name = prototype.name.as_deref().map(|name| heap.intern(name));
+            let function = Function {
+                name,
+                arity: prototype.arity,
+                upvalue_count: prototype.upvalue_count,
+                chunk,
+                caches: vec![Cache::Empty; prototype.cache_count],
+            };
+            functions.push(heap.alloc(Object::Function(function)));
+        }
+        *functions.last().expect("Programs have a script")
+    }
+
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        writer.string(&self.source);
+        writer.length(self.functions.len());
+        for function in &self.functions {
+            writer.prototype(function);
+        }
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+        bytes.extend(checksum(&writer.0).to_le_bytes());
+        bytes.extend(writer.0);
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Program, LoadError> {
+        let Some(rest) = bytes.strip_prefix(&MAGIC) else {
+            return Err(LoadError::NotCompiled);
+        };
+        let mut reader = Reader(rest);
+        let version = u16::from_le_bytes(reader.array()?);
+        if version != VERSION {
+            return Err(LoadError::UnsupportedVersion(version));
+        }
+        if u32::from_le_bytes(reader.array()?) != checksum(reader.0) {
+            return Err(LoadError::Corrupted);
+        }
+
+        let source = reader.string()?;
+        let mut functions = vec![];
+        for _ in 0..reader.length()? {
+            let function = reader.prototype(functions.len())?;
+            validate(&function, &functions)?;
+            functions.push(function);
+        }
+        if functions.is_empty() || !reader.0.is_empty() {
+            return Err(LoadError::Corrupted);
+        }
+        // The script is run without a closure to capture variables
+        if functions.last().is_some_and(|script| script.upvalue_count > 0) {
+            return Err(LoadError::InvalidCode);
+        }
+        Ok(Program { source, functions })
+    }
+}
+
+/// Adds the functions declared in `function`, recursively, then `function`.
+fn declared_functions(heap: &Heap, function: ObjRef, functions: &mut Vec<ObjRef>) {
+    for constant in &heap.function(function).chunk.constants {
+        if let Some(object) = constant.as_object() {
+            if let Object::Function(_) = heap.get(object) {
+                declared_functions(heap, object, functions);
+            }
+        }
+    }
+    functions.push(function);
+}
+
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn length(&mut self, length: usize) {
+        let length = u32::try_from(length).expect("Compiled scripts are smaller than 4 GiB");
+        self.0.extend(length.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.length(bytes.len());
+        self.0.extend(bytes);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.bytes(s.as_bytes());
+    }
+
+    fn prototype(&mut self, function: &Prototype) {
+        match &function.name {
+            Some(name) => {
+                self.0.push(1);
+                self.string(name);
+            }
+            None => self.0.push(0),
+        }
+        self.length(function.arity);
+        self.length(function.upvalue_count);
+        self.length(function.cache_count);
+        self.bytes(&function.code);
+
+        self.length(function.constants.len());
+        for constant in &function.constants {
+            match constant {
+                Constant::Number(n) => {
+                    self.0.push(0);
+                    self.0.extend(n.to_le_bytes());
+                }
+                Constant::String(s) => {
+                    self.0.push(1);
+                    self.string(s);
+                }
+                Constant::Function(index) => {
+                    self.0.push(2);
+                    self.length(*index);
+                }
+            }
+        }
+
+        self.length(function.locations.len());
+        for run in &function.locations {
+            for n in [run.line, run.span.start, run.span.end, run.length] {
+                self.length(n);
+            }
+        }
+    }
+}
+
+/// Reads from the start of the slice, failing if it is too short.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], LoadError> {
+        let Some((array, rest)) = self.0.split_first_chunk() else {
+            return Err(LoadError::Corrupted);
+        };
+        self.0 = rest;
+        Ok(*array)
+    }
+
+    fn byte(&mut self) -> Result<u8, LoadError> {
+        Ok(u8::from_le_bytes(self.array()?))
+    }
+
+    fn length(&mut self) -> Result<usize, LoadError> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, LoadError> {
+        let length = self.length()?;
+        if length > self.0.len() {
+            return Err(LoadError::Corrupted);
+        }
+        let (bytes, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(bytes.to_vec())
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        String::from_utf8(self.bytes()?).map_err(|_| LoadError::Corrupted)
+    }
+
+    /// `index` is that of the function in the program.
+    fn prototype(&mut self, index: usize) -> Result<Prototype, LoadError> {
+        let name = match self.byte()? {
+            0 => None,
+            1 => Some(self.string()?),
+            _ => return Err(LoadError::Corrupted),
+        };
+        let arity = self.length()?;
+        let upvalue_count = self.length()?;
+        let cache_count = self.length()?;
+        let code = self.bytes()?;
+        // Each cache is indexed by an instruction
+        if cache_count > code.len() {
+            return Err(LoadError::Corrupted);
+        }
+
+        let mut constants = vec![];
+        for _ in 0..self.length()? {
+            let constant = match self.byte()? {
+                0 => Constant::Number(f64::from_le_bytes(self.array()?)),
+                1 => Constant::String(self.string()?),
+                // Functions can only be constants of those declaring them
+                2 => match self.length()? {
+                    declared if declared < index => Constant::Function(declared),
+                    _ => return Err(LoadError::Corrupted),
+                },
+                _ => return Err(LoadError::Corrupted),
+            };
+            constants.push(constant);
+        }
+
+        let mut locations = vec![];
+        for _ in 0..self.length()? {
+            let (line, start, end, length) = (self.length()?, self.length()?, self.length()?, self.length()?);
+            locations.push(Run { line, span: Span { start, end }, length });
+        }
+        if locations.iter().map(|run| run.length).sum::<usize>() != code.len() {
+            return Err(LoadError::Corrupted);
+        }
+
+        Ok(Prototype { name, arity, upvalue_count, cache_count, code, constants, locations })
+    }
+}
+
+/// Checks that every instruction of `function` is followed by its operands,
+/// and that they index constants of the right kind, upvalues, caches and
+/// instructions that exist, so that the VM doesn't read past them.
+///
+/// Also checks that every instruction finds the values it uses on the stack,
+/// and as many whichever way the code gets to it, which the slots of locals
+/// rely on.
+///
+/// `declared` are the functions before `function` in the program.
+fn validate(function: &Prototype, declared: &[Prototype]) -> Result<(), LoadError> {
+    let code = &function.code;
+    let short = |offset: usize| match code.get(offset..offset + 2) {
+        Some(&[high, low]) => Ok(usize::from(u16::from_be_bytes([high, low]))),
+        _ => Err(LoadError::InvalidCode),
+    };
+    let byte = |offset: usize| code.get(offset).copied().ok_or(LoadError::InvalidCode);
+    // Names of globals, properties, classes and methods
+    let name = |offset: usize| match function.constants.get(short(offset)?) {
+        Some(Constant::String(_)) => Ok(()),
+        _ => Err(LoadError::InvalidCode),
+    };
+    let cache = |offset: usize| match short(offset)? < function.cache_count {
+        true => Ok(()),
+        false => Err(LoadError::InvalidCode),
+    };
+
+    // Offsets of the next instruction and of the jump target, by offset of
+    // instructions
+    let mut next = vec![None; code.len()];
+    let mut targets = vec![None; code.len()];
+    let mut last = None;
+    let mut offset = 0;
+    while offset < code.len() {
+        let opcode = OpCode::try_from(code[offset]).map_err(|_| LoadError::InvalidCode)?;
+        last = Some(opcode);
+        let operands = offset + 1;
+        let after = match opcode.operand() {
+            Operand::None => operands,
+            Operand::Byte => {
+                let index = usize::from(byte(operands)?);
+                if matches!(opcode, OpCode::GetUpvalue | OpCode::SetUpvalue) && index >= function.upvalue_count {
+                    return Err(LoadError::InvalidCode);
+                }
+                operands + 1
+            }
+            Operand::Constant => {
+                match (opcode, function.constants.get(short(operands)?)) {
+                    (OpCode::Constant, Some(Constant::Number(_) | Constant::String(_))) => (),
+                    (OpCode::Constant, _) => return Err(LoadError::InvalidCode),
+                    _ => name(operands)?,
+                }
+                operands + 2
+            }
+            Operand::Property => {
+                name(operands)?;
+                cache(operands + 2)?;
+                operands + 4
+            }
+            Operand::Invoke => {
+                name(operands)?;
+                cache(operands + 2)?;
+                byte(operands + 4)?;
+                operands + 5
+            }
+            Operand::Jump => {
+                let (jump, after) = (short(operands)?, operands + 2);
+                let target = if opcode == OpCode::Loop { after.checked_sub(jump) } else { Some(after + jump) };
+                targets[offset] = Some(target.ok_or(LoadError::InvalidCode)?);
+                after
+            }
+            Operand::Closure => {
+                let Some(&Constant::Function(index)) = function.constants.get(short(operands)?) else {
+                    return Err(LoadError::InvalidCode);
+                };
+                let mut after = operands + 2;
+                for _ in 0..declared[index].upvalue_count {
+                    match (byte(after)?, usize::from(byte(after + 1)?)) {
+                        // Slots of locals are checked with the depth of the stack
+                        (1, _) => (),
+                        (0, index) if index < function.upvalue_count => (),
+                        _ => return Err(LoadError::InvalidCode),
+                    }
+                    after += 2;
+                }
+                after
+            }
+        };
+        next[offset] = Some(after);
+        offset = after;
+    }
+
+    // Code can only end with instructions that don't run what follows
+    let ends = matches!(last, Some(OpCode::Return | OpCode::Jump | OpCode::Loop));
+    if !ends || targets.iter().flatten().any(|&target| next.get(target).is_none_or(Option::is_none)) {
+        return Err(LoadError::InvalidCode);
+    }
+
+    // The callee, then the arguments, are below the values of the code
+    let mut depths = vec![None; code.len()];
+    let mut pending = vec![(0, function.arity + 1)];
+    while let Some((offset, depth)) = pending.pop() {
+        match depths[offset] {
+            Some(known) if known == depth => continue,
+            Some(_) => return Err(LoadError::InvalidCode),
+            None => depths[offset] = Some(depth),
+        }
+        let opcode = OpCode::try_from(code[offset]).expect("Checked the opcodes");
+        let locals: Vec<u8> = match opcode {
+            OpCode::GetLocal | OpCode::SetLocal => vec![code[offset + 1]],
+            OpCode::Closure => code[offset + 3..next[offset].expect("Checked the instructions")]
+                .chunks(2)
+                .filter(|pair| pair[0] == 1)
+                .map(|pair| pair[1])
+                .collect(),
+            _ => vec![],
+        };
+        if locals.into_iter().any(|slot| usize::from(slot) >= depth) {
+            return Err(LoadError::InvalidCode);
+        }
+
+        let (pops, pushes) = stack_effect(opcode, code, offset);
+        let depth = depth.checked_sub(pops).ok_or(LoadError::InvalidCode)? + pushes;
+        pending.extend(targets[offset].map(|target| (target, depth)));
+        if !matches!(opcode, OpCode::Return | OpCode::Jump | OpCode::Loop) {
+            pending.push((next[offset].expect("Checked the instructions"), depth));
+        }
+    }
+    Ok(())
+}
+
+/// How many values the instruction at `offset` in `code` pops, then pushes,
+/// counting those it reads in place as popped and pushed back.
+fn stack_effect(opcode: OpCode, code: &[u8], offset: usize) -> (usize, usize) {
+    let count = || usize::from(code[offset + 1]);
+    match opcode {
+        OpCode::Jump | OpCode::Loop => (0, 0),
+        OpCode::Constant
+        | OpCode::Nil
+        | OpCode::True
+        | OpCode::False
+        | OpCode::GetLocal
+        | OpCode::GetGlobal
+        | OpCode::GetUpvalue
+        | OpCode::Closure
+        | OpCode::Class => (0, 1),
+        OpCode::Pop | OpCode::DefineGlobal | OpCode::Print | OpCode::CloseUpvalue | OpCode::Return => (1, 0),
+        OpCode::SetLocal
+        | OpCode::SetGlobal
+        | OpCode::SetUpvalue
+        | OpCode::GetProperty
+        | OpCode::Not
+        | OpCode::Negate
+        | OpCode::JumpIfFalse
+        | OpCode::JumpIfNil => (1, 1),
+        OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::GetIndex
+        | OpCode::Equal
+        | OpCode::Greater
+        | OpCode::GreaterEqual
+        | OpCode::Less
+        | OpCode::LessEqual
+        | OpCode::NotEqual
+        | OpCode::NotGreater
+        | OpCode::NotLess
+        | OpCode::Add
+        | OpCode::Subtract
+        | OpCode::Multiply
+        | OpCode::Divide
+        | OpCode::Inherit
+        | OpCode::Method => (2, 1),
+        OpCode::SetIndex => (3, 1),
+        OpCode::PopN => (count(), 0),
+        OpCode::BuildList => (count(), 1),
+        OpCode::BuildMap => (2 * count(), 1),
+        OpCode::Call => (count() + 1, 1),
+        OpCode::Invoke => (usize::from(code[offset + 5]) + 1, 1),
+    }
+}
+
+/// CRC-32, as used by zip and PNG.
+fn checksum(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::vm::{compile, disassemble};
+    use crate::interpreter::OptLevel;
+
+    const SOURCE: &str = "
+        fun counter() {
+            var count = 0;
+            fun increment() { count = count + 1; return count; }
+            return increment;
+        }
+        class A { method() { print \"method\"; } }
+        A().method();
+        print counter()() + 1.5;
+    ";
+
+    fn bytes() -> Vec<u8> {
+        let mut heap = Heap::default();
+        let script = compile(SOURCE, 0, &mut heap, vec![], OptLevel::O1).unwrap();
+        Program::new(&heap, script, SOURCE).to_bytes()
+    }
+
+    #[test]
+    fn loads_what_was_saved() {
+        let mut heap = Heap::default();
+        let script = compile(SOURCE, 0, &mut heap, vec![], OptLevel::O1).unwrap();
+        let program = Program::new(&heap, script, SOURCE);
+
+        let loaded = Program::from_bytes(&program.to_bytes()).unwrap();
+        let mut other = Heap::default();
+        let copy = loaded.load(&mut other);
+
+        assert_eq!(loaded, program);
+        assert_eq!(loaded.source, SOURCE);
+        assert_eq!(disassemble(&other, copy), disassemble(&heap, script));
+        assert_eq!(heap.function(script).chunk.locations, other.function(copy).chunk.locations);
+        assert_eq!(other.function(copy).caches, [Cache::Empty]);
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert_eq!(Program::from_bytes(SOURCE.as_bytes()), Err(LoadError::NotCompiled));
+        assert_eq!(Program::from_bytes(b""), Err(LoadError::NotCompiled));
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut bytes = bytes();
+        bytes[MAGIC.len()..MAGIC.len() + 2].copy_from_slice(&(VERSION + 1).to_le_bytes());
+
+        assert_eq!(Program::from_bytes(&bytes), Err(LoadError::UnsupportedVersion(VERSION + 1)));
+    }
+
+    #[test]
+    fn rejects_corrupted_files() {
+        let bytes = bytes();
+        let mut flipped = bytes.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+
+        assert_eq!(Program::from_bytes(&flipped), Err(LoadError::Corrupted));
+        assert_eq!(Program::from_bytes(&bytes[..bytes.len() - 1]), Err(LoadError::Corrupted));
+        assert_eq!(Program::from_bytes(&bytes[..5]), Err(LoadError::Corrupted));
+    }
+
+    #[test]
+    fn rejects_code_that_the_vm_cannot_run() {
+        let mut heap = Heap::default();
+        let script = compile(SOURCE, 0, &mut heap, vec![], OptLevel::O1).unwrap();
+        let program = Program::new(&heap, script, SOURCE);
+        let constants = &program.functions.last().unwrap().constants;
+        let number = constants.iter().position(|constant| matches!(constant, Constant::Number(_))).unwrap() as u8;
+        let load = |code: &[u8]| {
+            let mut program = Program::new(&heap, script, SOURCE);
+            let script = program.functions.last_mut().unwrap();
+            script.code = code.to_vec();
+            script.locations = vec![Run { line: 1, span: Span::default(), length: code.len() }];
+            Program::from_bytes(&program.to_bytes()).map(|_| ())
+        };
+        let (constant, global, jump) = (OpCode::Constant as u8, OpCode::GetGlobal as u8, OpCode::Jump as u8);
+        let (upvalue, nil, ret) = (OpCode::GetUpvalue as u8, OpCode::Nil as u8, OpCode::Return as u8);
+        let (local, pop, jump_if_false) = (OpCode::GetLocal as u8, OpCode::Pop as u8, OpCode::JumpIfFalse as u8);
+
+        assert_eq!(load(&[nil, ret]), Ok(()));
+        assert_eq!(load(&[constant, 0, number, ret]), Ok(()));
+        // The script's closure is in the first slot
+        assert_eq!(load(&[local, 0, ret]), Ok(()));
+        assert_eq!(load(&[nil, jump_if_false, 0, 2, pop, nil, ret]), Ok(()));
+        for code in [
+            &[255, ret][..],
+            &[constant, 0],
+            &[constant, 0xff, 0xff, ret],
+            &[global, 0, number, ret],
+            &[jump, 0, 1, ret],
+            &[upvalue, 0, ret],
+            &[nil],
+            &[local, 200, nil, ret],
+            &[pop, pop, nil, ret],
+            // The jump skips a value that the other path leaves
+            &[nil, jump_if_false, 0, 1, nil, ret],
+        ] {
+            assert_eq!(load(code), Err(LoadError::InvalidCode), "Loaded {code:?}");
+        }
+    }
+
+    #[test]
+    fn computes_standard_checksums() {
+        assert_eq!(checksum(b"123456789"), 0xcbf4_3926);
+        assert_eq!(checksum(b""), 0);
+    }
+}
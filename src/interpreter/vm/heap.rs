@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use super::object::{Cache, Closure, Function, Object};
+use super::shape::Shapes;
+use crate::interpreter::symbol::Symbol;
+
+/// Collections don't happen before the heap grows this big, in bytes.
+const MIN_NEXT_GC: usize = 1024 * 1024;
+
+/// How much the heap may grow, relative to what survived the last collection,
+/// before the next one.
+const GROWTH_FACTOR: usize = 2;
+
+/// Handle to an object in a `Heap`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct ObjRef(u32);
+
+#[cfg(feature = "nan-boxing")]
+impl ObjRef {
+    /// Position of the object in its heap, to pack it in fewer bits.
+    pub(crate) fn index(self) -> u32 {
+        self.0
+    }
+
+    pub(crate) fn from_index(index: u32) -> ObjRef {
+        ObjRef(index)
+    }
+}
+
+/// Owns the objects of the VM, which refer to each other by `ObjRef`.
+///
+/// Objects are freed by `collect`, which its owners call when `should_collect`
+/// says so, before allocating.
+pub(crate) struct Heap {
+    /// `None` where objects were freed, until the slot is reused.
+    objects: Vec<Option<Object>>,
+    /// Indices of the `None`s in `objects`.
+    free: Vec<u32>,
+    /// Every string in `objects`, so equal strings are allocated only once.
+    strings: HashMap<Symbol, ObjRef>,
+    /// Approximate size of the live objects, in bytes, without `shapes`.
+    bytes_allocated: usize,
+    next_gc: usize,
+    /// Whether to collect before every allocation.
+    stress: bool,
+    /// Shapes of the instances in `objects`.
+    pub(crate) shapes: Shapes,
+}
+
+impl Default for Heap {
+    fn default() -> Heap {
+        Heap {
+            objects: vec![],
+            free: vec![],
+            strings: HashMap::new(),
+            bytes_allocated: 0,
+            next_gc: MIN_NEXT_GC,
+            stress: false,
+            shapes: Shapes::default(),
+        }
+    }
+}
+
+impl Heap {
+    pub(crate) fn alloc(&mut self, object: Object) -> ObjRef {
+        self.bytes_allocated += object.size();
+        match self.free.pop() {
+            Some(index) => {
+                self.objects[index as usize] = Some(object);
+                ObjRef(index)
+            }
+            None => {
+                let reference = ObjRef(u32::try_from(self.objects.len()).expect("Heap is full"));
+                self.objects.push(Some(object));
+                reference
+            }
+        }
+    }
+
+    /// Returns the string object equal to `s`, allocating it if needed.
+    pub(crate) fn intern(&mut self, s: &str) -> ObjRef {
+        let s = Symbol::intern(s);
+        if let Some(&reference) = self.strings.get(&s) {
+            return reference;
+        }
+        let reference = self.alloc(Object::String(s.clone()));
+        self.strings.insert(s, reference);
+        reference
+    }
+
+    pub(crate) fn get(&self, reference: ObjRef) -> &Object {
+        self.objects[reference.0 as usize].as_ref().expect("Object was freed while in use")
+    }
+
+    pub(crate) fn get_mut(&mut self, reference: ObjRef) -> &mut Object {
+        self.objects[reference.0 as usize].as_mut().expect("Object was freed while in use")
+    }
+
+    /// Panics if `reference` is not a string, as the compiler only refers to
+    /// strings where they are expected.
+    pub(crate) fn string(&self, reference: ObjRef) -> &str {
+        match self.get(reference) {
+            Object::String(s) => s,
+            object => panic!("Expected a string, found {object:?}"),
+        }
+    }
+
+    /// Panics if `reference` is not a function.
+    pub(crate) fn function(&self, reference: ObjRef) -> &Function {
+        match self.get(reference) {
+            Object::Function(function) => function,
+            object => panic!("Expected a function, found {object:?}"),
+        }
+    }
+
+    /// Panics if `reference` is not a function.
+    pub(crate) fn function_mut(&mut self, reference: ObjRef) -> &mut Function {
+        match self.get_mut(reference) {
+            Object::Function(function) => function,
+            object => panic!("Expected a function, found {object:?}"),
+        }
+    }
+
+    /// Panics if `reference` is not a closure.
+    pub(crate) fn closure(&self, reference: ObjRef) -> &Closure {
+        match self.get(reference) {
+            Object::Closure(closure) => closure,
+            object => panic!("Expected a closure, found {object:?}"),
+        }
+    }
+
+    /// Makes `should_collect` always true, so that objects not kept alive
+    /// by the roots are freed as soon as possible.
+    pub(crate) fn set_stress(&mut self, stress: bool) {
+        self.stress = stress;
+    }
+
+    /// Whether the heap grew enough since the last collection.
+    pub(crate) fn should_collect(&self) -> bool {
+        self.stress || self.bytes_allocated + self.shapes.size() > self.next_gc
+    }
+
+    #[cfg(test)]
+    pub(crate) fn live_objects(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    /// Frees every object not reachable from `roots`, marking and sweeping.
+    pub(crate) fn collect(&mut self, roots: Vec<ObjRef>) {
+        let mut marks = vec![false; self.objects.len()];
+        let mut shape_marks = vec![false; self.shapes.len()];
+        let mut shapes = vec![];
+        let mut gray = roots;
+        while let Some(reference) = gray.pop() {
+            let index = reference.0 as usize;
+            if !marks[index] {
+                marks[index] = true;
+                let object = self.get(reference);
+                object.trace(&mut gray);
+                // Shapes keep the names of their fields alive
+                object.trace_shapes(&mut shapes);
+                for shape in shapes.drain(..) {
+                    self.shapes.mark(shape, &mut shape_marks, &mut gray);
+                }
+            }
+        }
+        // Caches forget what was freed, instead of keeping it alive
+        for (index, slot) in self.objects.iter_mut().enumerate() {
+            let Some(Object::Function(function)) = slot.as_mut().filter(|_| marks[index]) else {
+                continue;
+            };
+            for cache in &mut function.caches {
+                let object = |reference: ObjRef| marks[reference.0 as usize];
+                if !cache.is_live(object, |shape| Shapes::is_marked(shape, &shape_marks)) {
+                    *cache = Cache::Empty;
+                }
+            }
+        }
+        self.shapes.sweep(shape_marks);
+
+        // Interning doesn't keep strings alive
+        self.strings.retain(|_, reference| marks[reference.0 as usize]);
+        self.bytes_allocated = 0;
+        for (index, slot) in self.objects.iter_mut().enumerate() {
+            match slot {
+                Some(object) if marks[index] => self.bytes_allocated += object.size(),
+                Some(_) => {
+                    *slot = None;
+                    self.free.push(index as u32);
+                }
+                None => (),
+            }
+        }
+        self.next_gc = ((self.bytes_allocated + self.shapes.size()) * GROWTH_FACTOR).max(MIN_NEXT_GC);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::vm::object::{Class, Instance};
+    use crate::interpreter::vm::value::Value;
+
+    #[test]
+    fn interns_strings() {
+        let mut heap = Heap::default();
+
+        let a = heap.intern("a");
+        let b = heap.intern("b");
+
+        assert_ne!(a, b);
+        assert_eq!(heap.intern("a"), a);
+        assert_eq!(heap.string(b), "b");
+    }
+
+    #[test]
+    fn frees_unreachable_objects() {
+        let mut heap = Heap::default();
+        let kept = heap.intern("kept");
+        let freed = heap.intern("freed");
+
+        heap.collect(vec![kept]);
+
+        assert_eq!(heap.string(kept), "kept");
+        assert!(heap.objects[freed.0 as usize].is_none());
+        // Its slot is reused, and the string can be interned again
+        assert_eq!(heap.intern("freed"), freed);
+    }
+
+    #[test]
+    fn keeps_objects_reachable_from_roots() {
+        let mut heap = Heap::default();
+        let name = heap.intern("f");
+        let mut function = Function { name: Some(name), ..Function::default() };
+        let constant = heap.intern("constant");
+        function.chunk.add_constant(Value::object(constant));
+        let function = heap.alloc(Object::Function(function));
+        let closure = heap.alloc(Object::Closure(Closure { function, upvalues: vec![] }));
+
+        heap.collect(vec![closure]);
+
+        assert_eq!(heap.string(heap.function(heap.closure(closure).function).name.unwrap()), "f");
+        assert_eq!(heap.function(function).chunk.constants, [Value::object(constant)]);
+    }
+
+    #[test]
+    fn frees_names_of_fields_once_no_instance_has_them() {
+        let mut heap = Heap::default();
+        let name = heap.intern("A");
+        let class = heap.alloc(Object::Class(Class { name, methods: HashMap::new() }));
+        let field = heap.intern("field");
+        let shape = heap.shapes.with_field(Shapes::EMPTY, field);
+        let instance = heap.alloc(Object::Instance(Instance { class, shape, fields: vec![Value::NIL] }));
+
+        heap.collect(vec![instance]);
+        assert_eq!(heap.string(field), "field");
+
+        heap.collect(vec![]);
+        assert!(heap.objects[field.0 as usize].is_none());
+        assert_eq!(heap.shapes.slot(Shapes::EMPTY, field), None);
+    }
+
+    #[test]
+    fn clears_caches_of_what_was_freed() {
+        let mut heap = Heap::default();
+        let name = heap.intern("A");
+        let method = heap.alloc(Object::Function(Function::default()));
+        let class = heap.alloc(Object::Class(Class { name, methods: HashMap::new() }));
+        let field = heap.intern("field");
+        let shape = heap.shapes.with_field(Shapes::EMPTY, field);
+        let instance = heap.alloc(Object::Instance(Instance { class, shape, fields: vec![Value::NIL] }));
+        let caches = vec![
+            Cache::Method { class, shape: Shapes::EMPTY, method },
+            Cache::Field { shape, slot: 0 },
+            Cache::Transition { from: Shapes::EMPTY, to: shape },
+        ];
+        let function = heap.alloc(Object::Function(Function { caches: caches.clone(), ..Function::default() }));
+
+        heap.collect(vec![function, instance]);
+        assert_eq!(heap.function(function).caches[1..], caches[1..]);
+        assert_eq!(heap.function(function).caches[0], Cache::Empty);
+        assert!(heap.objects[method.0 as usize].is_none());
+
+        heap.collect(vec![function]);
+        assert_eq!(heap.function(function).caches, [Cache::Empty; 3]);
+        assert!(heap.objects[class.0 as usize].is_none());
+    }
+
+    #[test]
+    fn counts_shapes_towards_collections() {
+        let mut heap = Heap::default();
+        let mut shape = Shapes::EMPTY;
+        while !heap.should_collect() {
+            let name = heap.intern(&heap.shapes.len().to_string());
+            shape = heap.shapes.with_field(shape, name);
+        }
+        let name = heap.intern("A");
+        let class = heap.alloc(Object::Class(Class { name, methods: HashMap::new() }));
+        let instance = heap.alloc(Object::Instance(Instance { class, shape, fields: vec![] }));
+
+        heap.collect(vec![instance]);
+
+        assert!(heap.next_gc >= heap.shapes.size() * GROWTH_FACTOR);
+        heap.collect(vec![]);
+        assert_eq!(heap.shapes.size(), Shapes::default().size());
+    }
+
+    #[test]
+    fn grows_the_threshold_with_the_live_objects() {
+        let mut heap = Heap::default();
+        let big = heap.intern(&"a".repeat(MIN_NEXT_GC));
+        assert!(heap.should_collect());
+
+        heap.collect(vec![big]);
+
+        assert!(!heap.should_collect());
+        assert!(heap.next_gc >= 2 * MIN_NEXT_GC);
+        heap.set_stress(true);
+        assert!(heap.should_collect());
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use super::chunk::Chunk;
+use super::heap::ObjRef;
+use super::shape::ShapeId;
+use super::value::Value;
+use crate::interpreter::list;
+use crate::interpreter::map::{self, Key};
+use crate::interpreter::native::NativeFunction;
+use crate::interpreter::symbol::Symbol;
+
+/// Values of the VM that live in its heap.
+#[derive(Debug)]
+pub(crate) enum Object {
+    String(Symbol),
+    Function(Function),
+    Closure(Closure),
+    Upvalue(Upvalue),
+    Native(Rc<NativeFunction>),
+    Class(Class),
+    Instance(Instance),
+    BoundMethod(BoundMethod),
+    List(Vec<Value>),
+    ListMethod(ListMethod),
+    /// Keys that are strings are string objects.
+    Map(map::Map<ObjRef, Value>),
+    MapMethod(MapMethod),
+}
+
+impl Object {
+    /// Adds the objects this one refers to, which it keeps alive, to
+    /// `references`.
+    pub(crate) fn trace(&self, references: &mut Vec<ObjRef>) {
+        match self {
+            Object::String(_) | Object::Native(_) | Object::Upvalue(Upvalue::Open(_)) => (),
+            Object::Function(function) => {
+                references.extend(function.name);
+                // Caches don't keep what they remember alive, the heap clears them instead
+                references.extend(objects(&function.chunk.constants));
+            }
+            Object::Closure(closure) => {
+                references.push(closure.function);
+                references.extend(&closure.upvalues);
+            }
+            Object::Upvalue(Upvalue::Closed(value)) => references.extend(objects([value])),
+            Object::Class(class) => {
+                references.push(class.name);
+                references.extend(class.methods.iter().flat_map(|(&name, &method)| [name, method]));
+            }
+            Object::Instance(instance) => {
+                references.push(instance.class);
+                references.extend(objects(&instance.fields));
+            }
+            Object::BoundMethod(bound) => {
+                references.push(bound.method);
+                references.extend(objects([&bound.receiver]));
+            }
+            Object::List(elements) => references.extend(objects(elements)),
+            Object::ListMethod(method) => references.push(method.list),
+            Object::Map(map) => {
+                for (key, value) in map.iter() {
+                    if let Key::String(key) = key {
+                        references.push(*key);
+                    }
+                    references.extend(value.as_object());
+                }
+            }
+            Object::MapMethod(method) => references.push(method.map),
+        }
+    }
+
+    /// Adds the shapes this object refers to, which it keeps alive, to
+    /// `shapes`.
+    pub(crate) fn trace_shapes(&self, shapes: &mut Vec<ShapeId>) {
+        if let Object::Instance(instance) = self {
+            shapes.push(instance.shape);
+        }
+    }
+
+    /// Approximates how many bytes the object takes, including what it owns.
+    pub(crate) fn size(&self) -> usize {
+        let owned = match self {
+            Object::String(s) => s.len(),
+            Object::Function(function) => {
+                function.chunk.code.len()
+                    + function.chunk.constants.len() * std::mem::size_of::<Value>()
+                    + function.caches.len() * std::mem::size_of::<Cache>()
+            }
+            Object::Closure(closure) => closure.upvalues.len() * std::mem::size_of::<ObjRef>(),
+            Object::Class(class) => class.methods.len() * 2 * std::mem::size_of::<ObjRef>(),
+            Object::Instance(instance) => instance.fields.len() * std::mem::size_of::<Value>(),
+            Object::List(elements) => elements.len() * std::mem::size_of::<Value>(),
+            // Each key is stored twice, to find entries and to keep their order
+            Object::Map(map) => map.len() * (2 * std::mem::size_of::<Key<ObjRef>>() + std::mem::size_of::<Value>()),
+            Object::Native(_)
+            | Object::Upvalue(_)
+            | Object::BoundMethod(_)
+            | Object::ListMethod(_)
+            | Object::MapMethod(_) => 0,
+        };
+        std::mem::size_of::<Object>() + owned
+    }
+}
+
+/// Returns the objects among `values`.
+fn objects<'a, I: IntoIterator<Item = &'a Value>>(values: I) -> impl Iterator<Item = ObjRef> + use<'a, I> {
+    values.into_iter().filter_map(|value| value.as_object())
+}
+
+/// Compiled code of a function, or of a whole script.
+#[derive(Debug, Default)]
+pub(crate) struct Function {
+    /// `None` for scripts.
+    pub(crate) name: Option<ObjRef>,
+    pub(crate) arity: usize,
+    /// How many variables closures of the function capture.
+    pub(crate) upvalue_count: usize,
+    pub(crate) chunk: Chunk,
+    /// Indexed by the cache operands of property instructions.
+    pub(crate) caches: Vec<Cache>,
+}
+
+/// What a property instruction found the last time it ran, so that it finds
+/// it again without looking it up by name when it runs on similar instances.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Cache {
+    #[default]
+    Empty,
+    /// The field is at `slot` in instances of `shape`.
+    Field { shape: ShapeId, slot: usize },
+    /// Setting the field adds it to instances of `from`, which become `to`.
+    Transition { from: ShapeId, to: ShapeId },
+    /// Instances of `class` and `shape` have no field with the name, so the
+    /// property is the method.
+    Method { class: ObjRef, shape: ShapeId, method: ObjRef },
+}
+
+impl Cache {
+    /// Whether everything the cache remembers is still alive, as told by
+    /// `object` and `shape`.
+    pub(crate) fn is_live(self, object: impl Fn(ObjRef) -> bool, shape: impl Fn(ShapeId) -> bool) -> bool {
+        match self {
+            Cache::Empty => true,
+            Cache::Field { shape: cached, .. } => shape(cached),
+            Cache::Transition { from, to } => shape(from) && shape(to),
+            Cache::Method { class, shape: cached, method } => object(class) && object(method) && shape(cached),
+        }
+    }
+}
+
+/// A function together with the variables it captured, which is what the VM
+/// calls.
+#[derive(Debug)]
+pub(crate) struct Closure {
+    pub(crate) function: ObjRef,
+    /// `Upvalue` objects, in the order the function refers to them.
+    pub(crate) upvalues: Vec<ObjRef>,
+}
+
+/// A variable captured by closures, shared by all of them.
+#[derive(Debug)]
+pub(crate) enum Upvalue {
+    /// The variable is still on the stack, at this slot.
+    Open(usize),
+    /// The variable went out of scope, so it moved here.
+    Closed(Value),
+}
+
+#[derive(Debug)]
+pub(crate) struct Class {
+    pub(crate) name: ObjRef,
+    /// Closures by name, including those inherited, which are copied from
+    /// the superclass.
+    pub(crate) methods: HashMap<ObjRef, ObjRef>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Instance {
+    pub(crate) class: ObjRef,
+    /// Where each field is in `fields`.
+    pub(crate) shape: ShapeId,
+    pub(crate) fields: Vec<Value>,
+}
+
+/// A method read from an instance, which remembers the instance as `this`.
+#[derive(Debug)]
+pub(crate) struct BoundMethod {
+    pub(crate) receiver: Value,
+    pub(crate) method: ObjRef,
+}
+
+/// A method read from a list, which it runs on.
+#[derive(Debug)]
+pub(crate) struct ListMethod {
+    pub(crate) list: ObjRef,
+    pub(crate) method: list::Method,
+}
+
+/// A method read from a map, which it runs on.
+#[derive(Debug)]
+pub(crate) struct MapMethod {
+    pub(crate) map: ObjRef,
+    pub(crate) method: map::Method,
+}
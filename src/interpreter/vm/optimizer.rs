@@ -0,0 +1,502 @@
+//! Rewrites the code of compiled functions into code that prints the same
+//! and fails with the same errors, but runs fewer instructions.
+//!
+//! Instructions are decoded into a list, where jumps point to instructions
+//! instead of offsets, so that instructions can be removed and replaced
+//! freely before encoding the list again.
+
+use super::chunk::{Chunk, OpCode, Operand};
+use super::heap::Heap;
+use super::value::Value;
+use crate::interpreter::token::Span;
+
+#[derive(Clone, Debug)]
+struct Instruction {
+    opcode: OpCode,
+    /// Operand bytes, except for jumps.
+    operands: Vec<u8>,
+    /// Index of the instruction jumped to, for jumps.
+    target: Option<usize>,
+    /// Whether jumps land on the instruction, so that it starts a sequence
+    /// that can't be merged with the instructions before it.
+    is_target: bool,
+    line: usize,
+    span: Span,
+    /// Where the operand bytes came from, which is where the opcode came
+    /// from unless given, like for the argument count of `Invoke`.
+    operand_locations: Vec<(usize, Span)>,
+}
+
+impl Instruction {
+    fn new(opcode: OpCode, operands: Vec<u8>, at: &Instruction) -> Instruction {
+        let (line, span) = (at.line, at.span);
+        Instruction { opcode, operands, target: None, is_target: at.is_target, line, span, operand_locations: vec![] }
+    }
+
+    fn length(&self) -> usize {
+        1 + self.operands.len() + if self.target.is_some() { 2 } else { 0 }
+    }
+}
+
+/// Optimizes the complete code of a function, whose nested functions are in
+/// `heap`:
+///
+/// - Folds arithmetic and comparisons of number constants.
+/// - Removes the branches that constant conditions never take, and any
+///   other unreachable code.
+/// - Fuses negated comparisons and runs of pops into single instructions.
+/// - Removes constants that are no longer used.
+pub(crate) fn optimize(heap: &Heap, chunk: &mut Chunk) {
+    let mut instructions = decode(heap, chunk);
+    // Every rewrite removes instructions, and may enable others
+    loop {
+        let length = instructions.len();
+        instructions = remove_dead_code(peephole(chunk, instructions));
+        if instructions.len() == length {
+            break;
+        }
+    }
+    let constants = compact_constants(chunk, &mut instructions);
+    encode(chunk, constants, &instructions);
+}
+
+fn decode(heap: &Heap, chunk: &Chunk) -> Vec<Instruction> {
+    let locations: Vec<_> =
+        chunk.locations.iter().flat_map(|run| std::iter::repeat_n((run.line, run.span), run.length)).collect();
+    let mut instructions = vec![];
+    // Index of the instruction starting at each offset, to resolve jumps
+    let mut indices = vec![None; chunk.code.len()];
+    let mut jumps = vec![];
+
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let opcode = OpCode::try_from(chunk.code[offset]).expect("Compiler emits valid opcodes");
+        let start = offset;
+        offset += 1;
+        let length = match opcode.operand() {
+            Operand::None => 0,
+            Operand::Byte => 1,
+            Operand::Constant => 2,
+            Operand::Property => 4,
+            Operand::Invoke => 5,
+            Operand::Jump => {
+                let jump = short(&chunk.code[offset..]);
+                let next = offset + 2;
+                let target = if opcode == OpCode::Loop { next - jump } else { next + jump };
+                jumps.push((instructions.len(), target));
+                0
+            }
+            Operand::Closure => {
+                let function = chunk.constants[short(&chunk.code[offset..])];
+                let function = function.as_object().expect("Closures are made of functions");
+                2 + 2 * heap.function(function).upvalue_count
+            }
+        };
+        let operands = chunk.code[offset..offset + length].to_vec();
+        offset += if opcode.operand() == Operand::Jump { 2 } else { length };
+
+        indices[start] = Some(instructions.len());
+        let (line, span) = locations[start];
+        let operand_locations = locations[start + 1..start + 1 + length].to_vec();
+        let instruction = Instruction { opcode, operands, target: None, is_target: false, line, span, operand_locations };
+        instructions.push(instruction);
+    }
+
+    for (index, target) in jumps {
+        instructions[index].target = Some(indices[target].expect("Jumps land on instructions"));
+    }
+    instructions
+}
+
+/// Replaces the code of `chunk` with `instructions`, and its constant pool
+/// with `constants`.
+fn encode(chunk: &mut Chunk, constants: Vec<Value>, instructions: &[Instruction]) {
+    let offsets: Vec<usize> = instructions
+        .iter()
+        .scan(0, |offset, instruction| {
+            let start = *offset;
+            *offset += instruction.length();
+            Some(start)
+        })
+        .collect();
+
+    let mut encoded = Chunk::with_constants(constants);
+    for (index, instruction) in instructions.iter().enumerate() {
+        let (line, span) = (instruction.line, instruction.span);
+        encoded.write(instruction.opcode as u8, line, span);
+        for (index, &byte) in instruction.operands.iter().enumerate() {
+            let (line, span) = instruction.operand_locations.get(index).copied().unwrap_or((line, span));
+            encoded.write(byte, line, span);
+        }
+        if let Some(target) = instruction.target {
+            let next = offsets[index] + 3;
+            let jump = if instruction.opcode == OpCode::Loop { next - offsets[target] } else { offsets[target] - next };
+            // Code only shrinks, so jumps still fit
+            for byte in (jump as u16).to_be_bytes() {
+                encoded.write(byte, line, span);
+            }
+        }
+    }
+    *chunk = encoded;
+}
+
+/// Rewrites sequences of instructions as they are read, so that sequences
+/// that simplify to constants can simplify further with what follows them.
+fn peephole(chunk: &mut Chunk, mut instructions: Vec<Instruction>) -> Vec<Instruction> {
+    for instruction in &mut instructions {
+        instruction.is_target = false;
+    }
+    let mut pops = vec![false; instructions.len()];
+    for index in 0..instructions.len() {
+        if let Some(target) = instructions[index].target {
+            instructions[target].is_target = true;
+            pops[target] = instructions[target].opcode == OpCode::Pop;
+            // Where constant conditions jump, over the pop of the condition
+            if instructions[index].opcode == OpCode::JumpIfFalse && pops[target] {
+                instructions[target + 1].is_target = true;
+            }
+        }
+    }
+
+    let mut rewritten: Vec<Instruction> = vec![];
+    // Where each instruction ended up, or the next one if it was removed
+    let mut indices = vec![];
+    // Whether removed instructions were jumped to
+    let mut removed_target = false;
+    for mut instruction in instructions {
+        indices.push(rewritten.len());
+        instruction.is_target |= std::mem::take(&mut removed_target);
+        rewritten.push(instruction);
+        while let Some(rewrite) = rewrite(chunk, &rewritten, &pops) {
+            let start = rewritten.len() - rewrite.length;
+            if rewrite.replacement.is_none() {
+                removed_target |= rewritten[start].is_target;
+            }
+            rewritten.truncate(start);
+            rewritten.extend(rewrite.replacement);
+        }
+    }
+
+    for instruction in &mut rewritten {
+        instruction.target = instruction.target.map(|target| indices[target]);
+    }
+    rewritten
+}
+
+/// Replaces the last `length` instructions.
+struct Rewrite {
+    length: usize,
+    replacement: Option<Instruction>,
+}
+
+/// Finds a rewrite of the instructions at the end of `instructions`.
+///
+/// `pops` tells which of the instructions being rewritten are pops that
+/// jumps land on.
+fn rewrite(chunk: &mut Chunk, instructions: &[Instruction], pops: &[bool]) -> Option<Rewrite> {
+    let replace = |length, opcode, operands, at: &Instruction| Rewrite {
+        length,
+        replacement: Some(Instruction::new(opcode, operands, at)),
+    };
+    // Instructions after the first of a sequence can't be jumped to
+    let tail = |length: usize| {
+        let start = instructions.len().checked_sub(length)?;
+        let tail = &instructions[start..];
+        tail[1..].iter().all(|instruction| !instruction.is_target).then_some(tail)
+    };
+
+    if let Some([a, b, operation]) = tail(3) {
+        if let (Some(x), Some(y)) = (number(chunk, a), number(chunk, b)) {
+            let folded = match operation.opcode {
+                OpCode::Add => Some(Value::number(x + y)),
+                OpCode::Subtract => Some(Value::number(x - y)),
+                OpCode::Multiply => Some(Value::number(x * y)),
+                OpCode::Divide => Some(Value::number(x / y)),
+                OpCode::Equal => Some(Value::bool(x == y)),
+                OpCode::Greater => Some(Value::bool(x > y)),
+                OpCode::GreaterEqual => Some(Value::bool(x >= y)),
+                OpCode::Less => Some(Value::bool(x < y)),
+                OpCode::LessEqual => Some(Value::bool(x <= y)),
+                _ => None,
+            };
+            if let Some(replacement) = folded.and_then(|value| constant(chunk, value, a)) {
+                return Some(Rewrite { length: 3, replacement: Some(replacement) });
+            }
+        }
+
+        // Conditions that are always true never jump, and pop themselves
+        if truthiness(chunk, a) == Some(true) && b.opcode == OpCode::JumpIfFalse && operation.opcode == OpCode::Pop {
+            return Some(Rewrite { length: 3, replacement: None });
+        }
+    }
+
+    let [a, b] = tail(2)? else {
+        return None;
+    };
+    match (a.opcode, b.opcode) {
+        (_, OpCode::Negate) => {
+            let x = number(chunk, a)?;
+            Some(Rewrite { length: 2, replacement: Some(constant(chunk, Value::number(-x), a)?) })
+        }
+        (_, OpCode::Not) if truthiness(chunk, a).is_some() => {
+            let opcode = if truthiness(chunk, a) == Some(true) { OpCode::False } else { OpCode::True };
+            Some(replace(2, opcode, vec![], a))
+        }
+        (OpCode::Equal, OpCode::Not) => Some(replace(2, OpCode::NotEqual, vec![], a)),
+        (OpCode::Greater, OpCode::Not) => Some(replace(2, OpCode::NotGreater, vec![], a)),
+        (OpCode::Less, OpCode::Not) => Some(replace(2, OpCode::NotLess, vec![], a)),
+        (OpCode::Pop, OpCode::Pop) => Some(replace(2, OpCode::PopN, vec![2], a)),
+        (OpCode::PopN, OpCode::Pop) if a.operands[0] < u8::MAX => {
+            Some(replace(2, OpCode::PopN, vec![a.operands[0] + 1], a))
+        }
+        // Conditions that are always false jump over the pop of the condition
+        (_, OpCode::JumpIfFalse) if truthiness(chunk, a) == Some(false) => {
+            let target = b.target?;
+            pops.get(target).copied().filter(|&pop| pop)?;
+            let mut jump = Instruction::new(OpCode::Jump, vec![], a);
+            jump.target = Some(target + 1);
+            Some(Rewrite { length: 2, replacement: Some(jump) })
+        }
+        _ => None,
+    }
+}
+
+/// Whether `instruction` pushes a constant that is always truthy or falsey.
+fn truthiness(chunk: &Chunk, instruction: &Instruction) -> Option<bool> {
+    match instruction.opcode {
+        OpCode::True => Some(true),
+        OpCode::False | OpCode::Nil => Some(false),
+        OpCode::Constant => Some(!chunk.constants[short(&instruction.operands)].is_falsey()),
+        _ => None,
+    }
+}
+
+/// The number that `instruction` pushes, if it pushes a number constant.
+fn number(chunk: &Chunk, instruction: &Instruction) -> Option<f64> {
+    match instruction.opcode {
+        OpCode::Constant => chunk.constants[short(&instruction.operands)].as_number(),
+        _ => None,
+    }
+}
+
+/// An instruction pushing `value`, unless the constant pool is full.
+fn constant(chunk: &mut Chunk, value: Value, at: &Instruction) -> Option<Instruction> {
+    if let Some(b) = value.as_bool() {
+        let opcode = if b { OpCode::True } else { OpCode::False };
+        return Some(Instruction::new(opcode, vec![], at));
+    }
+    let index = u16::try_from(chunk.add_constant(value)).ok()?;
+    Some(Instruction::new(OpCode::Constant, index.to_be_bytes().to_vec(), at))
+}
+
+/// Removes instructions that can't run, and jumps to the next instruction.
+fn remove_dead_code(mut instructions: Vec<Instruction>) -> Vec<Instruction> {
+    let mut reachable = vec![false; instructions.len()];
+    let mut pending = vec![0];
+    while let Some(index) = pending.pop() {
+        if index >= instructions.len() || reachable[index] {
+            continue;
+        }
+        reachable[index] = true;
+        let instruction = &instructions[index];
+        pending.extend(instruction.target);
+        if !matches!(instruction.opcode, OpCode::Jump | OpCode::Loop | OpCode::Return) {
+            pending.push(index + 1);
+        }
+    }
+
+    // How many instructions before each are reachable
+    let before: Vec<usize> = reachable
+        .iter()
+        .scan(0, |count, &reachable| {
+            let before = *count;
+            *count += usize::from(reachable);
+            Some(before)
+        })
+        .collect();
+    let keep: Vec<bool> = (0..instructions.len())
+        .map(|index| {
+            let instruction = &instructions[index];
+            let skips_nothing = instruction.opcode == OpCode::Jump
+                && instruction.target.is_some_and(|target| before[target] == before[index] + 1);
+            reachable[index] && !skips_nothing
+        })
+        .collect();
+
+    // Where each instruction ends up, or the next one if it is removed
+    let indices: Vec<usize> = keep
+        .iter()
+        .scan(0, |count, &keep| {
+            let index = *count;
+            *count += usize::from(keep);
+            Some(index)
+        })
+        .collect();
+    let mut index = 0;
+    instructions.retain(|_| {
+        index += 1;
+        keep[index - 1]
+    });
+    for instruction in &mut instructions {
+        instruction.target = instruction.target.map(|target| indices[target]);
+    }
+    instructions
+}
+
+/// Returns the constants that instructions still use, renumbering them in
+/// the instructions.
+fn compact_constants(chunk: &Chunk, instructions: &mut [Instruction]) -> Vec<Value> {
+    let mut indices = vec![None; chunk.constants.len()];
+    let mut constants = vec![];
+    for instruction in instructions {
+        let operand = instruction.opcode.operand();
+        if matches!(operand, Operand::Constant | Operand::Property | Operand::Invoke | Operand::Closure) {
+            let old = short(&instruction.operands);
+            let index = *indices[old].get_or_insert_with(|| {
+                constants.push(chunk.constants[old]);
+                constants.len() - 1
+            });
+            instruction.operands[..2].copy_from_slice(&(index as u16).to_be_bytes());
+        }
+    }
+    constants
+}
+
+/// Reads the big-endian `u16` that `bytes` start with.
+fn short(bytes: &[u8]) -> usize {
+    usize::from(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::test_support::{run, run_vm, run_vm_err, run_vm_err_unoptimized, run_vm_unoptimized};
+    use crate::interpreter::vm::{compile, disassemble, Heap};
+    use crate::interpreter::OptLevel;
+
+    fn listing(source: &str, level: OptLevel) -> String {
+        let mut heap = Heap::default();
+        let script = compile(source, 0, &mut heap, vec![], level).expect("Compiled invalid code");
+        disassemble(&heap, script)
+    }
+
+    #[test]
+    fn folds_constant_expressions() {
+        assert_eq!(
+            listing("print 2 * 3 + 1; print -(1 - 3) < 1;", OptLevel::O1),
+            "\
+== <script> ==
+0000    1 OP_CONSTANT         0 '7'
+0003    | OP_PRINT
+0004    | OP_FALSE
+0005    | OP_PRINT
+0006    | OP_NIL
+0007    | OP_RETURN
+",
+        );
+    }
+
+    #[test]
+    fn removes_branches_not_taken() {
+        assert_eq!(
+            listing("if (1 < 2) print \"then\"; else print \"else\"; while (nil) print 1;", OptLevel::O1),
+            "\
+== <script> ==
+0000    1 OP_CONSTANT         0 'then'
+0003    | OP_PRINT
+0004    | OP_NIL
+0005    | OP_RETURN
+",
+        );
+        assert_eq!(
+            listing("if (!true) print \"then\"; else print \"else\";", OptLevel::O1),
+            "\
+== <script> ==
+0000    1 OP_CONSTANT         0 'else'
+0003    | OP_PRINT
+0004    | OP_NIL
+0005    | OP_RETURN
+",
+        );
+    }
+
+    #[test]
+    fn fuses_negated_comparisons_and_pops() {
+        assert_eq!(
+            listing("fun f(a, b) { { var c = a; var d = b; print c != d; print !(c > d); } }", OptLevel::O1),
+            "\
+== <script> ==
+0000    1 OP_CLOSURE          0 '<fn f>'
+0003    | OP_DEFINE_GLOBAL    1 'f'
+0006    | OP_NIL
+0007    | OP_RETURN
+== <fn f> ==
+0000    1 OP_GET_LOCAL        1
+0002    | OP_GET_LOCAL        2
+0004    | OP_GET_LOCAL        3
+0006    | OP_GET_LOCAL        4
+0008    | OP_NOT_EQUAL
+0009    | OP_PRINT
+0010    | OP_GET_LOCAL        3
+0012    | OP_GET_LOCAL        4
+0014    | OP_NOT_GREATER
+0015    | OP_PRINT
+0016    | OP_POP_N            2
+0018    | OP_NIL
+0019    | OP_RETURN
+",
+        );
+    }
+
+    #[test]
+    fn keeps_jump_targets_apart() {
+        // The `3` that `and` jumps to must not be folded with the `2`
+        let source = "var a = 1; print (a and 2) + 3; print (a or 2) + 3;";
+
+        assert_eq!(listing(source, OptLevel::O1).matches("OP_ADD").count(), 2);
+        assert_eq!(run_vm(source), "5\n4\n");
+    }
+
+    #[test]
+    fn prints_the_same_as_unoptimized_code() {
+        let source = "
+            print -0;
+            print 0 * -1;
+            print 0 / 0 <= 1;
+            print !(0 / 0 > 1);
+            print 1 / 0;
+            print \"a\" != \"b\";
+            print true and 3 or 4;
+            print nil or false and 1;
+            for (var i = 0; i < 3; i = i + 1) {
+                if (i == 1) print \"one\"; else { var x = i; var y = x * 2; print y; }
+            }
+            while (false) { print \"never\"; }
+            fun f() { if (true) return \"returned\"; return \"unreachable\"; }
+            print f();
+            fun counter() { var n = 0; fun next() { n = n + 1; return n; } return next; }
+            var next = counter();
+            if (1 > 0) next();
+            print next();
+        ";
+        let output = "-0\n-0\nfalse\ntrue\ninf\ntrue\n3\nfalse\n0\none\n4\nreturned\n2\n";
+
+        assert_eq!(run_vm(source), output);
+        assert_eq!(run_vm_unoptimized(source), output);
+        assert_eq!(run(source), output);
+    }
+
+    #[test]
+    fn keeps_runtime_errors() {
+        let sources = [
+            "print 1 +\n nil;",
+            "if (true) print -\"a\";",
+            "print 2 * 3 < \"6\";",
+            "{ var a; var b; a(); }",
+            "class A { m(a) {} }\nA().m(\n);",
+        ];
+        for source in sources {
+            assert_eq!(run_vm_err(source), run_vm_err_unoptimized(source));
+        }
+        assert_eq!(run_vm_err("if (true) print -\"a\";"), "1:17: Operand must be a number.");
+    }
+}
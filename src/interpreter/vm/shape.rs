@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+use super::heap::ObjRef;
+
+/// Handle to a `Shape` in `Shapes`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct ShapeId(u32);
+
+/// Where the fields of instances are, by name.
+///
+/// Instances that were given the same fields in the same order share a
+/// shape, so a property access that found a field at some slot of one
+/// instance finds it at the same slot of any instance of the same shape.
+///
+/// Each shape only knows the field it adds to its parent, so that a chain
+/// of shapes takes memory in proportion to its length.
+#[derive(Debug, Default)]
+struct Shape {
+    /// Shape with one less field, with the name and slot in
+    /// `Instance::fields` of the field this one adds, unless this is
+    /// `Shapes::EMPTY`.
+    parent: Option<(ShapeId, ObjRef, usize)>,
+    /// Shapes with one more field, by the name of that field.
+    transitions: HashMap<ObjRef, ShapeId>,
+}
+
+/// Every shape that instances have, or that caches remember.
+///
+/// Shapes refer to the names of the fields, which must be kept alive so that
+/// their references aren't reused for other strings. The heap does so for
+/// the shapes that live objects refer to, and frees the others with `sweep`.
+#[derive(Debug)]
+pub(crate) struct Shapes {
+    /// `None` where shapes were freed, until the slot is reused.
+    shapes: Vec<Option<Shape>>,
+    /// Indices of the `None`s in `shapes`.
+    free: Vec<u32>,
+}
+
+impl Default for Shapes {
+    fn default() -> Shapes {
+        Shapes { shapes: vec![Some(Shape::default())], free: vec![] }
+    }
+}
+
+impl Shapes {
+    /// Shape of instances without fields.
+    pub(crate) const EMPTY: ShapeId = ShapeId(0);
+
+    pub(crate) fn slot(&self, mut shape: ShapeId, name: ObjRef) -> Option<usize> {
+        while let Some((parent, field, slot)) = self.get(shape).parent {
+            if field == name {
+                return Some(slot);
+            }
+            shape = parent;
+        }
+        None
+    }
+
+    /// Returns the shape of instances of `shape` that are given a field
+    /// `name`, which they don't have yet, in the next slot.
+    pub(crate) fn with_field(&mut self, shape: ShapeId, name: ObjRef) -> ShapeId {
+        if let Some(&next) = self.get(shape).transitions.get(&name) {
+            return next;
+        }
+
+        let slot = self.get(shape).parent.map_or(0, |(_, _, slot)| slot + 1);
+        let next = Shape { parent: Some((shape, name, slot)), transitions: HashMap::new() };
+        let next = match self.free.pop() {
+            Some(index) => {
+                self.shapes[index as usize] = Some(next);
+                ShapeId(index)
+            }
+            None => {
+                self.shapes.push(Some(next));
+                ShapeId(u32::try_from(self.shapes.len() - 1).expect("Too many shapes"))
+            }
+        };
+        self.get_mut(shape).transitions.insert(name, next);
+        next
+    }
+
+    /// How many shapes there are room for, which is more than any `ShapeId`.
+    pub(crate) fn len(&self) -> usize {
+        self.shapes.len()
+    }
+
+    /// Approximates how many bytes the shapes take, counting each with its
+    /// entry in the transitions of its parent.
+    pub(crate) fn size(&self) -> usize {
+        let transition = std::mem::size_of::<(ObjRef, ShapeId)>();
+        (self.shapes.len() - self.free.len()) * (std::mem::size_of::<Option<Shape>>() + transition)
+    }
+
+    /// Marks `shape` and its parents in `marks`, by index, adding the names
+    /// of the fields they add to `names`. Shapes already marked stop the
+    /// walk, as their parents are marked too.
+    pub(crate) fn mark(&self, mut shape: ShapeId, marks: &mut [bool], names: &mut Vec<ObjRef>) {
+        while !std::mem::replace(&mut marks[shape.0 as usize], true) {
+            let Some((parent, name, _)) = self.get(shape).parent else {
+                break;
+            };
+            names.push(name);
+            shape = parent;
+        }
+    }
+
+    /// Whether `mark` marked `shape` in `marks`, by index, which the empty
+    /// shape always is.
+    pub(crate) fn is_marked(shape: ShapeId, marks: &[bool]) -> bool {
+        shape == Shapes::EMPTY || marks[shape.0 as usize]
+    }
+
+    /// Frees the shapes that `mark` didn't mark in `live`, by index, so that
+    /// the names of their fields can be freed too.
+    pub(crate) fn sweep(&mut self, mut live: Vec<bool>) {
+        live[Shapes::EMPTY.0 as usize] = true;
+        for (index, slot) in self.shapes.iter_mut().enumerate() {
+            match slot {
+                Some(shape) if live[index] => shape.transitions.retain(|_, next| live[next.0 as usize]),
+                Some(_) => {
+                    *slot = None;
+                    self.free.push(index as u32);
+                }
+                None => (),
+            }
+        }
+    }
+
+    fn get(&self, shape: ShapeId) -> &Shape {
+        self.shapes[shape.0 as usize].as_ref().expect("Shape was freed while in use")
+    }
+
+    fn get_mut(&mut self, shape: ShapeId) -> &mut Shape {
+        self.shapes[shape.0 as usize].as_mut().expect("Shape was freed while in use")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::vm::heap::Heap;
+
+    #[test]
+    fn shares_shapes_of_fields_added_in_the_same_order() {
+        let mut heap = Heap::default();
+        let (x, y) = (heap.intern("x"), heap.intern("y"));
+        let mut shapes = Shapes::default();
+
+        let xy = shapes.with_field(Shapes::EMPTY, x);
+        let xy = shapes.with_field(xy, y);
+        let yx = shapes.with_field(Shapes::EMPTY, y);
+        let yx = shapes.with_field(yx, x);
+
+        let x_again = shapes.with_field(Shapes::EMPTY, x);
+        assert_eq!(shapes.with_field(x_again, y), xy);
+        assert_ne!(xy, yx);
+        assert_eq!((shapes.slot(xy, x), shapes.slot(xy, y)), (Some(0), Some(1)));
+        assert_eq!((shapes.slot(yx, x), shapes.slot(yx, y)), (Some(1), Some(0)));
+        assert_eq!(shapes.slot(Shapes::EMPTY, x), None);
+    }
+
+    #[test]
+    fn sweeps_shapes_that_are_not_live_nor_parents_of_live_ones() {
+        let mut heap = Heap::default();
+        let (x, y, z) = (heap.intern("x"), heap.intern("y"), heap.intern("z"));
+        let mut shapes = Shapes::default();
+        let only_x = shapes.with_field(Shapes::EMPTY, x);
+        let xy = shapes.with_field(only_x, y);
+        let only_y = shapes.with_field(Shapes::EMPTY, y);
+
+        let mut live = vec![false; shapes.len()];
+        let mut names = vec![];
+        shapes.mark(xy, &mut live, &mut names);
+        shapes.sweep(live);
+
+        assert_eq!(names, [y, x]);
+        assert_eq!(shapes.with_field(Shapes::EMPTY, x), only_x);
+        assert_eq!(shapes.with_field(only_x, y), xy);
+        assert_eq!(shapes.free, [only_y.0]);
+        // Freed shapes are reused
+        assert_eq!(shapes.with_field(xy, z), only_y);
+    }
+
+    #[test]
+    fn take_memory_in_proportion_to_the_fields() {
+        let mut heap = Heap::default();
+        let names: Vec<ObjRef> = (0..10_000).map(|i| heap.intern(&format!("f{i}"))).collect();
+        let mut shapes = Shapes::default();
+
+        let mut shape = Shapes::EMPTY;
+        for (count, &name) in names.iter().enumerate() {
+            shape = shapes.with_field(shape, name);
+            if count == 999 {
+                assert!(shapes.size() < 1000 * 256);
+            }
+        }
+
+        assert!(shapes.size() < 10_000 * 256);
+        assert_eq!(shapes.slot(shape, names[0]), Some(0));
+        assert_eq!(shapes.slot(shape, names[9_999]), Some(9_999));
+    }
+}
@@ -0,0 +1,185 @@
+//! Values have the same interface whatever their representation, which the
+//! `nan-boxing` feature selects.
+
+#[cfg(feature = "nan-boxing")]
+mod nan_boxed;
+#[cfg(not(feature = "nan-boxing"))]
+mod tagged;
+
+#[cfg(feature = "nan-boxing")]
+pub(crate) use nan_boxed::Value;
+#[cfg(not(feature = "nan-boxing"))]
+pub(crate) use tagged::Value;
+
+use super::heap::{Heap, ObjRef};
+use super::object::Object;
+use crate::interpreter::map::Key;
+
+impl Value {
+    /// `nil` and `false` are falsey, everything else is truthy.
+    pub(crate) fn is_falsey(self) -> bool {
+        self.is_nil() || self.as_bool() == Some(false)
+    }
+
+    /// Formats the value like `print` does.
+    pub(crate) fn display(self, heap: &Heap) -> Display<'_> {
+        Display { value: self, heap }
+    }
+}
+
+impl From<Key<ObjRef>> for Value {
+    fn from(key: Key<ObjRef>) -> Self {
+        match key {
+            Key::String(s) => Value::object(s),
+            Key::Number(bits) => Value::number(f64::from_bits(bits)),
+            Key::Bool(b) => Value::bool(b),
+        }
+    }
+}
+
+pub(crate) struct Display<'a> {
+    value: Value,
+    heap: &'a Heap,
+}
+
+impl std::fmt::Display for Display<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write(f, &mut vec![])
+    }
+}
+
+impl Display<'_> {
+    /// Formats the value inside the lists and maps of `enclosing`, which are
+    /// shown as `[...]` and `{...}` if they contain themselves, instead of
+    /// forever.
+    fn write(&self, f: &mut std::fmt::Formatter<'_>, enclosing: &mut Vec<ObjRef>) -> std::fmt::Result {
+        let Some(object) = self.value.as_object() else {
+            return write!(f, "{:?}", self.value);
+        };
+        match self.heap.get(object) {
+            Object::String(s) => write!(f, "{s}"),
+            Object::Function(function) => match function.name {
+                Some(name) => write!(f, "<fn {}>", self.heap.string(name)),
+                None => write!(f, "<script>"),
+            },
+            Object::Closure(closure) => write!(f, "{}", Value::object(closure.function).display(self.heap)),
+            Object::Upvalue(_) => write!(f, "upvalue"),
+            Object::Native(native) => write!(f, "{native:?}"),
+            Object::Class(class) => write!(f, "{}", self.heap.string(class.name)),
+            Object::Instance(instance) => {
+                let Object::Class(class) = self.heap.get(instance.class) else {
+                    unreachable!("Instances are of classes");
+                };
+                write!(f, "{} instance", self.heap.string(class.name))
+            }
+            Object::BoundMethod(bound) => write!(f, "{}", Value::object(bound.method).display(self.heap)),
+            Object::List(_) if enclosing.contains(&object) => write!(f, "[...]"),
+            Object::List(elements) => {
+                enclosing.push(object);
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    element.display(self.heap).write_element(f, enclosing)?;
+                }
+                enclosing.pop();
+                write!(f, "]")
+            }
+            Object::Map(_) if enclosing.contains(&object) => write!(f, "{{...}}"),
+            Object::Map(map) => {
+                enclosing.push(object);
+                write!(f, "{{")?;
+                for (i, (&key, value)) in map.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    Value::from(key).display(self.heap).write_element(f, enclosing)?;
+                    write!(f, ": ")?;
+                    value.display(self.heap).write_element(f, enclosing)?;
+                }
+                enclosing.pop();
+                write!(f, "}}")
+            }
+            Object::ListMethod(_) | Object::MapMethod(_) => write!(f, "<native fn>"),
+        }
+    }
+
+    /// Like `write`, but quotes strings, so that elements of lists and maps
+    /// that are strings can be told apart from those that aren't.
+    fn write_element(&self, f: &mut std::fmt::Formatter<'_>, enclosing: &mut Vec<ObjRef>) -> std::fmt::Result {
+        match self.value.as_object().map(|object| self.heap.get(object)) {
+            Some(Object::String(s)) => write!(f, "\"{s}\""),
+            _ => self.write(f, enclosing),
+        }
+    }
+}
+
+/// Formats values that aren't objects, which don't need the heap.
+impl std::fmt::Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_nil() {
+            write!(f, "nil")
+        } else if let Some(b) = self.as_bool() {
+            write!(f, "{b}")
+        } else if let Some(n) = self.as_number() {
+            write!(f, "{n}")
+        } else if let Some(object) = self.as_object() {
+            write!(f, "{object:?}")
+        } else {
+            unreachable!("Values are one of the above")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_every_kind_of_value() {
+        let object = Heap::default().intern("s");
+
+        assert!(Value::NIL.is_nil());
+        assert_eq!(Value::bool(true).as_bool(), Some(true));
+        assert_eq!(Value::bool(false).as_bool(), Some(false));
+        assert_eq!(Value::number(1.5).as_number(), Some(1.5));
+        assert_eq!(Value::object(object).as_object(), Some(object));
+        assert_eq!(Value::NIL.as_number(), None);
+        assert_eq!(Value::number(0.0).as_bool(), None);
+        assert_eq!(Value::bool(false).as_object(), None);
+    }
+
+    #[test]
+    fn keeps_every_number() {
+        // Integer literals are numbers too, and every `i32` fits exactly
+        let integers = [0, 1, i32::MAX, i32::MIN].map(f64::from);
+        for n in integers.into_iter().chain([-0.0, 1.5, f64::INFINITY, -f64::INFINITY, f64::MIN_POSITIVE]) {
+            assert_eq!(Value::number(n).as_number().map(f64::to_bits), Some(n.to_bits()));
+        }
+        assert!(Value::number(f64::NAN).as_number().unwrap().is_nan());
+    }
+
+    #[test]
+    fn compares_like_lox() {
+        assert_eq!(Value::number(0.0), Value::number(-0.0));
+        assert_ne!(Value::number(f64::NAN), Value::number(f64::NAN));
+        assert_ne!(Value::NIL, Value::bool(false));
+        assert_ne!(Value::number(1.0), Value::bool(true));
+        assert_eq!(Value::bool(true), Value::bool(true));
+    }
+
+    #[cfg(feature = "nan-boxing")]
+    #[test]
+    fn fits_in_a_word() {
+        assert_eq!(std::mem::size_of::<Value>(), 8);
+    }
+
+    #[test]
+    fn is_falsey_only_for_nil_and_false() {
+        assert!(Value::NIL.is_falsey());
+        assert!(Value::bool(false).is_falsey());
+        assert!(!Value::number(0.0).is_falsey());
+        assert!(!Value::bool(true).is_falsey());
+    }
+}
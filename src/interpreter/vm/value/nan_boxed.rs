@@ -0,0 +1,125 @@
+use crate::interpreter::vm::heap::ObjRef;
+
+/// Bits set in every value that isn't a number, which make it a quiet NaN
+/// that arithmetic never produces.
+const QUIET_NAN: u64 = 0x7ffc_0000_0000_0000;
+
+/// Set, together with `QUIET_NAN`, in objects, whose index is in the low bits.
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+
+const TAG_NIL: u64 = 1;
+const TAG_FALSE: u64 = 2;
+const TAG_TRUE: u64 = 3;
+
+/// A value of the VM, whose objects live in its `Heap`, in a single 64-bit
+/// word.
+///
+/// Numbers are stored as they are. Everything else is hidden in the unused
+/// bits of a NaN: `nil` and booleans as small tags, and objects as their index
+/// in the heap.
+#[derive(Clone, Copy)]
+pub(crate) struct Value(u64);
+
+impl Value {
+    pub(crate) const NIL: Value = Value(QUIET_NAN | TAG_NIL);
+
+    pub(crate) fn bool(b: bool) -> Value {
+        Value(QUIET_NAN | if b { TAG_TRUE } else { TAG_FALSE })
+    }
+
+    pub(crate) fn number(n: f64) -> Value {
+        // NaNs produced by arithmetic could look like other values
+        if n.is_nan() {
+            Value(f64::NAN.to_bits())
+        } else {
+            Value(n.to_bits())
+        }
+    }
+
+    pub(crate) fn object(object: ObjRef) -> Value {
+        Value(SIGN_BIT | QUIET_NAN | u64::from(object.index()))
+    }
+
+    pub(crate) fn is_nil(self) -> bool {
+        self.0 == Value::NIL.0
+    }
+
+    pub(crate) fn as_bool(self) -> Option<bool> {
+        match self.0 {
+            bits if bits == QUIET_NAN | TAG_TRUE => Some(true),
+            bits if bits == QUIET_NAN | TAG_FALSE => Some(false),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_number(self) -> Option<f64> {
+        (self.0 & QUIET_NAN != QUIET_NAN).then(|| f64::from_bits(self.0))
+    }
+
+    pub(crate) fn as_object(self) -> Option<ObjRef> {
+        let is_object = self.0 & (SIGN_BIT | QUIET_NAN) == SIGN_BIT | QUIET_NAN;
+        is_object.then(|| ObjRef::from_index(self.0 as u32))
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.as_number(), other.as_number()) {
+            // So that NaN isn't equal to itself, and -0 is equal to 0
+            (Some(a), Some(b)) => a == b,
+            _ => self.0 == other.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::test_support::run_vm;
+
+    /// Checks that `value` is a number, and none of the other kinds.
+    fn only_number(value: Value) -> f64 {
+        assert!(!value.is_nil());
+        assert_eq!((value.as_bool(), value.as_object()), (None, None));
+        value.as_number().expect("Numbers are numbers")
+    }
+
+    #[test]
+    fn round_trips_integer_literals() {
+        for n in [0, 1, -1, 42, i32::MAX, i32::MIN] {
+            let value = Value::number(f64::from(n));
+            assert_eq!(only_number(value).to_bits(), f64::from(n).to_bits());
+            assert_eq!(value, Value::number(f64::from(n)));
+        }
+        // Past `i32`, literals are floats, which keep integers up to 2^53
+        for n in [2_147_483_648.0, 9_007_199_254_740_992.0, 1e300] {
+            assert_eq!(only_number(Value::number(n)).to_bits(), n.to_bits());
+        }
+        let printed = run_vm("print 2147483647; print -2147483648; print 9007199254740992;");
+        assert_eq!(printed, "2147483647\n-2147483648\n9007199254740992\n");
+    }
+
+    #[test]
+    fn keeps_the_sign_of_zero() {
+        // The sign bit of -0 is also set in objects, but not with the NaN bits
+        let zero = Value::number(-0.0);
+
+        assert_eq!(only_number(zero).to_bits(), (-0.0f64).to_bits());
+        assert_eq!(zero, Value::number(0.0));
+        assert_eq!(run_vm("print -0; print 0 == -0;"), "-0\ntrue\n");
+    }
+
+    #[test]
+    fn canonicalizes_nans() {
+        // NaNs whose payload would read as another kind of value
+        let payloads = [QUIET_NAN | TAG_NIL, QUIET_NAN | TAG_TRUE, SIGN_BIT | QUIET_NAN | 7, !0];
+        let nans = payloads.map(f64::from_bits).into_iter().chain([f64::NAN, -f64::NAN, f64::INFINITY - f64::INFINITY]);
+        for nan in nans {
+            let value = Value::number(nan);
+            assert_eq!(value.0, f64::NAN.to_bits());
+            assert!(only_number(value).is_nan());
+            assert_ne!(value, value);
+        }
+        assert_eq!(run_vm("var nan = 0 / 0; print nan; print nan == nan;"), "NaN\nfalse\n");
+    }
+}
@@ -0,0 +1,55 @@
+use crate::interpreter::vm::heap::ObjRef;
+
+/// A value of the VM, whose objects live in its `Heap`, as a tagged union.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) struct Value(Repr);
+
+#[derive(Clone, Copy, PartialEq)]
+enum Repr {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    /// Strings are interned, so equal strings are the same object.
+    Object(ObjRef),
+}
+
+impl Value {
+    pub(crate) const NIL: Value = Value(Repr::Nil);
+
+    pub(crate) fn bool(b: bool) -> Value {
+        Value(Repr::Bool(b))
+    }
+
+    pub(crate) fn number(n: f64) -> Value {
+        Value(Repr::Number(n))
+    }
+
+    pub(crate) fn object(object: ObjRef) -> Value {
+        Value(Repr::Object(object))
+    }
+
+    pub(crate) fn is_nil(self) -> bool {
+        self.0 == Repr::Nil
+    }
+
+    pub(crate) fn as_bool(self) -> Option<bool> {
+        match self.0 {
+            Repr::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_number(self) -> Option<f64> {
+        match self.0 {
+            Repr::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_object(self) -> Option<ObjRef> {
+        match self.0 {
+            Repr::Object(object) => Some(object),
+            _ => None,
+        }
+    }
+}
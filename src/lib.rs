@@ -1 +1,6 @@
 mod interpreter;
+
+pub use interpreter::{
+    disassemble, Backend, Bytecode, Code, Diagnostic, Error, LoadError, Lox, Natives, Object, OptLevel, Severity,
+    Value,
+};
@@ -1,3 +1,281 @@
-fn main() {
-    println!("Hello, world!");
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::process::ExitCode;
+
+use rust_lox_interpreter::{disassemble, Backend, Bytecode, Code, Error, Lox, Natives, OptLevel};
+
+/// Deeply recursive Lox code takes a lot of native stack.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+const USAGE: &str = "\
+Usage: lox [--error-format=human|json] [--backend=tree-walker|vm] [--gc-stress] [-O0|-O1] [script]
+       lox compile [-O0|-O1] <script> [-o <output>]
+       lox disasm [-O0|-O1] <script|compiled script>
+       lox explain <code>";
+
+/// How diagnostics are written to the standard error.
+#[derive(Clone, Copy)]
+enum ErrorFormat {
+    /// Rendered with source snippets, for people.
+    Human,
+    /// One JSON object per line, for tools.
+    Json,
+}
+
+struct Options {
+    error_format: ErrorFormat,
+    backend: Backend,
+    /// Whether the VM collects garbage before every allocation.
+    gc_stress: bool,
+    opt_level: OptLevel,
+    script: Option<String>,
+    /// Where `lox compile` saves the bytecode.
+    output: Option<String>,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let thread = std::thread::Builder::new().stack_size(STACK_SIZE).spawn(move || run(&args));
+    thread.expect("Could not start interpreter thread").join().unwrap_or(ExitCode::FAILURE)
+}
+
+fn run(args: &[String]) -> ExitCode {
+    let (command, args) = match args {
+        [command, rest @ ..] if matches!(command.as_str(), "compile" | "disasm" | "explain") => {
+            (command.as_str(), rest)
+        }
+        _ => ("run", args),
+    };
+    if command == "explain" {
+        return match args {
+            [code] => explain(code),
+            _ => usage_error("Expected one error code."),
+        };
+    }
+
+    let options = match parse_options(command, args) {
+        Ok(options) => options,
+        Err(message) => return usage_error(&message),
+    };
+
+    match (command, &options.script) {
+        ("compile", Some(path)) => compile(path, &options),
+        ("disasm", Some(path)) => run_disassembler(path, options.opt_level),
+        ("compile" | "disasm", None) => usage_error("Missing script."),
+        (_, None) => run_prompt(&options),
+        (_, Some(path)) => run_file(path, &options),
+    }
+}
+
+/// `command` is the one the options are for, like `compile`, or `run`.
+fn parse_options(command: &str, args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        error_format: ErrorFormat::Human,
+        backend: Backend::TreeWalker,
+        gc_stress: false,
+        opt_level: OptLevel::default(),
+        script: None,
+        output: None,
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.split_once('=') {
+            Some(("--error-format", "human")) => options.error_format = ErrorFormat::Human,
+            Some(("--error-format", "json")) => options.error_format = ErrorFormat::Json,
+            Some(("--error-format", format)) => return Err(format!("Unknown error format '{format}'.")),
+            Some(("--backend", "tree-walker")) => options.backend = Backend::TreeWalker,
+            Some(("--backend", "vm")) => options.backend = Backend::Vm,
+            Some(("--backend", backend)) => return Err(format!("Unknown backend '{backend}'.")),
+            None if arg == "--gc-stress" => options.gc_stress = true,
+            None if arg == "-O0" => options.opt_level = OptLevel::O0,
+            None if arg == "-O1" => options.opt_level = OptLevel::O1,
+            None if arg == "-o" && command == "compile" => match args.next() {
+                Some(output) => options.output = Some(output.clone()),
+                None => return Err("Missing output after '-o'.".to_string()),
+            },
+            _ if arg.starts_with('-') => return Err(format!("Unknown option '{arg}'.")),
+            _ if options.script.is_none() => options.script = Some(arg.clone()),
+            _ => return Err("Only one script can be run.".to_string()),
+        }
+    }
+
+    Ok(options)
+}
+
+fn usage_error(message: &str) -> ExitCode {
+    eprintln!("{message}\n{USAGE}");
+    ExitCode::from(64)
+}
+
+/// Prints the explanation of an error code, like `L0001`.
+fn explain(code: &str) -> ExitCode {
+    match Code::parse(code) {
+        Some(code) => {
+            println!("{}", code.explanation());
+            ExitCode::SUCCESS
+        }
+        None => {
+            eprintln!("Unknown error code '{code}'.");
+            ExitCode::from(64)
+        }
+    }
+}
+
+/// Prints the bytecode that the script at `path` compiles to, or that was
+/// saved there by `lox compile`.
+fn run_disassembler(path: &str, level: OptLevel) -> ExitCode {
+    if is_compiled(path) {
+        return match load(path) {
+            Ok(bytecode) => {
+                print!("{}", bytecode.disassemble());
+                ExitCode::SUCCESS
+            }
+            Err(code) => code,
+        };
+    }
+    let Some(source) = read(path) else {
+        return ExitCode::from(74);
+    };
+
+    match disassemble(&source, level) {
+        Ok(listing) => {
+            print!("{listing}");
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            report(&error, path, &source, ErrorFormat::Human);
+            ExitCode::from(65)
+        }
+    }
+}
+
+/// Saves the bytecode that the script at `path` compiles to, next to it
+/// unless another output is given.
+fn compile(path: &str, options: &Options) -> ExitCode {
+    let Some(source) = read(path) else {
+        return ExitCode::from(74);
+    };
+    let output = match &options.output {
+        Some(output) => Path::new(output).to_path_buf(),
+        None => Path::new(path).with_extension("loxc"),
+    };
+
+    let bytecode = match Bytecode::compile(&source, options.opt_level) {
+        Ok(bytecode) => bytecode,
+        Err(error) => {
+            report(&error, path, &source, ErrorFormat::Human);
+            return ExitCode::from(65);
+        }
+    };
+    match std::fs::write(&output, bytecode.to_bytes()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("Could not write {}: {error}", output.display());
+            ExitCode::from(73)
+        }
+    }
+}
+
+fn run_file(path: &str, options: &Options) -> ExitCode {
+    if is_compiled(path) {
+        return run_bytecode(path, options);
+    }
+    let Some(source) = read(path) else {
+        return ExitCode::from(74);
+    };
+
+    let result = lox(options, options.backend).run(&source);
+    exit_code(result, path, &source, options.error_format)
+}
+
+/// Whether `path` names a script saved by `lox compile`.
+fn is_compiled(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|extension| extension == "loxc")
+}
+
+/// Runs a script saved by `lox compile`, on the VM whatever the backend.
+fn run_bytecode(path: &str, options: &Options) -> ExitCode {
+    let bytecode = match load(path) {
+        Ok(bytecode) => bytecode,
+        Err(code) => return code,
+    };
+
+    let result = lox(options, Backend::Vm).run_bytecode(&bytecode);
+    exit_code(result, path, bytecode.source(), options.error_format)
+}
+
+/// Reports why the script saved at `path` couldn't be loaded, if it couldn't,
+/// returning the exit code for it.
+fn load(path: &str) -> Result<Bytecode, ExitCode> {
+    let bytes = std::fs::read(path).map_err(|error| {
+        eprintln!("Could not read {path}: {error}");
+        ExitCode::from(74)
+    })?;
+    Bytecode::from_bytes(&bytes).map_err(|error| {
+        eprintln!("Could not load {path}: {error}.");
+        ExitCode::from(65)
+    })
+}
+
+/// Reports the error of running `source`, if any.
+fn exit_code(result: Result<(), Error>, path: &str, source: &str, format: ErrorFormat) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            report(&error, path, source, format);
+            match error {
+                Error::Compile(_) => ExitCode::from(65),
+                Error::Runtime(_) => ExitCode::from(70),
+            }
+        }
+    }
+}
+
+/// Reports why the file couldn't be read, if it couldn't.
+fn read(path: &str) -> Option<String> {
+    std::fs::read_to_string(path)
+        .inspect_err(|error| eprintln!("Could not read {path}: {error}"))
+        .ok()
+}
+
+/// Runs each line as it is typed, keeping variables between lines.
+fn run_prompt(options: &Options) -> ExitCode {
+    let mut lox = lox(options, options.backend);
+    let mut lines = std::io::stdin().lock().lines();
+    // Functions defined by earlier lines point into them
+    let mut source = String::new();
+    loop {
+        print!("> ");
+        let _ = std::io::stdout().flush();
+        let Some(Ok(line)) = lines.next() else {
+            println!();
+            return ExitCode::SUCCESS;
+        };
+        if !source.is_empty() {
+            source.push('\n');
+        }
+        let start = source.len();
+        source.push_str(&line);
+        if let Err(error) = lox.run_from(&source, start) {
+            report(&error, "<prompt>", &source, options.error_format);
+        }
+    }
+}
+
+/// Makes an interpreter that prints to the standard output.
+fn lox(options: &Options, backend: Backend) -> Lox {
+    let mut lox = Lox::with_backend(Box::new(std::io::stdout()), &Natives::new(), backend);
+    lox.set_gc_stress(options.gc_stress);
+    lox.set_opt_level(options.opt_level);
+    lox
+}
+
+/// `file_name` is where `source` came from.
+fn report(error: &Error, file_name: &str, source: &str, format: ErrorFormat) {
+    for diagnostic in error.diagnostics() {
+        match format {
+            ErrorFormat::Human => eprintln!("{}", diagnostic.render(file_name, source)),
+            ErrorFormat::Json => eprintln!("{}", diagnostic.to_json(file_name, source)),
+        }
+    }
 }
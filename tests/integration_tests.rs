@@ -0,0 +1,329 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use rust_lox_interpreter::{Backend, Error, Lox, Natives, Value};
+
+/// Output shared with a `Lox` so tests can read what was printed.
+#[derive(Clone, Default)]
+struct Output(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Output {
+    fn printed(&self) -> String {
+        String::from_utf8(self.0.take()).unwrap()
+    }
+}
+
+mod natives {
+    use super::*;
+
+    #[test]
+    fn calls_host_functions() {
+        let mut natives = Natives::new();
+        natives.define("hypot", 2, |arguments| match arguments {
+            [Value::Number(a), Value::Number(b)] => Ok(Value::Number(a.hypot(*b))),
+            _ => Err("hypot() takes two numbers.".to_string()),
+        });
+        let output = Output::default();
+
+        let result = Lox::with_natives(Box::new(output.clone()), &natives).run("print hypot(3, 4);");
+
+        assert!(result.is_ok());
+        assert_eq!(output.printed(), "5\n");
+    }
+
+    #[test]
+    fn reports_errors_of_host_functions() {
+        let mut natives = Natives::empty();
+        natives.define("fail", 1, |arguments| Err(format!("Failed with {}.", arguments[0])));
+
+        let code = "\nfail(\"style\");";
+
+        let result = Lox::with_natives(Box::new(Output::default()), &natives).run(code);
+
+        let Err(Error::Runtime(diagnostic)) = result else {
+            panic!("Expected a runtime error, got {result:?}");
+        };
+        assert_eq!(diagnostic.message(), "Failed with style.");
+        assert!(diagnostic.render("script.lox", code).contains("--> script.lox:2:13"));
+    }
+
+    #[test]
+    fn passes_objects_through_host_functions() {
+        let mut natives = Natives::new();
+        natives.define("identity", 1, |arguments| Ok(arguments[0].clone()));
+        natives.define("describe", 1, |arguments| Ok(Value::String(arguments[0].to_string().into())));
+        let output = Output::default();
+        let code = "
+            class Bagel {}
+            var bagel = Bagel();
+            print identity(bagel) == bagel;
+            print describe(bagel);
+        ";
+
+        let result = Lox::with_natives(Box::new(output.clone()), &natives).run(code);
+
+        assert!(result.is_ok());
+        assert_eq!(output.printed(), "true\nBagel instance\n");
+    }
+
+    #[test]
+    fn keeps_state_of_host_closures() {
+        let calls = Rc::new(RefCell::new(0));
+        let mut natives = Natives::empty();
+        let counter = Rc::clone(&calls);
+        natives.define("tick", 0, move |_| {
+            *counter.borrow_mut() += 1;
+            Ok(Value::Number(*counter.borrow() as f64))
+        });
+        let output = Output::default();
+
+        let result = Lox::with_natives(Box::new(output.clone()), &natives).run("tick(); print tick();");
+
+        assert!(result.is_ok());
+        assert_eq!(output.printed(), "2\n");
+        assert_eq!(*calls.borrow(), 2);
+    }
+
+    #[test]
+    fn rejects_objects_kept_from_earlier_calls() {
+        let kept = Rc::new(RefCell::new(Value::Nil));
+        let mut natives = Natives::empty();
+        let slot = Rc::clone(&kept);
+        natives.define("keep", 1, move |arguments| Ok(slot.replace(arguments[0].clone())));
+        let code = "class A {}\nkeep(A());\nprint keep(nil);";
+
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let mut lox = Lox::with_backend(Box::new(Output::default()), &natives, backend);
+            let result = lox.run(code);
+
+            // Instances of the tree-walker outlive calls, unlike those of the VM
+            match backend {
+                Backend::TreeWalker => assert!(result.is_ok()),
+                Backend::Vm => {
+                    let Err(Error::Runtime(diagnostic)) = result else {
+                        panic!("Expected a runtime error, got {result:?}");
+                    };
+                    assert_eq!(diagnostic.message(), "Native function returned an object it was not given.");
+                    assert!(diagnostic.render("script.lox", code).contains("--> script.lox:3:15"));
+                }
+            }
+        }
+
+        // Nor can objects of one interpreter be returned by another
+        let mut vm = Lox::with_backend(Box::new(Output::default()), &natives, Backend::Vm);
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            vm.run("class A {} keep(A());").unwrap();
+            let result = Lox::with_backend(Box::new(Output::default()), &natives, backend).run("keep(nil);");
+            let Err(Error::Runtime(diagnostic)) = result else {
+                panic!("Expected a runtime error on {backend:?}, got {result:?}");
+            };
+            assert_eq!(diagnostic.message(), "Native function returned an object it was not given.");
+        }
+    }
+}
+
+mod backends {
+    use super::*;
+
+    fn run(source: &str, backend: Backend) -> (String, Option<String>) {
+        let mut natives = Natives::new();
+        natives.define("describe", 1, |arguments| Ok(Value::String(arguments[0].to_string().into())));
+        let output = Output::default();
+        let result = Lox::with_backend(Box::new(output.clone()), &natives, backend).run(source);
+        let error = result.err().map(|error| error.diagnostics()[0].render("script.lox", source));
+        (output.printed(), error)
+    }
+
+    #[test]
+    fn print_the_same() {
+        let source = "
+            class Point {
+                init(x, y) { this.x = x; this.y = y; }
+                sum() { return this.x + this.y; }
+            }
+            var point = Point(1, 2);
+            print point.sum();
+            print describe(point);
+            print describe(point.sum);
+            print point.z;
+        ";
+
+        let (printed, error) = run(source, Backend::Vm);
+
+        assert_eq!(printed, "3\nPoint instance\n<fn sum>\n");
+        assert_eq!((printed, error), run(source, Backend::TreeWalker));
+    }
+
+    #[test]
+    fn trace_functions_called_by_list_methods_the_same() {
+        let source = "
+            fun check(x) { return [x].map(describe).filter((s) => -s); }
+            print [1, 2].map(check);
+        ";
+
+        let (printed, error) = run(source, Backend::Vm);
+
+        assert!(error.as_deref().is_some_and(|error| error.contains("   1: filter() at script.lox:2:69\n")));
+        assert_eq!((printed, error), run(source, Backend::TreeWalker));
+    }
+}
+
+mod bytecode {
+    use rust_lox_interpreter::{disassemble, Bytecode, LoadError, OptLevel};
+
+    use super::*;
+
+    #[test]
+    fn runs_saved_bytecode_like_the_source() {
+        let source = "
+            fun greet(name) { return \"Hello, \" + name + \"!\"; }
+            print greet(\"bytecode\");
+            print nil + 1;
+        ";
+        let bytes = Bytecode::compile(source, OptLevel::O1).unwrap().to_bytes();
+        let bytecode = Bytecode::from_bytes(&bytes).unwrap();
+
+        for backend in [Backend::Vm, Backend::TreeWalker] {
+            let output = Output::default();
+            let result = Lox::with_backend(Box::new(output.clone()), &Natives::new(), backend).run_bytecode(&bytecode);
+
+            let Err(Error::Runtime(diagnostic)) = result else {
+                panic!("Expected a runtime error, got {result:?}");
+            };
+            assert_eq!(output.printed(), "Hello, bytecode!\n");
+            assert!(diagnostic.render("script.lox", bytecode.source()).contains("--> script.lox:4:23"));
+        }
+    }
+
+    #[test]
+    fn lists_saved_bytecode_like_the_source() {
+        let source = "fun add(a, b) { return a + b; }\nprint add(1, 2);";
+        let bytes = Bytecode::compile(source, OptLevel::O1).unwrap().to_bytes();
+
+        let listing = Bytecode::from_bytes(&bytes).unwrap().disassemble();
+
+        assert!(listing.contains("== <fn add> =="));
+        assert_eq!(listing, disassemble(source, OptLevel::O1).unwrap());
+    }
+
+    #[test]
+    fn rejects_scripts_that_are_not_compiled() {
+        let result = Bytecode::from_bytes(b"print 1;");
+
+        assert!(matches!(result, Err(LoadError::NotCompiled)));
+    }
+}
+
+mod error_codes {
+    use rust_lox_interpreter::Code;
+
+    use super::*;
+
+    fn codes(source: &str) -> Vec<String> {
+        let Err(error) = Lox::with_output(Box::new(Output::default())).run(source) else {
+            panic!("Code ran without errors");
+        };
+        error.diagnostics().iter().map(|diagnostic| diagnostic.code().unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn gives_codes_to_errors_of_every_phase() {
+        assert_eq!(codes("\"open"), ["L0001"]);
+        assert_eq!(codes("print 1 +;"), ["L0101"]);
+        assert_eq!(codes("return;"), ["L0203"]);
+        assert_eq!(codes("print -nil;"), ["L0304"]);
+    }
+
+    #[test]
+    fn explains_codes_of_diagnostics() {
+        let code = Code::parse(&codes("print missing;")[0]).unwrap();
+
+        assert!(code.explanation().starts_with("A variable was used"));
+    }
+}
+
+mod stack_traces {
+    use super::*;
+
+    fn rendered(source: &str) -> String {
+        let Err(error) = Lox::with_output(Box::new(Output::default())).run(source) else {
+            panic!("Code ran without errors");
+        };
+        error.diagnostics()[0].render("script.lox", source)
+    }
+
+    #[test]
+    fn lists_calls_innermost_first() {
+        let source = "\
+fun inner() {
+  return -nil;
+}
+class Outer {
+  call() { inner(); }
+}
+Outer().call();
+";
+
+        assert!(rendered(source).ends_with(
+            "\
+stack backtrace:
+   0: inner() at script.lox:2:10
+   1: call() at script.lox:5:18
+   2: script at script.lox:7:14
+",
+        ));
+    }
+
+    #[test]
+    fn collapses_recursion() {
+        let source = "fun f(n) { if (n > 0) f(n - 1); else nil(); }\nf(3);";
+
+        assert!(rendered(source).ends_with(
+            "\
+stack backtrace:
+   0: f() at script.lox:1:42
+   1: f() at script.lox:1:30
+      [previous frame repeated 2 more times]
+   4: script at script.lox:2:4
+",
+        ));
+    }
+
+    #[test]
+    fn omits_traces_of_top_level_errors() {
+        assert!(!rendered("print -nil;").contains("stack backtrace"));
+    }
+}
+
+mod prompt {
+    use super::*;
+
+    #[test]
+    fn points_into_lines_run_before() {
+        for backend in [Backend::TreeWalker, Backend::Vm] {
+            let mut lox = Lox::with_backend(Box::new(Output::default()), &Natives::new(), backend);
+            let mut source = "fun f() { return -nil; }".to_string();
+            lox.run(&source).unwrap();
+            let start = source.len() + 1;
+            source.push_str("\nf();");
+
+            let Err(Error::Runtime(diagnostic)) = lox.run_from(&source, start) else {
+                panic!("Expected a runtime error on {backend:?}");
+            };
+            let rendered = diagnostic.render("<prompt>", &source);
+            assert!(rendered.contains("--> <prompt>:1:18"), "{rendered}");
+            assert!(rendered.contains("script at <prompt>:2:3"), "{rendered}");
+        }
+    }
+}